@@ -0,0 +1,329 @@
+//! Benchmarks of the aggregation pipeline over seeded in-memory datasets.
+//!
+//! The 100M row datasets take several GB of memory, they only run with `BRC_BENCH_LARGE=1`.
+//! Building with `--features rayon` also benchmarks `aggregate` on the rayon backend.
+
+use std::{hint::black_box, sync::Arc};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+#[cfg(feature = "rayon")]
+use rs::backend::Backend;
+use rs::{
+    aggregate_unchecked, avx2_available, handle_entry, parse_value, parse_value_swar,
+    process_chunk,
+    seed::{StationList, Unknown},
+    table::{FlatTable, StationTable},
+    AggregateError, AggregateOptions, BumpAlloc, Map, Stations,
+};
+
+const SEED: u64 = 0x1b7c_0000_2024;
+
+/// `aggregate` with the fast parser, which is what the benchmarks measure
+fn aggregate(data: &[u8], options: &AggregateOptions) -> Result<Stations, AggregateError> {
+    // SAFETY: every shape generates well-formed records
+    unsafe { aggregate_unchecked(data, options) }
+}
+
+/// splitmix64, so the datasets are identical on every machine
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Names {
+    /// 3..=16 bytes, all stored inline in the map
+    Short,
+    /// 90 bytes, all stored in the arena
+    Long,
+    /// 1, 16 and 100 bytes in turn, at least as long as the index prefix
+    Mixed,
+}
+
+#[derive(Clone, Copy)]
+struct Shape {
+    rows: usize,
+    stations: usize,
+    names: Names,
+}
+
+impl Shape {
+    fn name(&self) -> String {
+        let names = match self.names {
+            Names::Short => "short",
+            Names::Long => "long",
+            Names::Mixed => "mixed",
+        };
+        format!("{}-stations/{names}", self.stations)
+    }
+
+    /// Renders the dataset in the challenge format
+    fn generate(&self) -> Vec<u8> {
+        let mut rng = Rng(SEED ^ self.stations as u64 ^ (self.names as u64) << 32);
+        let names: Vec<Vec<u8>> = (0..self.stations)
+            .map(|i| {
+                let len = match self.names {
+                    Names::Short => 3 + rng.below(14) as usize,
+                    Names::Long => 90,
+                    Names::Mixed => [1, 16, 100][i % 3],
+                };
+                // Prefix with the index so all names are distinct
+                let mut name = format!("{i:x}").into_bytes();
+                name.resize(len.max(name.len()), 0);
+                for byte in &mut name[..] {
+                    if *byte == 0 {
+                        *byte = b'a' + rng.below(26) as u8;
+                    }
+                }
+                name
+            })
+            .collect();
+        let avg_len = names.iter().map(Vec::len).sum::<usize>() / names.len() + 7;
+        let mut data = Vec::with_capacity(self.rows * avg_len);
+        for _ in 0..self.rows {
+            data.extend_from_slice(&names[rng.below(names.len() as u64) as usize]);
+            data.push(b';');
+            push_value(&mut data, rng.below(1999) as i64 - 999);
+        }
+        data
+    }
+}
+
+fn push_value(data: &mut Vec<u8>, tenths: i64) {
+    if tenths < 0 {
+        data.push(b'-');
+    }
+    let tenths = tenths.unsigned_abs();
+    data.extend_from_slice((tenths / 10).to_string().as_bytes());
+    data.push(b'.');
+    data.push(b'0' + (tenths % 10) as u8);
+    data.push(b'\n');
+}
+
+fn shapes(rows: usize) -> impl Iterator<Item = Shape> {
+    [
+        (413, Names::Short),
+        (413, Names::Long),
+        (413, Names::Mixed),
+        (10_000, Names::Short),
+        (10_000, Names::Long),
+    ]
+    .into_iter()
+    .map(move |(stations, names)| Shape {
+        rows,
+        stations,
+        names,
+    })
+}
+
+fn row_counts() -> Vec<usize> {
+    let mut rows = vec![1_000_000, 10_000_000];
+    if std::env::var_os("BRC_BENCH_LARGE").is_some() {
+        rows.push(100_000_000);
+    }
+    rows
+}
+
+/// Splits a dataset into (station, before dot, after dot) triples
+fn split_records(data: &[u8]) -> Vec<(&[u8], &[u8], u8)> {
+    data.split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let semicolon = line.iter().position(|&b| b == b';').unwrap();
+            let value = &line[semicolon + 1..];
+            let (before_dot, after_dot) = value.split_at(value.len() - 2);
+            (&line[..semicolon], before_dot, after_dot[1])
+        })
+        .collect()
+}
+
+fn bench_parse_value(c: &mut Criterion) {
+    let mut rng = Rng(SEED);
+    let mut data = Vec::new();
+    for _ in 0..1_000_000 {
+        push_value(&mut data, rng.below(1999) as i64 - 999);
+    }
+    // The words the SWAR decoder reads, spilling into the next value like in the hot loop
+    data.extend_from_slice(&[b'\n'; 8]);
+    let mut words = Vec::new();
+    let mut start = 0;
+    while start + 8 < data.len() {
+        words.push(u64::from_le_bytes(
+            data[start..start + 8].try_into().unwrap(),
+        ));
+        start += data[start..].iter().position(|&b| b == b'\n').unwrap() + 1;
+    }
+    let values: Vec<(&[u8], u8)> = data
+        .split(|&b| b == b'\n')
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            let (before_dot, after_dot) = value.split_at(value.len() - 2);
+            (before_dot, after_dot[1])
+        })
+        .collect();
+    let bytes: usize = values.iter().map(|(before, _)| before.len() + 2).sum();
+
+    let mut group = c.benchmark_group("parse_value");
+    group.throughput(Throughput::Bytes(bytes as u64));
+    group.bench_function("1M", |b| {
+        b.iter(|| {
+            values.iter().fold(0i64, |acc, &(before_dot, after_dot)| {
+                // SAFETY: the generated values are `[-]d{1,2}.d`
+                acc + unsafe { parse_value(black_box(before_dot), after_dot) } as i64
+            })
+        })
+    });
+    group.bench_function("1M-swar", |b| {
+        b.iter(|| {
+            words.iter().fold(0i64, |acc, &word| {
+                acc + parse_value_swar::<b'.'>(black_box(word)).unwrap().0 as i64
+            })
+        })
+    });
+    group.finish();
+}
+
+fn bench_handle_entry(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handle_entry/1M-rows");
+    for shape in shapes(1_000_000) {
+        let data = shape.generate();
+        let records = split_records(&data);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        // The map is kept across iterations so this measures the steady state where every
+        // station is already known, which is what the hot loop sees for almost all rows
+        let bump = BumpAlloc::new();
+        let mut map = Map::with_capacity(1024 * 8);
+        group.bench_function(BenchmarkId::new("hashbrown", shape.name()), |b| {
+            b.iter(|| {
+                for &(station, before_dot, after_dot) in &records {
+                    // SAFETY: the generated values are `[-]d{1,2}.d`
+                    let value = unsafe { parse_value(before_dot, after_dot) };
+                    handle_entry(&mut map, &bump, station, value, 0);
+                }
+            })
+        });
+        let mut table = FlatTable::with_capacity(1024 * 8);
+        group.bench_function(BenchmarkId::new("flat", shape.name()), |b| {
+            b.iter(|| {
+                for &(station, before_dot, after_dot) in &records {
+                    // SAFETY: the generated values are `[-]d{1,2}.d`
+                    let value = unsafe { parse_value(before_dot, after_dot) };
+                    table.upsert(&bump, station, value, 0);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_pipeline(c: &mut Criterion) {
+    let avx2 = avx2_available();
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    for rows in row_counts() {
+        let mut group = c.benchmark_group(format!("pipeline/{}M-rows", rows / 1_000_000));
+        if rows > 1_000_000 {
+            group.sample_size(10);
+        }
+        for shape in shapes(rows) {
+            let data = shape.generate();
+            group.throughput(Throughput::Bytes(data.len() as u64));
+
+            let bump = BumpAlloc::new();
+            let mut map = Map::with_capacity(1024 * 8);
+            group.bench_function(BenchmarkId::new("process_chunk", shape.name()), |b| {
+                // SAFETY: `avx2` was detected and the generated records are well-formed
+                b.iter(|| unsafe {
+                    process_chunk::<b';', b'.'>(
+                        &data,
+                        0,
+                        data.len(),
+                        &mut map,
+                        &bump,
+                        avx2,
+                        None,
+                        None,
+                        None,
+                        0,
+                    )
+                    .unwrap()
+                })
+            });
+            // work() on every core plus the merge of their maps
+            group.bench_function(BenchmarkId::new("aggregate", shape.name()), |b| {
+                b.iter(|| {
+                    aggregate(
+                        &data,
+                        &AggregateOptions {
+                            threads,
+                            ..Default::default()
+                        },
+                    )
+                    .unwrap()
+                })
+            });
+            // The same with every station of the data pre-seeded and any other one rejected, so
+            // the hot loop never takes the insertion path
+            let mut list = Vec::new();
+            for line in data.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+                let station = line.split(|&b| b == b';').next().unwrap();
+                list.extend_from_slice(station);
+                list.push(b'\n');
+            }
+            let stations = Arc::new(StationList::parse(&list, Unknown::Error));
+            group.bench_function(BenchmarkId::new("aggregate-seeded", shape.name()), |b| {
+                b.iter(|| {
+                    aggregate(
+                        &data,
+                        &AggregateOptions {
+                            threads,
+                            stations: Some(Arc::clone(&stations)),
+                            ..Default::default()
+                        },
+                    )
+                    .unwrap()
+                })
+            });
+            // The same on a rayon pool of as many threads
+            #[cfg(feature = "rayon")]
+            {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap();
+                group.bench_function(BenchmarkId::new("aggregate-rayon", shape.name()), |b| {
+                    b.iter(|| {
+                        pool.install(|| {
+                            aggregate(
+                                &data,
+                                &AggregateOptions {
+                                    backend: Backend::Rayon,
+                                    ..Default::default()
+                                },
+                            )
+                            .unwrap()
+                        })
+                    })
+                });
+            }
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(
+    benches,
+    bench_parse_value,
+    bench_handle_entry,
+    bench_pipeline
+);
+criterion_main!(benches);
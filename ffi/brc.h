@@ -0,0 +1,60 @@
+/* C API of the aggregator, implemented in src/ffi.rs.
+ *
+ * Build the library with `cargo build --release`, which produces librs.so and librs.a in
+ * target/release. Every function returns one of the BRC_* codes, brc_last_error_message()
+ * describes the last error on the calling thread. */
+
+#ifndef BRC_H
+#define BRC_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define BRC_OK 0
+/* A required pointer argument was null */
+#define BRC_NULL_ARGUMENT 1
+/* The file couldn't be opened or mapped */
+#define BRC_IO 2
+/* The input couldn't be aggregated */
+#define BRC_AGGREGATE 3
+/* The station index is out of bounds */
+#define BRC_OUT_OF_BOUNDS 4
+/* A panic was caught, this is a bug */
+#define BRC_PANIC 5
+
+/* The aggregates of every station, sorted by name */
+typedef struct BrcResult brc_result;
+
+/* Aggregates the file at path and stores the result in *out. The input doesn't need to be
+ * well-formed, lines that aren't a station, a `;` and a temperature like `-12.3` or `4` are
+ * skipped. */
+int brc_aggregate_file(const char *path, brc_result **out);
+
+/* Aggregates the len bytes at data and stores the result in *out, skipping malformed lines as
+ * brc_aggregate_file() does */
+int brc_aggregate_buffer(const uint8_t *data, size_t len, brc_result **out);
+
+/* Number of stations in result, 0 if it is null */
+size_t brc_result_len(const brc_result *result);
+
+/* Reads station i of result. The name isn't NUL-terminated and stays valid until the result is
+ * freed. Temperatures are in tenths of a degree, sum is the sum of all readings. */
+int brc_result_get(const brc_result *result, size_t i, const uint8_t **name, size_t *name_len,
+                   uint64_t *count, int64_t *sum, int16_t *min, int16_t *max);
+
+/* Frees result and the station names it owns, does nothing if it is null */
+void brc_result_free(brc_result *result);
+
+/* Description of the last error on the calling thread, NULL if there was none. Valid until the
+ * next call on this thread. */
+const char *brc_last_error_message(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,64 @@
+/* Links against the library and exercises every function of brc.h, run it under valgrind to
+ * check for leaks and invalid accesses:
+ *
+ *     cargo build --release
+ *     cc -o target/check ffi/check.c -Iffi -Ltarget/release -lrs
+ *     LD_LIBRARY_PATH=target/release valgrind --leak-check=full --error-exitcode=1 target/check
+ *
+ * tests/ffi.rs does the same against the library of the test build.
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "brc.h"
+
+#define CHECK(cond)                                                                            \
+    do {                                                                                       \
+        if (!(cond)) {                                                                         \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond);           \
+            return 1;                                                                          \
+        }                                                                                      \
+    } while (0)
+
+int main(void) {
+    static const char input[] = "Oslo;-3.5\nCairo;30.1\nOslo;2.5\n";
+    brc_result *result = NULL;
+    CHECK(brc_aggregate_buffer((const uint8_t *)input, strlen(input), &result) == BRC_OK);
+    CHECK(brc_result_len(result) == 2);
+
+    const uint8_t *name;
+    size_t name_len;
+    uint64_t count;
+    int64_t sum;
+    int16_t min, max;
+    CHECK(brc_result_get(result, 0, &name, &name_len, &count, &sum, &min, &max) == BRC_OK);
+    CHECK(name_len == 5 && memcmp(name, "Cairo", 5) == 0);
+    CHECK(count == 1 && sum == 301 && min == 301 && max == 301);
+    CHECK(brc_result_get(result, 1, &name, &name_len, &count, &sum, &min, &max) == BRC_OK);
+    CHECK(name_len == 4 && memcmp(name, "Oslo", 4) == 0);
+    CHECK(count == 2 && sum == -10 && min == -35 && max == 25);
+
+    CHECK(brc_result_get(result, 2, &name, &name_len, &count, &sum, &min, &max) ==
+          BRC_OUT_OF_BOUNDS);
+    CHECK(brc_last_error_message() != NULL);
+    CHECK(brc_result_get(result, 0, NULL, &name_len, &count, &sum, &min, &max) ==
+          BRC_NULL_ARGUMENT);
+    brc_result_free(result);
+
+    static const char malformed[] = "no delimiter here\nOslo;\nOslo;1.0\nCairo;abc";
+    CHECK(brc_aggregate_buffer((const uint8_t *)malformed, strlen(malformed), &result) == BRC_OK);
+    CHECK(brc_result_len(result) == 1);
+    brc_result_free(result);
+
+    CHECK(brc_aggregate_buffer(NULL, 0, &result) == BRC_OK);
+    CHECK(brc_result_len(result) == 0);
+    brc_result_free(result);
+    brc_result_free(NULL);
+
+    CHECK(brc_aggregate_file("/nonexistent/measurements.txt", &result) == BRC_IO);
+    CHECK(strstr(brc_last_error_message(), "/nonexistent/measurements.txt") != NULL);
+
+    puts("ok");
+    return 0;
+}
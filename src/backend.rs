@@ -0,0 +1,171 @@
+//! How `aggregate` runs its workers, picked with `--backend`.
+//!
+//! The native backend spawns its own scoped threads that claim chunks from a shared queue. The
+//! rayon backend, only available with the `rayon` feature, runs inside the caller's current
+//! rayon pool instead, so a caller that already uses one doesn't oversubscribe the machine.
+
+/// Where the workers of `aggregate` run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// `options.threads` scoped threads, the calling thread being one of them
+    #[default]
+    Native,
+    /// Tasks on the current rayon pool, `options.threads` is ignored
+    #[cfg(feature = "rayon")]
+    Rayon,
+}
+
+#[cfg(feature = "rayon")]
+pub(crate) use rayon_backend::aggregate_rayon;
+
+#[cfg(feature = "rayon")]
+mod rayon_backend {
+    use rayon::prelude::*;
+
+    use crate::{
+        auto_chunk_size, merge_maps, work_for, AggregateError, AggregateOptions, Stations,
+        WorkQueue,
+    };
+
+    /// Ranges handed out per thread of the pool, so a task that is slower than the others doesn't
+    /// hold up the whole pass
+    const RANGES_PER_THREAD: usize = 4;
+
+    /// Splits `data` into ranges of whole chunks and aggregates them as tasks on the current pool,
+    /// with the maps of finished tasks merged in the pool as well.
+    ///
+    /// Chunks start at the same offsets as with the native backend, so sampled and shadow checked
+    /// chunks are the same and so are the results. `numa`, `prefault` and `schedule` are ignored,
+    /// the pool decides which thread runs which range.
+    pub(crate) fn aggregate_rayon(
+        data: &[u8],
+        options: &AggregateOptions,
+        trusted: bool,
+    ) -> Result<Stations, AggregateError> {
+        let threads = rayon::current_num_threads();
+        let chunk_size = options
+            .chunk_size
+            .unwrap_or_else(|| auto_chunk_size(data.len(), threads));
+        let work = work_for(options.delimiter, options.decimal_comma, options.table);
+        // The chunks don't depend on who claims them, claiming them all up front finds their
+        // starts
+        let whole = WorkQueue::new(data.len(), chunk_size).tapering(threads);
+        let starts: Vec<usize> = std::iter::from_fn(|| whole.claim(0))
+            .map(|chunk| chunk.start)
+            .collect();
+        let per_range = starts.len().div_ceil(threads * RANGES_PER_THREAD).max(1);
+        let merged = (0..starts.len().div_ceil(per_range))
+            .into_par_iter()
+            .map(|index| {
+                let start = starts[index * per_range];
+                let end = starts.get((index + 1) * per_range).copied();
+                let queue =
+                    WorkQueue::range(data.len(), start, end.unwrap_or(data.len()), chunk_size)
+                        .tapering(threads);
+                let (map, stats) = work(data, &queue, index, options, trusted, None)?;
+                if let (Some(sink), Some(stats)) = (&options.stats, stats) {
+                    sink.lock().unwrap().push(stats);
+                }
+                Ok(map)
+            })
+            .reduce(
+                || Ok::<_, AggregateError>(Stations::default()),
+                |left, right| match (left, right) {
+                    (Ok(left), Ok(right)) => {
+                        // Merge the smaller map into the larger one
+                        let (mut into, from) = if left.len() > right.len() {
+                            (left, right)
+                        } else {
+                            (right, left)
+                        };
+                        merge_maps(&mut into, from);
+                        Ok(into)
+                    }
+                    // Report the earliest error if several ranges hit one
+                    (Err(left), Err(right)) if right.offset() < left.offset() => Err(right),
+                    (Err(err), _) | (_, Err(err)) => Err(err),
+                },
+            )?;
+        options
+            .limits
+            .check_stations(merged.len(), None)
+            .map_err(AggregateError::Limit)?;
+        Ok(merged)
+    }
+}
+
+#[cfg(all(test, feature = "rayon"))]
+mod tests {
+    use super::*;
+    use crate::{
+        aggregate, aggregate_unchecked,
+        sample::ChunkSample,
+        shadow::ShadowCheck,
+        tests::{generate, summarize, Rng},
+        AggregateOptions,
+    };
+
+    #[test]
+    fn rayon_matches_native() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(3)
+            .build()
+            .unwrap();
+        let mut rng = Rng(0x304);
+        for case in 0..30 {
+            let stations = 1 + rng.below(100);
+            let records = rng.below(2000) as usize;
+            let (input, expected) = generate(&mut rng, stations, records, case % 2 == 1);
+            let options = AggregateOptions {
+                threads: 3,
+                chunk_size: Some(1 + rng.below(256) as usize),
+                // Both pick the same chunks
+                sample: (case % 3 == 0).then_some(ChunkSample {
+                    percent: 50.0,
+                    seed: case,
+                }),
+                shadow_check: (case % 3 == 1).then_some(ShadowCheck {
+                    percent: 50.0,
+                    seed: case,
+                }),
+                ..Default::default()
+            };
+            let rayon = AggregateOptions {
+                backend: Backend::Rayon,
+                ..options.clone()
+            };
+            let checked = |options| summarize(aggregate(&input, options).unwrap());
+            // SAFETY: `generate` only writes well-formed records
+            let fast =
+                |options| summarize(unsafe { aggregate_unchecked(&input, options) }.unwrap());
+            let native = (checked(&options), fast(&options));
+            assert_eq!(
+                pool.install(|| (checked(&rayon), fast(&rayon))),
+                native,
+                "case {case}"
+            );
+            if options.sample.is_none() {
+                assert_eq!(native.0, expected, "case {case}");
+            }
+        }
+    }
+
+    #[test]
+    fn rayon_reports_the_same_error() {
+        let input = b"Oslo;1.0\nRome;2.0\nOslo;x\nRome;3.0\nLima;\n";
+        let options = AggregateOptions {
+            threads: 3,
+            chunk_size: Some(8),
+            strict: true,
+            ..Default::default()
+        };
+        let rayon = AggregateOptions {
+            backend: Backend::Rayon,
+            ..options.clone()
+        };
+        let native = aggregate(input, &options).unwrap_err();
+        let rayon = aggregate(input, &rayon).unwrap_err();
+        assert_eq!(native.offset(), 18);
+        assert_eq!(native.to_string(), rayon.to_string());
+    }
+}
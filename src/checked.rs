@@ -0,0 +1,360 @@
+use std::{error::Error, fmt};
+
+use crate::{parse_value, parse_whole};
+
+/// A record that doesn't match `station;[-]d{1,2}.d`
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    /// Byte offset of the start of the offending line in the input
+    pub offset: usize,
+    pub reason: &'static str,
+    /// The start of the offending line, one byte longer than what is shown if it was cut off
+    pub line: Vec<u8>,
+}
+
+const MAX_SHOWN: usize = 120;
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shown = &self.line[..self.line.len().min(MAX_SHOWN)];
+        write!(
+            f,
+            "{} at byte {}: {:?}",
+            self.reason,
+            self.offset,
+            String::from_utf8_lossy(shown)
+        )?;
+        if shown.len() < self.line.len() {
+            f.write_str("...")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseError {}
+
+/// Parses a `[-]d{1,2}.d` temperature into tenths, with `radix` in place of the `.`, rejecting
+/// anything else
+pub fn parse_value_checked(value: &[u8], radix: u8) -> Option<i16> {
+    let digits = value.strip_prefix(b"-").unwrap_or(value);
+    let [int @ .., sep, frac] = digits else {
+        return None;
+    };
+    if *sep != radix {
+        return None;
+    }
+    if !matches!(int.len(), 1..=2) || !int.iter().all(u8::is_ascii_digit) || !frac.is_ascii_digit()
+    {
+        return None;
+    }
+    // SAFETY: the shape was checked above
+    Some(unsafe { parse_value(&value[..value.len() - 2], *frac) })
+}
+
+/// Parses a `[-]d{1,2}` temperature in whole degrees into tenths, rejecting anything else
+fn parse_whole_checked(value: &[u8]) -> Option<i16> {
+    let digits = value.strip_prefix(b"-").unwrap_or(value);
+    if !matches!(digits.len(), 1..=2) || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    // SAFETY: the shape was checked above
+    Some(unsafe { parse_whole(value) })
+}
+
+/// Splits a `line` starting with an RFC 4180 style quoted station name like `"St. John's; NL";12.3`
+/// after the delimiter that follows the closing quote. The name is unescaped into `name`, without
+/// the quotes and with `""` read as a single `"`, and the rest of the line after the delimiter is
+/// returned. Names can't span lines, a quote left open at the end of the line is an error.
+pub fn split_quoted<'a>(
+    line: &'a [u8],
+    delimiter: u8,
+    name: &mut Vec<u8>,
+) -> Result<&'a [u8], &'static str> {
+    name.clear();
+    let mut rest = &line[1..];
+    loop {
+        let Some(quote) = rest.iter().position(|&b| b == b'"') else {
+            return Err("unterminated quoted station name");
+        };
+        name.extend_from_slice(&rest[..quote]);
+        match &rest[quote + 1..] {
+            [b'"', after @ ..] => {
+                name.push(b'"');
+                rest = after;
+            }
+            [b, value @ ..] if *b == delimiter => return Ok(value),
+            _ => return Err("missing delimiter after the quoted station name"),
+        }
+    }
+}
+
+/// What the checked parser accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checked {
+    /// Longest station name in bytes
+    pub max_station_len: usize,
+    /// Also accept temperatures in whole degrees like the fast parser, `--strict` doesn't
+    pub whole_degrees: bool,
+    /// Read station names starting with `"` as quoted, see [`split_quoted`]
+    pub quoted_names: bool,
+    /// Skip blank lines and records that don't parse instead of failing, like the fast parser
+    /// does with the former, `--strict` doesn't
+    pub skip_invalid: bool,
+}
+
+/// Validating counterpart of the fast record loop, used with `--strict`.
+///
+/// `data` must consist of whole lines, the last of which may be followed by the newline that ends
+/// the file. `offset` is the position of `data` in the input and is only used for error reporting.
+/// Stations end at the first `delimiter`, may be at most `checked.max_station_len` bytes long, and
+/// temperatures use `radix` as the decimal separator. With `checked.quoted_names`, `handle_entry`
+/// gets quoted names unescaped and without their quotes. With `checked.skip_invalid`, the lines
+/// that would fail are skipped instead.
+pub fn parse_records(
+    data: &[u8],
+    offset: usize,
+    delimiter: u8,
+    radix: u8,
+    checked: Checked,
+    mut handle_entry: impl FnMut(&[u8], i16),
+) -> Result<(), ParseError> {
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+    let mut line_start = offset;
+    let mut unescaped = Vec::new();
+    for line in data.split(|&b| b == b'\n') {
+        let record = parse_record(line, delimiter, radix, checked, &mut unescaped);
+        match record {
+            Ok((station, value)) => handle_entry(station, value),
+            Err(_) if checked.skip_invalid => {}
+            Err(reason) => {
+                return Err(ParseError {
+                    offset: line_start,
+                    reason,
+                    // Lines of hostile input can be as long as the file, keep only what is shown
+                    line: line[..line.len().min(MAX_SHOWN + 1)].to_vec(),
+                });
+            }
+        }
+        line_start += line.len() + 1;
+    }
+    Ok(())
+}
+
+/// Splits a single `line` into its station and temperature, or says why it can't. Quoted names
+/// are unescaped into `unescaped`.
+fn parse_record<'a>(
+    line: &'a [u8],
+    delimiter: u8,
+    radix: u8,
+    checked: Checked,
+    unescaped: &'a mut Vec<u8>,
+) -> Result<(&'a [u8], i16), &'static str> {
+    if line.is_empty() {
+        return Err("blank line");
+    }
+    let (station, value) = match line {
+        [b'"', ..] if checked.quoted_names => {
+            let value = split_quoted(line, delimiter, unescaped)?;
+            (&unescaped[..], value)
+        }
+        _ => {
+            let Some(split) = line.iter().position(|&b| b == delimiter) else {
+                return Err("missing delimiter");
+            };
+            (&line[..split], &line[split + 1..])
+        }
+    };
+    if station.is_empty() {
+        return Err("empty station name");
+    }
+    if station.len() > checked.max_station_len {
+        return Err("station name longer than --max-station-len");
+    }
+    let parsed = parse_value_checked(value, radix)
+        .or_else(|| checked.whole_degrees.then(|| parse_whole_checked(value))?);
+    let Some(value) = parsed else {
+        // A value with the other separator most likely means the whole file uses it
+        let reason = match radix {
+            b'.' if parse_value_checked(value, b',').is_some() => {
+                "expected a decimal point, found a comma"
+            }
+            b',' if parse_value_checked(value, b'.').is_some() => {
+                "expected a decimal comma, found a point"
+            }
+            _ => "malformed temperature",
+        };
+        return Err(reason);
+    };
+    Ok((station, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aggregate, aggregate_unchecked, AggregateError, AggregateOptions};
+
+    fn split(line: &[u8]) -> Result<(Vec<u8>, &[u8]), &'static str> {
+        let mut name = Vec::new();
+        let value = split_quoted(line, b';', &mut name)?;
+        Ok((name, value))
+    }
+
+    /// `(station, count, sum, min, max)` of every station of `input`, sorted by name
+    fn quoted(
+        input: &[u8],
+        chunk_size: usize,
+        threads: usize,
+        strict: bool,
+    ) -> Vec<(String, usize, i64, i16, i16)> {
+        let options = AggregateOptions {
+            threads,
+            chunk_size: Some(chunk_size),
+            strict,
+            quoted_names: true,
+            ..Default::default()
+        };
+        let mut stations: Vec<_> = aggregate(input, &options)
+            .unwrap()
+            .into_iter()
+            .map(|(name, rec)| {
+                let name = String::from_utf8(name.into_vec()).unwrap();
+                (name, rec.count, rec.sum, rec.min, rec.max)
+            })
+            .collect();
+        stations.sort();
+        stations
+    }
+
+    #[test]
+    fn delimiter_inside_quotes() {
+        let (name, value) = split(b"\"St. John's; NL\";12.3").unwrap();
+        assert_eq!(name, b"St. John's; NL");
+        assert_eq!(value, b"12.3");
+        let (name, value) = split(b"\";;\";-1.0").unwrap();
+        assert_eq!(name, b";;");
+        assert_eq!(value, b"-1.0");
+    }
+
+    #[test]
+    fn escaped_quotes() {
+        let (name, value) = split(b"\"The \"\"Rock\"\"\";4.5").unwrap();
+        assert_eq!(name, b"The \"Rock\"");
+        assert_eq!(value, b"4.5");
+        let (name, _) = split(b"\"\"\"\";4.5").unwrap();
+        assert_eq!(name, b"\"");
+        assert_eq!(
+            split(b"\"Open;1.0"),
+            Err("unterminated quoted station name")
+        );
+        assert_eq!(
+            split(b"\"Closed\"x;1.0"),
+            Err("missing delimiter after the quoted station name")
+        );
+    }
+
+    #[test]
+    fn quoted_name_across_chunks() {
+        let input = b"\"Halifax; NS\";1.0\n\"St. John's; NL\";2.0\n\"Halifax; NS\";3.0\n";
+        let expected = vec![
+            ("Halifax; NS".to_owned(), 2, 40, 10, 30),
+            ("St. John's; NL".to_owned(), 1, 20, 20, 20),
+        ];
+        // Every chunk size puts a chunk edge inside some quoted name
+        for chunk_size in 1..input.len() {
+            for strict in [false, true] {
+                assert_eq!(
+                    quoted(input, chunk_size, 3, strict),
+                    expected,
+                    "{chunk_size}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mixed_quoted_and_unquoted() {
+        let input = b"Hamburg;12.0\n\"Hamburg\";14.0\n\"a;b\";-3.5\nBulawayo;8\n";
+        let expected = vec![
+            ("Bulawayo".to_owned(), 1, 80, 80, 80),
+            ("Hamburg".to_owned(), 2, 260, 120, 140),
+            ("a;b".to_owned(), 1, -35, -35, -35),
+        ];
+        assert_eq!(quoted(input, 4, 2, false), expected);
+    }
+
+    #[test]
+    fn blank_lines_without_strict() {
+        let input = b"\"a;b\";1.0\n\nHamburg;2.0\n\n\n\"a;b\";3.0\n";
+        let expected = vec![
+            ("Hamburg".to_owned(), 1, 20, 20, 20),
+            ("a;b".to_owned(), 2, 40, 10, 30),
+        ];
+        for chunk_size in 1..input.len() {
+            assert_eq!(quoted(input, chunk_size, 2, false), expected);
+        }
+        // Malformed records are skipped as well
+        let input = b"\"a;b\";1.0\n\"open;2.0\nnodelimiter\nHamburg;x\n";
+        assert_eq!(
+            quoted(input, 64, 1, false),
+            vec![("a;b".to_owned(), 1, 10, 10, 10)]
+        );
+        let options = AggregateOptions {
+            strict: true,
+            quoted_names: true,
+            ..Default::default()
+        };
+        assert!(aggregate(b"\"a;b\";1.0\n\n\"a;b\";3.0\n", &options).is_err());
+    }
+
+    #[test]
+    fn decimal_comma() {
+        let input = b"Hamburg;12,0\nOslo;-3,5\nHamburg;8,5\nOslo;0,0\n";
+        let expected = aggregate(
+            b"Hamburg;12.0\nOslo;-3.5\nHamburg;8.5\nOslo;0.0\n",
+            &AggregateOptions::default(),
+        )
+        .unwrap();
+        for chunk_size in 1..input.len() {
+            for (threads, strict) in [(1, false), (3, false), (3, true)] {
+                let options = AggregateOptions {
+                    threads,
+                    chunk_size: Some(chunk_size),
+                    strict,
+                    decimal_comma: true,
+                    ..Default::default()
+                };
+                assert_eq!(aggregate(input, &options).unwrap(), expected);
+                // SAFETY: the input is well-formed
+                let fast = unsafe { aggregate_unchecked(input, &options) };
+                assert_eq!(fast.unwrap(), expected, "{chunk_size}");
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_decimal_separator() {
+        let strict = |input: &[u8], decimal_comma| {
+            let options = AggregateOptions {
+                strict: true,
+                decimal_comma,
+                ..Default::default()
+            };
+            match aggregate(input, &options) {
+                Err(AggregateError::Parse(err)) => (err.offset, err.reason),
+                other => panic!("{other:?}"),
+            }
+        };
+        assert_eq!(
+            strict(b"Oslo;1,0\nOslo;1.0\n", true),
+            (9, "expected a decimal comma, found a point")
+        );
+        assert_eq!(
+            strict(b"Oslo;1.0\nOslo;1,0\n", false),
+            (9, "expected a decimal point, found a comma")
+        );
+        assert_eq!(
+            strict(b"Oslo;1,0\nOslo;1x0\n", true),
+            (9, "malformed temperature")
+        );
+    }
+}
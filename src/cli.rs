@@ -0,0 +1,804 @@
+use std::{error::Error, time::Duration};
+
+use rs::{
+    backend::Backend,
+    histogram,
+    limits::Limits,
+    names::{Collation, FoldCase, NamePolicy},
+    sample::ChunkSample,
+    seed::Unknown,
+    shadow::ShadowCheck,
+    table::Table,
+    DELIMITERS,
+};
+
+const USAGE: &str = "usage: rs [OPTIONS] [PATH]
+       rs serve [OPTIONS] [PATH]
+       rs diff [OPTIONS] OLD NEW
+
+Computes the min/mean/max temperature per station of PATH (default: measurements.txt).
+Ctrl-C prints the results of the input parsed so far and exits with status 130, a second
+Ctrl-C exits right away.
+
+`serve` aggregates PATH once and answers HTTP requests for the results until it is killed
+(requires the serve feature):
+    GET /stations      every station as JSON, `?sort=ORDER` orders them like --collate and
+                       `?top=N` only returns the first N
+    GET /stations/NAME a single station, NAME is percent-decoded
+    GET /healthz       `ok` once the results are ready
+    POST /refresh      aggregate PATH again, e.g. after more was appended to it
+
+`diff` aggregates OLD and NEW and prints how count, min, mean and max of every station moved,
+and the stations only found in one of them. Exits with status 1 if anything differs by more
+than --threshold, 0 if nothing does.
+
+options:
+    --strict           reject malformed records (including blank lines) instead of assuming
+                       well-formed input, blank lines are skipped otherwise and temperatures in
+                       whole degrees like `Oslo;-3` are accepted
+    --untrusted        for input that may be hostile: implies --strict and --validate-utf8 and
+                       defaults to --max-station-len 100, --max-stations 10000,
+                       --max-input-size 16g, --max-output-size 16m and --timeout 10m. Every
+                       accepted line is then at most the station limit plus 7 bytes long,
+                       memory, output and run time stay bounded by the limits, and going over
+                       one fails the run with an error naming it
+    --max-station-len N
+                       reject station names longer than N bytes, requires --strict
+    --max-stations N   fail once there are more than N distinct stations
+    --max-input-size SIZE
+                       refuse inputs larger than SIZE bytes, accepts k/m/g suffixes
+    --max-output-size SIZE
+                       fail once a report gets larger than SIZE bytes, what fits is printed,
+                       accepts k/m/g suffixes
+    --timeout DURATION fail once aggregating an input took longer than DURATION, checked after
+                       every chunk. Both inputs of `diff`, every --follow pass and every serve
+                       refresh get their own, accepts ms/s/m suffixes
+    --delimiter CHAR   byte between the station and the temperature, one of `;` (default), `,`,
+                       `|`, `:` or `\t` for tab; the first one in a line ends the station name
+    --decimal-comma    temperatures use `,` as the decimal separator, e.g. `Hamburg;12,3`, the
+                       output still uses `.`
+    --quoted-names     station names starting with `\"` run up to the closing quote and may
+                       contain the delimiter, with `\"\"` for a quote inside them, e.g.
+                       `\"St. John's; NL\";12.3` is station `St. John's; NL`. Records are
+                       validated as with --strict, but whole degrees are accepted without it
+    --validate-utf8    check that station names are valid UTF-8, invalid sequences are replaced
+                       with U+FFFD, or rejected with --strict
+    --nfc              merge station names that only differ in Unicode composition by
+                       normalizing them to NFC (requires the unicode feature)
+    --trim             strip ASCII whitespace from both ends of station names, merging
+                       `Hamburg ` into `Hamburg`
+    --fold-case[=lower]
+                       merge station names that only differ in case, shown as the spelling of
+                       their first reading or case folded with `=lower`. Folds ASCII letters, and
+                       with the unicode feature every letter of valid UTF-8 names by Unicode
+                       simple case folding
+    --collate ORDER    order of the output: `bytes` (default) compares raw bytes like the
+                       reference implementation, `unicode` compares code points and puts names
+                       that aren't valid UTF-8 last, `accent-insensitive` compares letters
+                       without accents and case first and only breaks ties by them, e.g. `Århus`
+                       sorts with the names starting with `A`. This is not a locale's order
+                       (requires the unicode feature)
+    --stations-file PATH
+                       start every worker's table with the stations of PATH, one name per line,
+                       sized so that it never grows while they are found
+    --stations-strict[=skip]
+                       fail on a station that isn't in --stations-file, or leave out its
+                       readings and warn how many there were with `=skip`
+    --emit-empty       also print the stations of --stations-file without any readings, as
+                       `station;;;`
+    --group-by PATH    aggregate per group instead of per station, PATH maps station names to
+                       group labels with one `station;group` line each
+    --default-group LABEL
+                       group of the stations missing from the mapping (default: unmapped)
+    --drop-unmapped    leave out stations missing from the mapping instead
+    --threads N        number of workers, 0 (default) for one per available core, at most as
+                       many as the cgroup's CPU quota and cpuset allow; with 1 the whole input
+                       is parsed on the main thread, chunk by chunk in file order
+    --chunk-size SIZE  bytes claimed by a worker at a time, accepts k/m/g suffixes
+                       (default: file size / (threads * 16), clamped to 64k..=8m, or to a
+                       chunk per thread on smaller files). Near the end of the input chunks
+                       shrink to what is left / (threads * 2), down to 64k, so the workers
+                       finish close together
+    --max-memory SIZE  map and process the file in windows of at most SIZE bytes, accepts
+                       k/m/g suffixes (default: the whole file, 256m on 32-bit targets)
+    --table TABLE      table the workers aggregate into, `hashbrown` (default) or `flat` for a
+                       plain open addressing table with linear probing
+    --backend BACKEND  `native` (default) to spawn a thread per worker, or `rayon` to run them
+                       as tasks on a rayon pool of --threads threads (requires the rayon feature)
+    --prefault-parallel
+                       have every worker fault in its share of the file before parsing starts
+    --shadow-check[=PERCENT]
+                       also parse a seeded sample of PERCENT% (default: 1) of the chunks with
+                       the checked parser, and abort if the fast parser disagrees with it
+    --shadow-seed SEED seed picking the chunks for --shadow-check (default: 0)
+    --populate         ask the kernel to populate the whole mapping up front (MAP_POPULATE)
+    --numa             give every NUMA node its own region of the file and pin each worker to a
+                       node, workers only take chunks from other regions once theirs is done.
+                       Has no effect on a single node (requires Linux and the numa feature)
+    --save-state PATH  also write the aggregates to PATH in a binary format that --merge-state
+                       reads, before names are normalized or grouped
+    --merge-state PATH add the aggregates saved in PATH to those of the input, can be repeated
+    --record-schedule PATH
+                       log which worker claimed which chunk, in order, to PATH, also when the
+                       run fails
+    --replay-schedule PATH
+                       hand out chunks exactly as logged in PATH by --record-schedule, one claim
+                       at a time; the input and --chunk-size must match the recording
+    --precision N      fractional digits of the mean, 1 (default) to 3; min and max keep the
+                       single digit of the input
+    --histogram N      also count the readings of every station in N buckets splitting
+                       -99.9..=99.9 evenly, appended to its line as N more fields; a value t on
+                       the edge of two buckets goes to bucket (10t + 999) * N / 1999. Costs 4N
+                       bytes per station and worker
+    --follow           keep running after the input is aggregated, add whatever is appended to
+                       it and print the whole report again, separated by a blank line; a last
+                       line without a newline waits for the next pass. Ctrl-C prints the final
+                       report and exits
+    --interval DURATION
+                       time between checks for appended data with --follow, accepts ms/s/m
+                       suffixes (default: 1s)
+    --stats            print a summary of the run to stderr: throughput, arena usage, map
+                       capacities and the chunks, bytes and rows of every thread, and how much
+                       longer its first chunk took than its median one
+    --stats-format FORMAT
+                       `text` (default) or `json` for a single JSON object, implies --stats
+    --timing           print where the time went to stderr: opening and mapping the input,
+                       parsing, merging, sorting and writing the output, and how long every
+                       worker was busy parsing
+    --timing-format FORMAT
+                       `text` (default) or `json` for a single JSON object, implies --timing
+    --estimate         parse a few samples of the input on one thread and print the expected run
+                       time, station count and peak memory with bounds instead of running
+    --estimate-and-run print the estimate, then run and compare the actual time with it
+    --sample-bytes SIZE
+                       only aggregate the first SIZE bytes, cut after the last newline in them,
+                       accepts k/m/g suffixes; the whole file if it is smaller
+    --sample-random PERCENT
+                       only aggregate a random PERCENT% of the chunks, skipping the others
+    --seed SEED        seed picking the chunks for --sample-random (default: 0)
+    --listen ADDR      address `serve` listens on (default: 127.0.0.1:8080)
+    --threshold DEGREES
+                       `diff` leaves out stations whose count is the same and whose min, mean
+                       and max moved by at most DEGREES (default: 0)
+    --diff-format FORMAT
+                       output of `diff`, `text` (default) or `json` for a single JSON object
+    --redact-values    replace values with their per-column percentile rank among all stations
+                       and round counts up to a power of two. Histograms would show the values,
+                       so it can't be combined with --histogram
+    -h, --help         print this message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub path: String,
+    pub strict: bool,
+    pub untrusted: bool,
+    pub max_station_len: Option<usize>,
+    pub max_stations: Option<usize>,
+    pub max_input_size: Option<usize>,
+    pub max_output_size: Option<usize>,
+    pub timeout: Option<Duration>,
+    pub delimiter: u8,
+    pub decimal_comma: bool,
+    pub quoted_names: bool,
+    pub validate_utf8: bool,
+    pub nfc: bool,
+    pub trim: bool,
+    pub fold_case: Option<FoldCase>,
+    pub collate: Collation,
+    pub stations_file: Option<String>,
+    pub unknown_stations: Unknown,
+    pub emit_empty: bool,
+    pub group_by: Option<String>,
+    pub default_group: String,
+    pub drop_unmapped: bool,
+    pub threads: usize,
+    pub chunk_size: Option<usize>,
+    pub max_memory: Option<usize>,
+    pub table: Table,
+    pub backend: Backend,
+    pub prefault_parallel: bool,
+    pub shadow_percent: Option<f64>,
+    pub shadow_seed: u64,
+    pub populate: bool,
+    pub numa: bool,
+    pub precision: u32,
+    pub histogram: usize,
+    pub serve: bool,
+    pub listen: String,
+    pub diff: bool,
+    /// NEW of `diff`, `path` is OLD
+    pub other_path: Option<String>,
+    pub threshold: f64,
+    pub diff_format: StatsFormat,
+    pub follow: bool,
+    pub interval: Duration,
+    pub stats: bool,
+    pub stats_format: StatsFormat,
+    pub timing: bool,
+    pub timing_format: StatsFormat,
+    pub estimate: bool,
+    pub estimate_and_run: bool,
+    pub sample_bytes: Option<usize>,
+    pub sample_percent: Option<f64>,
+    pub seed: u64,
+    pub redact_values: bool,
+    pub save_state: Option<String>,
+    pub merge_state: Vec<String>,
+    pub record_schedule: Option<String>,
+    pub replay_schedule: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            path: "measurements.txt".to_owned(),
+            strict: false,
+            untrusted: false,
+            max_station_len: None,
+            max_stations: None,
+            max_input_size: None,
+            max_output_size: None,
+            timeout: None,
+            delimiter: b';',
+            decimal_comma: false,
+            quoted_names: false,
+            validate_utf8: false,
+            nfc: false,
+            trim: false,
+            fold_case: None,
+            collate: Collation::Bytes,
+            stations_file: None,
+            unknown_stations: Unknown::Add,
+            emit_empty: false,
+            group_by: None,
+            default_group: "unmapped".to_owned(),
+            drop_unmapped: false,
+            threads: 0,
+            chunk_size: None,
+            max_memory: None,
+            table: Table::Hashbrown,
+            backend: Backend::Native,
+            prefault_parallel: false,
+            shadow_percent: None,
+            shadow_seed: 0,
+            populate: false,
+            numa: false,
+            precision: 1,
+            histogram: 0,
+            serve: false,
+            listen: "127.0.0.1:8080".to_owned(),
+            diff: false,
+            other_path: None,
+            threshold: 0.0,
+            diff_format: StatsFormat::Text,
+            follow: false,
+            interval: Duration::from_secs(1),
+            stats: false,
+            stats_format: StatsFormat::Text,
+            timing: false,
+            timing_format: StatsFormat::Text,
+            estimate: false,
+            estimate_and_run: false,
+            sample_bytes: None,
+            sample_percent: None,
+            seed: 0,
+            redact_values: false,
+            save_state: None,
+            merge_state: Vec::new(),
+            record_schedule: None,
+            replay_schedule: None,
+        }
+    }
+}
+
+impl Options {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        let mut options = Self::default();
+        let mut path = None;
+        let mut args = args.peekable();
+        match args
+            .next_if(|arg| arg == "serve" || arg == "diff")
+            .as_deref()
+        {
+            Some("serve") if cfg!(not(feature = "serve")) => {
+                return Err("serve requires building with the serve feature".into());
+            }
+            Some("serve") => options.serve = true,
+            Some(_) => options.diff = true,
+            None => {}
+        }
+        while let Some(arg) = args.next() {
+            // Accept both `--flag value` and `--flag=value`
+            let (flag, mut inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_owned(), Some(value.to_owned()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .take()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{flag} expects a value"))
+            };
+            match flag.as_str() {
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                "--strict" => options.strict = true,
+                "--untrusted" => options.untrusted = true,
+                "--max-station-len" => {
+                    let len = value()?;
+                    options.max_station_len =
+                        Some(len.parse().map_err(|_| format!("invalid length {len:?}"))?);
+                }
+                "--max-stations" => {
+                    let count = value()?;
+                    options.max_stations = Some(
+                        count
+                            .parse()
+                            .map_err(|_| format!("invalid count {count:?}"))?,
+                    );
+                }
+                "--max-input-size" => options.max_input_size = Some(parse_size(&value()?)?),
+                "--max-output-size" => options.max_output_size = Some(parse_size(&value()?)?),
+                "--timeout" => options.timeout = Some(parse_duration(&value()?)?),
+                "--delimiter" => options.delimiter = parse_delimiter(&value()?)?,
+                "--decimal-comma" => options.decimal_comma = true,
+                "--quoted-names" => options.quoted_names = true,
+                "--validate-utf8" => options.validate_utf8 = true,
+                "--nfc" if cfg!(feature = "unicode") => options.nfc = true,
+                "--nfc" => return Err("--nfc requires building with the unicode feature".into()),
+                "--trim" => options.trim = true,
+                "--fold-case" => {
+                    // The display mode is optional, so it can only be passed inline
+                    options.fold_case = Some(match inline.take().as_deref() {
+                        None => FoldCase::First,
+                        Some("lower") => FoldCase::Lower,
+                        Some(mode) => {
+                            return Err(
+                                format!("unknown --fold-case {mode:?}, expected lower").into()
+                            )
+                        }
+                    });
+                }
+                "--collate" => options.collate = parse_collation(&value()?)?,
+                "--stations-file" => options.stations_file = Some(value()?),
+                "--stations-strict" => {
+                    options.unknown_stations = match inline.take().as_deref() {
+                        None => Unknown::Error,
+                        Some("skip") => Unknown::Skip,
+                        Some(mode) => {
+                            return Err(format!(
+                                "unknown --stations-strict {mode:?}, expected skip"
+                            )
+                            .into())
+                        }
+                    };
+                }
+                "--emit-empty" => options.emit_empty = true,
+                "--group-by" => options.group_by = Some(value()?),
+                "--default-group" => options.default_group = value()?,
+                "--drop-unmapped" => options.drop_unmapped = true,
+                "--threads" => {
+                    let threads = value()?;
+                    options.threads = threads
+                        .parse()
+                        .map_err(|_| format!("invalid thread count {threads:?}"))?;
+                }
+                "--chunk-size" => {
+                    let size = parse_size(&value()?)?;
+                    if size == 0 {
+                        return Err("--chunk-size must be at least 1 byte".into());
+                    }
+                    options.chunk_size = Some(size);
+                }
+                "--max-memory" => {
+                    let size = parse_size(&value()?)?;
+                    if size == 0 {
+                        return Err("--max-memory must be at least 1 byte".into());
+                    }
+                    options.max_memory = Some(size);
+                }
+                "--table" => {
+                    options.table = match value()?.as_str() {
+                        "hashbrown" => Table::Hashbrown,
+                        "flat" => Table::Flat,
+                        table => {
+                            return Err(format!(
+                                "unknown table {table:?}, expected hashbrown or flat"
+                            )
+                            .into())
+                        }
+                    };
+                }
+                "--backend" => {
+                    options.backend = match value()?.as_str() {
+                        "native" => Backend::Native,
+                        #[cfg(feature = "rayon")]
+                        "rayon" => Backend::Rayon,
+                        #[cfg(not(feature = "rayon"))]
+                        "rayon" => {
+                            return Err(
+                                "--backend rayon requires building with the rayon feature".into()
+                            )
+                        }
+                        backend => {
+                            return Err(format!(
+                                "unknown backend {backend:?}, expected native or rayon"
+                            )
+                            .into())
+                        }
+                    };
+                }
+                "--prefault-parallel" => options.prefault_parallel = true,
+                "--shadow-check" => {
+                    // The percentage is optional, so it can only be passed inline
+                    let percent = match inline.take() {
+                        Some(percent) => percent
+                            .parse()
+                            .ok()
+                            .filter(|percent| (0.0..=100.0).contains(percent))
+                            .ok_or_else(|| format!("invalid percentage {percent:?}"))?,
+                        None => 1.0,
+                    };
+                    options.shadow_percent = Some(percent);
+                }
+                "--shadow-seed" => {
+                    let seed = value()?;
+                    options.shadow_seed =
+                        seed.parse().map_err(|_| format!("invalid seed {seed:?}"))?;
+                }
+                "--populate" => options.populate = true,
+                "--numa" if cfg!(all(feature = "numa", target_os = "linux")) => options.numa = true,
+                "--numa" => {
+                    return Err("--numa requires Linux and building with the numa feature".into())
+                }
+                "--precision" => {
+                    let digits = value()?;
+                    options.precision = digits
+                        .parse()
+                        .ok()
+                        .filter(|digits| (1..=3).contains(digits))
+                        .ok_or_else(|| format!("invalid precision {digits:?}, expected 1 to 3"))?;
+                }
+                "--histogram" => {
+                    let buckets = value()?;
+                    options.histogram = buckets
+                        .parse()
+                        .ok()
+                        .filter(|buckets| (1..=histogram::MAX_BUCKETS).contains(buckets))
+                        .ok_or_else(|| {
+                            format!(
+                                "invalid bucket count {buckets:?}, expected 1..={}",
+                                histogram::MAX_BUCKETS
+                            )
+                        })?;
+                }
+                "--follow" => options.follow = true,
+                "--interval" => options.interval = parse_duration(&value()?)?,
+                "--stats" => options.stats = true,
+                "--stats-format" => {
+                    options.stats = true;
+                    options.stats_format = parse_format(&value()?)?;
+                }
+                "--timing" => options.timing = true,
+                "--timing-format" => {
+                    options.timing = true;
+                    options.timing_format = parse_format(&value()?)?;
+                }
+                "--estimate" => options.estimate = true,
+                "--estimate-and-run" => options.estimate_and_run = true,
+                "--sample-bytes" => options.sample_bytes = Some(parse_size(&value()?)?),
+                "--sample-random" => {
+                    let percent = value()?;
+                    options.sample_percent = Some(
+                        percent
+                            .parse()
+                            .ok()
+                            .filter(|percent| (0.0..=100.0).contains(percent))
+                            .ok_or_else(|| format!("invalid percentage {percent:?}"))?,
+                    );
+                }
+                "--seed" => {
+                    let seed = value()?;
+                    options.seed = seed.parse().map_err(|_| format!("invalid seed {seed:?}"))?;
+                }
+                "--listen" => options.listen = value()?,
+                "--threshold" => {
+                    let threshold = value()?;
+                    options.threshold = threshold
+                        .parse()
+                        .ok()
+                        .filter(|threshold: &f64| *threshold >= 0.0 && threshold.is_finite())
+                        .ok_or_else(|| format!("invalid threshold {threshold:?}"))?;
+                }
+                "--diff-format" => options.diff_format = parse_format(&value()?)?,
+                "--redact-values" => options.redact_values = true,
+                "--save-state" => options.save_state = Some(value()?),
+                "--merge-state" => options.merge_state.push(value()?),
+                "--record-schedule" => options.record_schedule = Some(value()?),
+                "--replay-schedule" => options.replay_schedule = Some(value()?),
+                _ if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(format!("unknown option {flag}, see --help").into())
+                }
+                _ if path.is_none() => {
+                    path = Some(flag);
+                    continue;
+                }
+                _ if options.diff && options.other_path.is_none() => {
+                    options.other_path = Some(flag);
+                    continue;
+                }
+                _ => return Err(format!("unexpected argument {flag}, see --help").into()),
+            }
+            if inline.is_some() {
+                return Err(format!("{flag} does not take a value").into());
+            }
+        }
+        if options.untrusted {
+            options.strict = true;
+            options.validate_utf8 = true;
+        }
+        // Only the checked parser looks at the length of station names
+        if options.max_station_len.is_some() && !options.strict {
+            return Err("--max-station-len requires --strict or --untrusted".into());
+        }
+        if options.stations_file.is_none() {
+            for (set, flag) in [
+                (
+                    options.unknown_stations != Unknown::Add,
+                    "--stations-strict",
+                ),
+                (options.emit_empty, "--emit-empty"),
+            ] {
+                if set {
+                    return Err(format!("{flag} requires --stations-file").into());
+                }
+            }
+        }
+        // Stations without readings have no values to rank
+        if options.emit_empty && options.redact_values {
+            return Err("--emit-empty can't be combined with --redact-values".into());
+        }
+        // The fast parser the shadow check compares against doesn't understand quotes
+        if options.quoted_names && options.shadow_percent.is_some() {
+            return Err("--quoted-names can't be combined with --shadow-check".into());
+        }
+        // Shadow checked chunks are aggregated without looking at the list
+        if options.unknown_stations != Unknown::Add && options.shadow_percent.is_some() {
+            return Err("--stations-strict can't be combined with --shadow-check".into());
+        }
+        if options.histogram != 0 {
+            // Neither the state format nor the redacted output has room for histograms
+            for (set, flag) in [
+                (options.save_state.is_some(), "--save-state"),
+                (!options.merge_state.is_empty(), "--merge-state"),
+                (options.redact_values, "--redact-values"),
+            ] {
+                if set {
+                    return Err(format!("--histogram can't be combined with {flag}").into());
+                }
+            }
+        }
+        if options.follow
+            && (options.record_schedule.is_some() || options.replay_schedule.is_some())
+        {
+            return Err("schedules can't be recorded or replayed with --follow".into());
+        }
+        if options.follow && (options.estimate || options.estimate_and_run) {
+            return Err("--follow runs until interrupted, it can't be estimated".into());
+        }
+        if options.sample_percent == Some(0.0) {
+            return Err("--sample-random 0 would not aggregate anything".into());
+        }
+        if options.sample_bytes == Some(0) {
+            return Err("--sample-bytes must be at least 1 byte".into());
+        }
+        if options.follow && options.sample_bytes.is_some() {
+            return Err("--sample-bytes can't be combined with --follow".into());
+        }
+        if options.threads != 0 && options.replay_schedule.is_some() {
+            return Err(
+                "--replay-schedule runs on the recorded number of threads, it can't be \
+                        combined with --threads"
+                    .into(),
+            );
+        }
+        if options.backend != Backend::Native {
+            for (set, flag) in [
+                (options.numa, "--numa"),
+                (options.prefault_parallel, "--prefault-parallel"),
+                (options.record_schedule.is_some(), "--record-schedule"),
+                (options.replay_schedule.is_some(), "--replay-schedule"),
+            ] {
+                if set {
+                    return Err(format!(
+                        "{flag} needs the native backend, the rayon pool decides where chunks run"
+                    )
+                    .into());
+                }
+            }
+        }
+        if options.diff && options.other_path.is_none() {
+            return Err("diff expects an OLD and a NEW file, see --help".into());
+        }
+        let command = match (options.serve, options.diff) {
+            (true, _) => Some("serve"),
+            (_, true) => Some("diff"),
+            _ => None,
+        };
+        if let Some(command) = command {
+            for (set, flag) in [
+                (options.follow, "--follow"),
+                (options.estimate || options.estimate_and_run, "--estimate"),
+                (options.stats, "--stats"),
+                (options.timing, "--timing"),
+                (options.save_state.is_some(), "--save-state"),
+                (!options.merge_state.is_empty(), "--merge-state"),
+                (options.record_schedule.is_some(), "--record-schedule"),
+                (options.replay_schedule.is_some(), "--replay-schedule"),
+                (options.diff && options.redact_values, "--redact-values"),
+                (options.emit_empty, "--emit-empty"),
+            ] {
+                if set {
+                    return Err(format!("{flag} can't be combined with {command}").into());
+                }
+            }
+        }
+        if options.record_schedule.is_some() && options.replay_schedule.is_some() {
+            return Err("--record-schedule and --replay-schedule can't be combined".into());
+        }
+        if let Some(path) = path {
+            options.path = path;
+        }
+        Ok(options)
+    }
+
+    pub fn shadow_check(&self) -> Option<ShadowCheck> {
+        self.shadow_percent.map(|percent| ShadowCheck {
+            percent,
+            seed: self.shadow_seed,
+        })
+    }
+
+    /// Whether only part of the input is aggregated, `cut` if `--sample-bytes` is smaller than it
+    pub fn is_sampled(&self, cut: bool) -> bool {
+        cut || self.sample_percent.is_some_and(|percent| percent < 100.0)
+    }
+
+    pub fn sample(&self) -> Option<ChunkSample> {
+        self.sample_percent.map(|percent| ChunkSample {
+            percent,
+            seed: self.seed,
+        })
+    }
+
+    pub fn limits(&self) -> Limits {
+        let defaults = if self.untrusted {
+            Limits::UNTRUSTED
+        } else {
+            Limits::NONE
+        };
+        Limits {
+            max_station_len: self.max_station_len.unwrap_or(defaults.max_station_len),
+            max_stations: self.max_stations.unwrap_or(defaults.max_stations),
+            max_input_size: self
+                .max_input_size
+                .map_or(defaults.max_input_size, |size| size as u64),
+            max_output_size: self
+                .max_output_size
+                .map_or(defaults.max_output_size, |size| size as u64),
+            max_run_time: self.timeout.unwrap_or(defaults.max_run_time),
+        }
+    }
+
+    pub fn name_policy(&self) -> NamePolicy {
+        NamePolicy {
+            validate_utf8: self.validate_utf8,
+            strict: self.strict,
+            nfc: self.nfc,
+            trim: self.trim,
+            fold_case: self.fold_case,
+        }
+    }
+}
+
+fn parse_format(format: &str) -> Result<StatsFormat, Box<dyn Error>> {
+    match format {
+        "text" => Ok(StatsFormat::Text),
+        "json" => Ok(StatsFormat::Json),
+        _ => Err(format!("unknown format {format:?}, expected text or json").into()),
+    }
+}
+
+pub fn parse_collation(collation: &str) -> Result<Collation, Box<dyn Error>> {
+    match collation {
+        "bytes" => Ok(Collation::Bytes),
+        "unicode" => Ok(Collation::Unicode),
+        "accent-insensitive" if cfg!(feature = "unicode") => Ok(Collation::AccentInsensitive),
+        "accent-insensitive" => {
+            Err("--collate accent-insensitive requires building with the unicode feature".into())
+        }
+        _ => Err(format!(
+            "unknown collation {collation:?}, expected bytes, unicode or accent-insensitive"
+        )
+        .into()),
+    }
+}
+
+/// Parses a single byte delimiter, `\t` can be passed escaped
+fn parse_delimiter(delimiter: &str) -> Result<u8, Box<dyn Error>> {
+    let byte = match delimiter.as_bytes() {
+        b"\\t" => b'\t',
+        &[byte] => byte,
+        _ => return Err(format!("--delimiter expects a single byte, found {delimiter:?}").into()),
+    };
+    if matches!(byte, b'\n' | b'.' | b'-' | b'0'..=b'9') {
+        return Err(
+            format!("{delimiter:?} can't be a delimiter, it can be part of a record").into(),
+        );
+    }
+    if !DELIMITERS.contains(&byte) {
+        return Err(format!(
+            "unsupported delimiter {delimiter:?}, expected one of `;`, `,`, `|`, `:` or `\\t`"
+        )
+        .into());
+    }
+    Ok(byte)
+}
+
+/// Parses a byte count with an optional binary `k`/`m`/`g` suffix, e.g. `512k` or `4M`
+fn parse_size(size: &str) -> Result<usize, Box<dyn Error>> {
+    let invalid = || format!("invalid size {size:?}");
+    let (digits, multiplier) = match size.as_bytes().last().map(u8::to_ascii_lowercase) {
+        Some(b'k') => (&size[..size.len() - 1], 1 << 10),
+        Some(b'm') => (&size[..size.len() - 1], 1 << 20),
+        Some(b'g') => (&size[..size.len() - 1], 1 << 30),
+        _ => (size, 1),
+    };
+    let n: usize = digits.parse().map_err(|_| invalid())?;
+    Ok(n.checked_mul(multiplier).ok_or_else(invalid)?)
+}
+
+/// Parses a duration with a `ms`, `s` or `m` suffix, e.g. `500ms` or `5s`
+fn parse_duration(duration: &str) -> Result<Duration, Box<dyn Error>> {
+    let invalid = || format!("invalid duration {duration:?}, expected e.g. 500ms, 5s or 1m");
+    let (digits, unit) = if let Some(digits) = duration.strip_suffix("ms") {
+        (digits, Duration::from_millis(1))
+    } else if let Some(digits) = duration.strip_suffix('s') {
+        (digits, Duration::from_secs(1))
+    } else if let Some(digits) = duration.strip_suffix('m') {
+        (digits, Duration::from_secs(60))
+    } else {
+        return Err(invalid().into());
+    };
+    let n: u32 = digits.parse().map_err(|_| invalid())?;
+    Ok(unit * n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, Box<dyn Error>> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn redacted_output_has_no_histograms() {
+        let err = parse(&["in.txt", "--histogram", "4", "--redact-values"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--histogram can't be combined with --redact-values"
+        );
+        assert!(parse(&["in.txt", "--redact-values"]).unwrap().redact_values);
+    }
+}
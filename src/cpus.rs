@@ -0,0 +1,244 @@
+//! The CPU limit of the process's cgroup, which the default number of workers doesn't go over.
+//!
+//! `available_parallelism` only knows about the cgroup quota on some runtimes and cgroup versions,
+//! a container with a 4 CPU quota on a 64 core node can still see 64 and then gets throttled. The
+//! quota is read from `cpu.max` on cgroup v2, including those of the parent groups, and from
+//! `cpu.cfs_quota_us` and `cpu.cfs_period_us` on v1, the cpuset from `cpuset.cpus.effective` or
+//! `cpuset.cpus`. Anything missing or unreadable counts as unlimited, as does every limit on
+//! other systems than Linux.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    path::Path,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuLimit {
+    /// What `available_parallelism` reports
+    pub available: usize,
+    /// CPUs worth of time the quota allows per period
+    pub quota: Option<f64>,
+    /// CPUs in the cpuset
+    pub cpuset: Option<usize>,
+}
+
+impl CpuLimit {
+    pub fn detect() -> Self {
+        Self::detect_in(Path::new("/"))
+    }
+
+    /// Reads the limits of the cgroup in `root/proc/self/cgroup`, with the hierarchies mounted in
+    /// `root/sys/fs/cgroup`
+    pub fn detect_in(root: &Path) -> Self {
+        #[cfg(target_os = "linux")]
+        let (quota, cpuset) = linux::read_limits(root);
+        #[cfg(not(target_os = "linux"))]
+        let (quota, cpuset) = {
+            let _ = root;
+            (None, None)
+        };
+        Self {
+            available: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            quota,
+            cpuset,
+        }
+    }
+
+    /// Workers to run by default, the fewest CPUs any of the limits allows
+    pub fn threads(&self) -> usize {
+        let quota = self.quota.map_or(usize::MAX, |quota| quota.ceil() as usize);
+        let cpuset = self.cpuset.unwrap_or(usize::MAX);
+        self.available.min(quota).min(cpuset).max(1)
+    }
+
+    /// Writes the limits as a JSON object
+    pub fn write_json(&self, mut out: impl Write) -> io::Result<()> {
+        let quota = self
+            .quota
+            .map_or("null".to_owned(), |quota| quota.to_string());
+        let cpuset = self
+            .cpuset
+            .map_or("null".to_owned(), |cpus| cpus.to_string());
+        write!(
+            out,
+            "{{\"available\":{},\"quota\":{quota},\"cpuset\":{cpuset},\"threads\":{}}}",
+            self.available,
+            self.threads()
+        )
+    }
+}
+
+impl fmt::Display for CpuLimit {
+    /// E.g. `4 of 64 available CPUs, cgroup quota 4.00 CPUs`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} available CPUs", self.threads(), self.available)?;
+        if let Some(quota) = self.quota {
+            write!(f, ", cgroup quota {quota:.2} CPUs")?;
+        }
+        if let Some(cpus) = self.cpuset {
+            write!(f, ", cpuset of {cpus} CPUs")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use crate::numa::parse_cpu_list;
+
+    /// The quota and cpuset of every hierarchy listed in `root/proc/self/cgroup`, the lowest ones
+    /// if there are several
+    pub(super) fn read_limits(root: &Path) -> (Option<f64>, Option<usize>) {
+        let Ok(cgroups) = fs::read_to_string(root.join("proc/self/cgroup")) else {
+            return (None, None);
+        };
+        let mount = root.join("sys/fs/cgroup");
+        let (mut quota, mut cpuset) = (None, None);
+        // Lines look like `hierarchy:controllers:path`, v2 has no controllers
+        for line in cgroups.lines() {
+            let mut fields = line.splitn(3, ':').skip(1);
+            let (Some(controllers), Some(path)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            let path = path.trim_start_matches('/');
+            if controllers.is_empty() {
+                // The limits of the parent groups apply as well
+                for group in Path::new(path).ancestors() {
+                    let dir = mount.join(group);
+                    quota = min(quota, read(&dir.join("cpu.max")).and_then(parse_cpu_max));
+                }
+                let cpus = read(&mount.join(path).join("cpuset.cpus.effective"));
+                cpuset = min(cpuset, cpus.and_then(count_cpus));
+                continue;
+            }
+            let dir = v1_dir(&mount, controllers, path);
+            for controller in controllers.split(',') {
+                match controller {
+                    "cpu" => quota = min(quota, read_cfs_quota(&dir)),
+                    "cpuset" => {
+                        let cpus = read(&dir.join("cpuset.effective_cpus"))
+                            .or_else(|| read(&dir.join("cpuset.cpus")));
+                        cpuset = min(cpuset, cpus.and_then(count_cpus));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        (quota, cpuset)
+    }
+
+    /// Directory of a v1 group, the root of its hierarchy if the path is from outside the
+    /// container's view of it
+    fn v1_dir(mount: &Path, controllers: &str, path: &str) -> PathBuf {
+        let hierarchy = mount.join(controllers);
+        let dir = hierarchy.join(path);
+        match dir.is_dir() {
+            true => dir,
+            false => hierarchy,
+        }
+    }
+
+    fn read(path: &Path) -> Option<String> {
+        fs::read_to_string(path).ok()
+    }
+
+    fn min<T: PartialOrd>(a: Option<T>, b: Option<T>) -> Option<T> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(if b < a { b } else { a }),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Parses `$MAX $PERIOD` in microseconds, where `$MAX` is `max` without a quota
+    fn parse_cpu_max(contents: String) -> Option<f64> {
+        let mut fields = contents.split_whitespace();
+        let max = fields.next()?.parse::<u64>().ok()?;
+        let period = fields
+            .next()
+            .map_or(Some(100_000), |period| period.parse().ok())?;
+        quota(max, period)
+    }
+
+    /// `cpu.cfs_quota_us` is -1 without a quota
+    fn read_cfs_quota(dir: &Path) -> Option<f64> {
+        let max = read(&dir.join("cpu.cfs_quota_us"))?
+            .trim()
+            .parse::<i64>()
+            .ok()?;
+        let period = read(&dir.join("cpu.cfs_period_us"))?.trim().parse().ok()?;
+        quota(u64::try_from(max).ok()?, period)
+    }
+
+    fn quota(max: u64, period: u64) -> Option<f64> {
+        (max > 0 && period > 0).then(|| max as f64 / period as f64)
+    }
+
+    fn count_cpus(list: String) -> Option<usize> {
+        parse_cpu_list(&list)
+            .map(|cpus| cpus.len())
+            .filter(|&cpus| cpus > 0)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{cpus::CpuLimit, tests::TempFile};
+
+        fn cpu_max(contents: &str) -> Option<f64> {
+            parse_cpu_max(contents.to_owned())
+        }
+
+        #[test]
+        fn parses_cpu_max() {
+            assert_eq!(cpu_max("max 100000\n"), None);
+            assert_eq!(cpu_max("50000 100000\n"), Some(0.5));
+            assert_eq!(cpu_max("250000 100000\n"), Some(2.5));
+            assert_eq!(cpu_max("200000"), Some(2.0));
+            for malformed in [
+                "",
+                "\n",
+                "lots 100000",
+                "50000 often",
+                "-1 100000",
+                "0 100000",
+            ] {
+                assert_eq!(cpu_max(malformed), None, "{malformed:?}");
+            }
+        }
+
+        /// The limit read from a cgroup v2 hierarchy whose root group has `cpu_max`
+        fn detect_v2(name: &str, cpu_max: &str) -> CpuLimit {
+            let root = TempFile::new(name);
+            fs::create_dir_all(root.0.join("proc/self")).unwrap();
+            fs::create_dir_all(root.0.join("sys/fs/cgroup")).unwrap();
+            fs::write(root.0.join("proc/self/cgroup"), "0::/\n").unwrap();
+            fs::write(root.0.join("sys/fs/cgroup/cpu.max"), cpu_max).unwrap();
+            let limit = CpuLimit::detect_in(&root.0);
+            fs::remove_dir_all(&root.0).unwrap();
+            limit
+        }
+
+        #[test]
+        fn threads_from_cpu_max() {
+            let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+            let limit = detect_v2("cpu-max-none", "max 100000\n");
+            assert_eq!((limit.quota, limit.threads()), (None, available));
+            // Half a CPU still needs a worker
+            let limit = detect_v2("cpu-max-half", "50000 100000\n");
+            assert_eq!((limit.quota, limit.threads()), (Some(0.5), 1));
+            let limit = detect_v2("cpu-max-2.5", "250000 100000\n");
+            assert_eq!(
+                (limit.quota, limit.threads()),
+                (Some(2.5), available.min(3))
+            );
+            let limit = detect_v2("cpu-max-malformed", "unlimited\n");
+            assert_eq!((limit.quota, limit.threads()), (None, available));
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! `rs diff`, comparing the aggregates of two inputs station by station.
+
+use std::io::{self, Write};
+
+use rs::{Map, MeasurementRecord};
+
+use crate::{write_json_string, Results};
+
+/// How a station found in both inputs moved from the old to the new one
+struct Change<'a> {
+    name: &'a [u8],
+    count: i64,
+    /// In degrees
+    min: f64,
+    mean: f64,
+    max: f64,
+}
+
+/// The stations that moved by more than the threshold, and those only found in one input, in
+/// the output order of each input
+pub struct Diff<'a> {
+    changed: Vec<Change<'a>>,
+    only_old: Vec<&'a [u8]>,
+    only_new: Vec<&'a [u8]>,
+}
+
+impl<'a> Diff<'a> {
+    /// Compares `old` with `new`, leaving out stations whose count is the same and whose min, mean
+    /// and max moved by at most `threshold` degrees
+    pub fn new(old: &'a Results, new: &'a Results, threshold: f64) -> Self {
+        let by_name: Map<&[u8], &MeasurementRecord> =
+            new.iter().map(|(name, record)| (&**name, record)).collect();
+        let mut changed = Vec::new();
+        let mut only_old = Vec::new();
+        for (name, before) in old {
+            let Some(after) = by_name.get(&**name) else {
+                only_old.push(&**name);
+                continue;
+            };
+            let tenths = |before: i16, after: i16| f64::from(after - before) / 10.0;
+            let change = Change {
+                name,
+                count: after.count as i64 - before.count as i64,
+                min: tenths(before.min, after.min),
+                mean: mean(after) - mean(before),
+                max: tenths(before.max, after.max),
+            };
+            if change.count != 0
+                || [change.min, change.mean, change.max]
+                    .iter()
+                    .any(|delta| delta.abs() > threshold)
+            {
+                changed.push(change);
+            }
+        }
+        let old: Map<&[u8], ()> = old.iter().map(|(name, _)| (&**name, ())).collect();
+        let only_new = new
+            .iter()
+            .map(|(name, _)| &**name)
+            .filter(|name| !old.contains_key(name))
+            .collect();
+        Self {
+            changed,
+            only_old,
+            only_new,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.only_old.is_empty() && self.only_new.is_empty()
+    }
+
+    /// Writes a section per kind of difference, with one `station;count;min;mean;max` line of
+    /// signed deltas per changed station. Prints nothing without any difference.
+    pub fn write_text(
+        &self,
+        mut out: impl Write,
+        old_path: &str,
+        new_path: &str,
+        precision: u32,
+    ) -> io::Result<()> {
+        let precision = precision as usize;
+        if !self.changed.is_empty() {
+            writeln!(out, "changed (count;min;mean;max):")?;
+        }
+        for change in &self.changed {
+            out.write_all(change.name)?;
+            writeln!(
+                out,
+                ";{:+};{:+.1};{:+.precision$};{:+.1}",
+                change.count, change.min, change.mean, change.max
+            )?;
+        }
+        for (names, path) in [(&self.only_old, old_path), (&self.only_new, new_path)] {
+            if !names.is_empty() {
+                writeln!(out, "only in {path}:")?;
+            }
+            for name in names {
+                out.write_all(name)?;
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the differences as a single line JSON object
+    pub fn write_json(&self, mut out: impl Write, precision: u32) -> io::Result<()> {
+        let precision = precision as usize;
+        let mut json = b"{\"changed\":[".to_vec();
+        for (i, change) in self.changed.iter().enumerate() {
+            if i > 0 {
+                json.push(b',');
+            }
+            json.extend_from_slice(b"{\"name\":");
+            write_json_string(&mut json, change.name);
+            write!(
+                json,
+                ",\"count\":{},\"min\":{:.1},\"mean\":{:.precision$},\"max\":{:.1}}}",
+                change.count, change.min, change.mean, change.max
+            )?;
+        }
+        for (key, names) in [("only_old", &self.only_old), ("only_new", &self.only_new)] {
+            write!(json, "],\"{key}\":[")?;
+            for (i, name) in names.iter().enumerate() {
+                if i > 0 {
+                    json.push(b',');
+                }
+                write_json_string(&mut json, name);
+            }
+        }
+        json.extend_from_slice(b"]}\n");
+        out.write_all(&json)
+    }
+}
+
+/// Mean in degrees
+fn mean(record: &MeasurementRecord) -> f64 {
+    record.sum as f64 / record.count as f64 / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stations with `(name, readings in tenths)`, in the given order
+    fn results(stations: &[(&str, &[i16])]) -> Results {
+        stations
+            .iter()
+            .map(|&(name, readings)| {
+                let mut record = MeasurementRecord::first(readings[0], 0);
+                for &value in &readings[1..] {
+                    record.merge(&MeasurementRecord::first(value, 0));
+                }
+                (name.as_bytes().into(), record)
+            })
+            .collect()
+    }
+
+    fn text(diff: &Diff) -> String {
+        let mut out = Vec::new();
+        diff.write_text(&mut out, "old.txt", "new.txt", 1).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn json(diff: &Diff) -> String {
+        let mut out = Vec::new();
+        diff.write_json(&mut out, 1).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn identical_inputs() {
+        let old = results(&[("Oslo", &[10, 20]), ("Rome", &[200])]);
+        let diff = Diff::new(&old, &old, 0.0);
+        assert!(diff.is_empty());
+        assert_eq!(text(&diff), "");
+        assert_eq!(
+            json(&diff),
+            "{\"changed\":[],\"only_old\":[],\"only_new\":[]}\n"
+        );
+    }
+
+    #[test]
+    fn added_removed_and_changed() {
+        let old = results(&[("Lima", &[150]), ("Oslo", &[10, 20]), ("Rome", &[200])]);
+        let new = results(&[("Oslo", &[10, 35]), ("Rome", &[200]), ("Sofia", &[-5])]);
+        let diff = Diff::new(&old, &new, 0.0);
+        assert!(!diff.is_empty());
+        assert_eq!(
+            text(&diff),
+            "changed (count;min;mean;max):\n\
+             Oslo;+0;+0.0;+0.8;+1.5\n\
+             only in old.txt:\n\
+             Lima\n\
+             only in new.txt:\n\
+             Sofia\n"
+        );
+        assert_eq!(
+            json(&diff),
+            "{\"changed\":[{\"name\":\"Oslo\",\"count\":0,\"min\":0.0,\"mean\":0.8,\"max\":1.5}],\
+             \"only_old\":[\"Lima\"],\"only_new\":[\"Sofia\"]}\n"
+        );
+    }
+
+    #[test]
+    fn threshold() {
+        let old = results(&[("Oslo", &[10, 20])]);
+        let new = results(&[("Oslo", &[10, 25])]);
+        // Max moved by 0.5 and the mean by 0.25 degrees
+        assert!(Diff::new(&old, &new, 0.5).is_empty());
+        assert!(!Diff::new(&old, &new, 0.4).is_empty());
+        // A different count always shows
+        let more = results(&[("Oslo", &[10, 20, 15])]);
+        let diff = Diff::new(&old, &more, 10.0);
+        assert_eq!(
+            text(&diff),
+            "changed (count;min;mean;max):\nOslo;+1;+0.0;+0.0;+0.0\n"
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Run time and memory estimates for `--estimate`, from a few calibration samples of the input.
+//!
+//! The model is linear: every worker is assumed to parse at the single-threaded rate measured on
+//! the samples, so the run takes `file_len / (rate * threads)`. The bounds use the slowest and
+//! fastest sample instead of the mean rate. Memory is the mapped input, which becomes resident as
+//! it is parsed, plus a map and an arena chunk per worker and the merged result.
+
+use std::{fmt, mem::size_of, time::Duration};
+
+use crate::{MeasurementRecord, StationKey, BUMP_CAP};
+
+/// One calibration parse
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub bytes: usize,
+    pub elapsed: Duration,
+    /// Distinct stations in this and every earlier sample
+    pub stations: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    /// Lowest, expected and highest run time in seconds
+    pub seconds: [f64; 3],
+    /// Lowest and highest number of distinct stations
+    pub stations: [usize; 2],
+    /// Expected peak memory in bytes, for the lowest and highest station count
+    pub memory: [u64; 2],
+}
+
+impl Estimate {
+    /// Extrapolates `samples` to `file_len` bytes parsed by `threads` workers, mapping at most
+    /// `window` bytes of the input at a time. `None` without any sampled bytes.
+    pub fn new(samples: &[Sample], file_len: u64, threads: usize, window: u64) -> Option<Self> {
+        let bytes: usize = samples.iter().map(|sample| sample.bytes).sum();
+        let elapsed: f64 = samples
+            .iter()
+            .map(|sample| sample.elapsed.as_secs_f64())
+            .sum();
+        if bytes == 0 || elapsed == 0.0 {
+            return None;
+        }
+        let rates = samples
+            .iter()
+            .filter(|sample| sample.bytes > 0)
+            .map(|sample| {
+                sample.bytes as f64 / sample.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+            });
+        let slowest = rates.clone().fold(f64::INFINITY, f64::min);
+        let fastest = rates.fold(0.0, f64::max);
+        let seconds = |rate: f64| file_len as f64 / (rate * threads as f64);
+
+        let seen = samples.last().map_or(0, |sample| sample.stations);
+        // Stations still turning up in the last sample may keep doing so over the rest of the
+        // file, at most at the rate they were found so far
+        let still_growing = match samples {
+            [.., before, last] => last.stations > before.stations,
+            [last] => last.stations > 0,
+            [] => false,
+        };
+        let most = if still_growing {
+            (seen as f64 * file_len as f64 / bytes as f64).ceil() as usize
+        } else {
+            seen
+        };
+        let memory = |stations| {
+            let mapped = window.min(file_len);
+            let per_worker = map_bytes::<StationKey>(stations) + BUMP_CAP as u64;
+            // The merged map holds owned names, assumed to average 16 bytes
+            let merged = map_bytes::<Box<[u8]>>(stations) + stations as u64 * 16;
+            mapped + per_worker * threads as u64 + merged
+        };
+        Some(Self {
+            seconds: [
+                seconds(fastest),
+                seconds(bytes as f64 / elapsed),
+                seconds(slowest),
+            ],
+            stations: [seen, most],
+            memory: [memory(seen), memory(most)],
+        })
+    }
+}
+
+/// Table size of a map of `stations` entries keyed by `K`, created with the capacity `work` uses
+fn map_bytes<K>(stations: usize) -> u64 {
+    let capacity = stations.max(1024 * 8);
+    // hashbrown keeps the table at most 7/8 full and rounds up to a power of two buckets, with a
+    // control byte per bucket
+    let buckets = (capacity * 8 / 7).next_power_of_two();
+    (buckets * (size_of::<(K, MeasurementRecord)>() + 1)) as u64
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [fastest, expected, slowest] = self.seconds;
+        let [fewest, most] = self.stations;
+        let [least, largest] = self.memory;
+        let mib = |bytes: u64| bytes as f64 / (1 << 20) as f64;
+        writeln!(
+            f,
+            "estimated time:     {expected:.2} s ({fastest:.2}..{slowest:.2} s)"
+        )?;
+        writeln!(f, "estimated stations: {fewest}..{most}")?;
+        write!(
+            f,
+            "estimated memory:   {:.1}..{:.1} MiB",
+            mib(least),
+            mib(largest)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: usize = 1_000_000;
+
+    fn sample(bytes: usize, millis: u64, stations: usize) -> Sample {
+        Sample {
+            bytes,
+            elapsed: Duration::from_millis(millis),
+            stations,
+        }
+    }
+
+    #[test]
+    fn synthetic_calibration() {
+        // 1 MB/s and 2 MB/s, 4/3 MB/s over both
+        let samples = [sample(MB, 1000, 10), sample(MB, 500, 10)];
+        let estimate = Estimate::new(&samples, 10 * MB as u64, 2, u64::MAX).unwrap();
+        assert_eq!(estimate.seconds, [2.5, 3.75, 5.0]);
+        // No new station in the last sample
+        assert_eq!(estimate.stations, [10, 10]);
+        let memory = 10 * MB as u64
+            + 2 * (map_bytes::<StationKey>(10) + BUMP_CAP as u64)
+            + map_bytes::<Box<[u8]>>(10)
+            + 10 * 16;
+        assert_eq!(estimate.memory, [memory, memory]);
+        assert_eq!(
+            estimate.to_string(),
+            format!(
+                "estimated time:     3.75 s (2.50..5.00 s)\n\
+                 estimated stations: 10..10\n\
+                 estimated memory:   {0:.1}..{0:.1} MiB",
+                memory as f64 / (1 << 20) as f64
+            )
+        );
+    }
+
+    #[test]
+    fn growing_stations() {
+        // Still finding stations, at most 50 over the 5 times larger file
+        let samples = [sample(MB, 100, 6), sample(MB, 100, 10)];
+        let estimate = Estimate::new(&samples, 10 * MB as u64, 1, MB as u64).unwrap();
+        assert_eq!(estimate.stations, [10, 50]);
+        // Only a window of the input is mapped at a time, and the tables only grow past 8192
+        let [fewest, most] = estimate.memory;
+        assert_eq!(most - fewest, 40 * 16);
+        assert_eq!(
+            fewest,
+            MB as u64
+                + map_bytes::<StationKey>(0)
+                + BUMP_CAP as u64
+                + map_bytes::<Box<[u8]>>(0)
+                + 10 * 16
+        );
+        // 10 MB/s on one worker
+        assert_eq!(estimate.seconds, [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn nothing_sampled() {
+        assert_eq!(Estimate::new(&[], 100, 1, 100), None);
+        assert_eq!(Estimate::new(&[sample(0, 10, 0)], 100, 1, 100), None);
+        assert_eq!(Estimate::new(&[sample(100, 0, 1)], 100, 1, 100), None);
+    }
+
+    #[test]
+    fn table_sizes() {
+        let entry = size_of::<(StationKey, MeasurementRecord)>() as u64 + 1;
+        // At least the 8192 entries `work` reserves, in 16384 buckets
+        assert_eq!(map_bytes::<StationKey>(10), 16384 * entry);
+        assert_eq!(map_bytes::<StationKey>(20_000), 32768 * entry);
+    }
+}
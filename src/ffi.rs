@@ -0,0 +1,228 @@
+//! C API for embedding the aggregator, declared in `ffi/brc.h`.
+//!
+//! Every function returns one of the `BRC_*` codes and leaves a description of the last error on
+//! the calling thread for [`brc_last_error_message`]. Panics are caught at every entry point and
+//! reported as [`BRC_PANIC`], except in builds with `panic = "abort"` like the release profile,
+//! where they abort the process as anywhere else.
+//!
+//! Input is read with the checked parser, malformed lines are skipped instead of being trusted.
+//!
+//! A result owns the names of its stations, the pointers handed out by [`brc_result_get`] stay
+//! valid until it is freed with [`brc_result_free`].
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    fs::File,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+
+#[cfg(not(target_family = "wasm"))]
+use memmap2::Mmap;
+
+use crate::{aggregate, cpus::CpuLimit, AggregateOptions, MeasurementRecord};
+
+pub const BRC_OK: c_int = 0;
+/// A required pointer argument was null
+pub const BRC_NULL_ARGUMENT: c_int = 1;
+/// The file couldn't be opened or mapped
+pub const BRC_IO: c_int = 2;
+/// The input couldn't be aggregated
+pub const BRC_AGGREGATE: c_int = 3;
+/// The station index is out of bounds
+pub const BRC_OUT_OF_BOUNDS: c_int = 4;
+/// A panic was caught, this is a bug
+pub const BRC_PANIC: c_int = 5;
+
+/// The aggregates of every station, sorted by name
+pub struct BrcResult {
+    stations: Vec<(Box<[u8]>, MeasurementRecord)>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: impl Into<Vec<u8>>) {
+    let mut message = message.into();
+    message.retain(|&b| b != 0);
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(message).ok());
+}
+
+/// Runs `f`, records its error if it fails and turns a panic into [`BRC_PANIC`]
+fn guard(f: impl FnOnce() -> Result<(), (c_int, String)>) -> c_int {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => BRC_OK,
+        Ok(Err((code, message))) => {
+            set_error(message);
+            code
+        }
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+            set_error(format!("panicked: {message}"));
+            BRC_PANIC
+        }
+    }
+}
+
+fn aggregate_into(data: &[u8], out: *mut *mut BrcResult) -> Result<(), (c_int, String)> {
+    let options = AggregateOptions {
+        threads: CpuLimit::detect().threads(),
+        ..Default::default()
+    };
+    let map = aggregate(data, &options).map_err(|err| (BRC_AGGREGATE, err.to_string()))?;
+    let mut stations: Vec<_> = map.into_iter().collect();
+    stations.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    // SAFETY: checked to be non-null by the caller, valid for writes per the contract
+    unsafe { *out = Box::into_raw(Box::new(BrcResult { stations })) };
+    Ok(())
+}
+
+/// Aggregates the file at `path` and stores the result in `*out`.
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string and `out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn brc_aggregate_file(
+    path: *const c_char,
+    out: *mut *mut BrcResult,
+) -> c_int {
+    guard(|| {
+        if path.is_null() || out.is_null() {
+            return Err((
+                BRC_NULL_ARGUMENT,
+                "path and out must not be null".to_owned(),
+            ));
+        }
+        let path = unsafe { CStr::from_ptr(path) }.to_string_lossy();
+        let io_error = |err| (BRC_IO, format!("{path}: {err}"));
+        let file = File::open(&*path).map_err(io_error)?;
+        // Empty files can't be mapped
+        if file.metadata().map_err(io_error)?.len() == 0 {
+            return aggregate_into(&[], out);
+        }
+        #[cfg(not(target_family = "wasm"))]
+        // SAFETY: the file is assumed not to be modified while it is mapped, like in the binary
+        let data = unsafe { Mmap::map(&file) }.map_err(io_error)?;
+        #[cfg(target_family = "wasm")]
+        let data = {
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut &file, &mut data).map_err(io_error)?;
+            data
+        };
+        aggregate_into(&data, out)
+    })
+}
+
+/// Aggregates the `len` bytes at `data` and stores the result in `*out`.
+///
+/// # Safety
+/// `data` must be valid for reads of `len` bytes, or may be null if `len` is 0, and `out` valid
+/// for writes.
+#[no_mangle]
+pub unsafe extern "C" fn brc_aggregate_buffer(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut BrcResult,
+) -> c_int {
+    guard(|| {
+        if (data.is_null() && len > 0) || out.is_null() {
+            return Err((
+                BRC_NULL_ARGUMENT,
+                "data and out must not be null".to_owned(),
+            ));
+        }
+        let data = match len {
+            0 => &[],
+            _ => unsafe { std::slice::from_raw_parts(data, len) },
+        };
+        aggregate_into(data, out)
+    })
+}
+
+/// Number of stations in `result`, 0 if it is null.
+///
+/// # Safety
+/// `result` must be null or a result that wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn brc_result_len(result: *const BrcResult) -> usize {
+    unsafe { result.as_ref() }.map_or(0, |result| result.stations.len())
+}
+
+/// Reads station `index` of `result`. The name isn't NUL-terminated. Temperatures are in tenths
+/// of a degree, `sum` is the sum of all readings.
+///
+/// # Safety
+/// `result` must be a result that wasn't freed yet, every other pointer must be valid for writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn brc_result_get(
+    result: *const BrcResult,
+    index: usize,
+    name: *mut *const u8,
+    name_len: *mut usize,
+    count: *mut u64,
+    sum: *mut i64,
+    min: *mut i16,
+    max: *mut i16,
+) -> c_int {
+    guard(|| {
+        let pointers = [
+            name.cast(),
+            name_len.cast(),
+            count.cast(),
+            sum.cast(),
+            min.cast(),
+            max.cast(),
+        ];
+        if result.is_null() || pointers.contains(&ptr::null_mut::<u8>()) {
+            return Err((BRC_NULL_ARGUMENT, "arguments must not be null".to_owned()));
+        }
+        let stations = unsafe { &(*result).stations };
+        let Some((station, record)) = stations.get(index) else {
+            return Err((
+                BRC_OUT_OF_BOUNDS,
+                format!(
+                    "index {index} out of bounds for {} stations",
+                    stations.len()
+                ),
+            ));
+        };
+        unsafe {
+            *name = station.as_ptr();
+            *name_len = station.len();
+            *count = record.count as u64;
+            *sum = record.sum;
+            *min = record.min;
+            *max = record.max;
+        }
+        Ok(())
+    })
+}
+
+/// Frees `result` and the station names it owns, does nothing if it is null.
+///
+/// # Safety
+/// `result` must be null or a result that wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn brc_result_free(result: *mut BrcResult) {
+    if !result.is_null() {
+        drop(unsafe { Box::from_raw(result) });
+    }
+}
+
+/// Description of the last error on the calling thread, null if there was none. Valid until the
+/// next call on this thread.
+#[no_mangle]
+pub extern "C" fn brc_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
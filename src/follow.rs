@@ -0,0 +1,19 @@
+use std::time::{Duration, Instant};
+
+use crate::interrupt::INTERRUPT;
+
+/// Waits for `interval`, returns false as soon as Ctrl-C was pressed
+pub fn sleep(interval: Duration) -> bool {
+    const STEP: Duration = Duration::from_millis(50);
+    let deadline = Instant::now() + interval;
+    loop {
+        if INTERRUPT.is_requested() {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        std::thread::sleep(STEP.min(deadline - now));
+    }
+}
@@ -0,0 +1,101 @@
+use std::{error::Error, fs};
+
+use hashbrown::hash_map::EntryRef;
+
+use crate::{Map, Stations};
+
+/// Station to group label mapping loaded from a `station;group` file
+pub struct Groups {
+    groups: Map<Box<[u8]>, Box<[u8]>>,
+    /// Group of the stations missing from the mapping, `None` drops them
+    unmapped: Option<Box<[u8]>>,
+}
+
+impl Groups {
+    pub fn load(path: &str, unmapped: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+        let mut groups = Map::new();
+        for (line_number, line) in contents.split(|&b| b == b'\n').enumerate() {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
+            let Some(semicolon) = line.iter().position(|&b| b == b';') else {
+                return Err(format!(
+                    "{path}:{}: expected `station;group`, found {:?}",
+                    line_number + 1,
+                    String::from_utf8_lossy(line)
+                )
+                .into());
+            };
+            groups.insert(line[..semicolon].into(), line[semicolon + 1..].into());
+        }
+        let unmapped = unmapped.map(|group| group.as_bytes().into());
+        Ok(Self { groups, unmapped })
+    }
+
+    /// Folds every station's record into the record of its group. Counts and sums add up and
+    /// min/max are taken across members, so the mean comes from the combined sum and count.
+    pub fn apply(&self, map: Stations) -> Stations {
+        let mut grouped = Stations::new();
+        for (station, rec) in map {
+            let Some(group) = self.groups.get(&station).or(self.unmapped.as_ref()) else {
+                continue;
+            };
+            match grouped.entry_ref(&**group) {
+                EntryRef::Occupied(mut existing) => existing.get_mut().merge(&rec),
+                EntryRef::Vacant(slot) => {
+                    slot.insert(rec);
+                }
+            }
+        }
+        grouped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::TempFile, MeasurementRecord};
+
+    fn groups(unmapped: Option<&str>) -> Groups {
+        let file = TempFile::new(&format!("groups-{}", unmapped.is_some()));
+        fs::write(&file.0, "Oslo;north\r\nTromsø;north\n\n").unwrap();
+        Groups::load(file.path(), unmapped).unwrap()
+    }
+
+    fn readings(readings: &[(&str, i16)]) -> Stations {
+        let mut map = Stations::new();
+        for &(station, value) in readings {
+            let rec = MeasurementRecord::first(value, 0);
+            match map.entry_ref(station.as_bytes()) {
+                EntryRef::Occupied(mut existing) => existing.get_mut().merge(&rec),
+                EntryRef::Vacant(slot) => {
+                    slot.insert(rec);
+                }
+            }
+        }
+        map
+    }
+
+    #[test]
+    fn two_stations_in_a_group() {
+        let map = readings(&[("Oslo", 10), ("Oslo", 30), ("Tromsø", -50), ("Rome", 200)]);
+        let grouped = groups(Some("other")).apply(map.clone());
+        assert_eq!(grouped.len(), 2);
+        let north = &grouped[&b"north"[..]];
+        assert_eq!(
+            (north.count, north.sum, north.min, north.max),
+            (3, -10, -50, 30)
+        );
+        // The mean is the one of all readings, not the mean of the two means
+        assert_eq!(north.mean(), -3);
+        let other = &grouped[&b"other"[..]];
+        assert_eq!((other.count, other.min, other.max), (1, 200, 200));
+
+        // Dropped without a group for them
+        let grouped = groups(None).apply(map);
+        let names: Vec<&[u8]> = grouped.keys().map(|name| &name[..]).collect();
+        assert_eq!(names, [b"north"]);
+    }
+}
@@ -0,0 +1,114 @@
+//! Per-station temperature histograms for `--histogram`.
+//!
+//! The valid range -99.9..=99.9 is split into `buckets` equal parts, a temperature of `t` tenths
+//! lands in bucket `(t + 999) * buckets / 1999`, so a value on an edge always goes to the bucket
+//! above it. Counts are u32, every station costs `4 * buckets` bytes per worker on top of its
+//! record, e.g. 4 MB per worker for 10,000 stations and 100 buckets.
+
+/// Temperatures in tenths that a histogram covers
+const RANGE: std::ops::RangeInclusive<i16> = -999..=999;
+const VALUES: usize = 1999;
+
+/// The most buckets that make sense, one per value
+pub const MAX_BUCKETS: usize = VALUES;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram(Box<[u32]>);
+
+impl Histogram {
+    pub fn new(buckets: usize) -> Self {
+        debug_assert!((1..=MAX_BUCKETS).contains(&buckets));
+        Self(vec![0; buckets].into_boxed_slice())
+    }
+
+    #[inline(always)]
+    pub fn add(&mut self, value: i16) {
+        debug_assert!(RANGE.contains(&value));
+        let bucket = (value - RANGE.start()) as usize * self.0.len() / VALUES;
+        self.0[bucket] += 1;
+    }
+
+    /// Adds `other`'s counts element-wise, both must have the same number of buckets
+    pub fn merge(&mut self, other: &Self) {
+        debug_assert_eq!(self.0.len(), other.0.len());
+        for (count, other) in self.0.iter_mut().zip(&other.0) {
+            *count += other;
+        }
+    }
+
+    pub fn counts(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aggregate, AggregateOptions};
+
+    fn bucket(buckets: usize, value: i16) -> usize {
+        let mut histogram = Histogram::new(buckets);
+        histogram.add(value);
+        histogram
+            .counts()
+            .iter()
+            .position(|&count| count == 1)
+            .unwrap()
+    }
+
+    #[test]
+    fn bucket_totals_match_counts() {
+        let mut input = String::new();
+        for i in 0..3000i32 {
+            let value = (i * 7919 % 1999) - 999;
+            let sign = if value < 0 { "-" } else { "" };
+            let (whole, tenths) = (value.abs() / 10, value.abs() % 10);
+            input += &format!("s{};{sign}{whole}.{tenths}\n", i % 7);
+        }
+        let mut first = None;
+        for (threads, chunk_size) in [(1, 1 << 20), (3, 64), (4, 1000)] {
+            let options = AggregateOptions {
+                threads,
+                chunk_size: Some(chunk_size),
+                strict: true,
+                histogram: 10,
+                ..Default::default()
+            };
+            let stations = aggregate(input.as_bytes(), &options).unwrap();
+            assert_eq!(stations.len(), 7);
+            for rec in stations.values() {
+                let counts = rec.histogram.as_ref().unwrap().counts();
+                assert_eq!(counts.iter().sum::<u32>() as usize, rec.count);
+            }
+            // However the input was split, the histograms come out the same
+            assert_eq!(first.get_or_insert_with(|| stations.clone()), &stations);
+        }
+    }
+
+    #[test]
+    fn edge_values_go_to_the_bucket_above() {
+        for buckets in [1, 2, 3, 10, 100, 1000, MAX_BUCKETS] {
+            assert_eq!(bucket(buckets, -999), 0);
+            assert_eq!(bucket(buckets, 999), buckets - 1);
+            let mut last = 0;
+            for value in RANGE {
+                let got = bucket(buckets, value);
+                // Buckets only ever step up by one, and the lowest value of each is the first one
+                // at or above its lower edge of `k * 1999 / buckets` tenths above -99.9
+                assert!(got == last || got == last + 1, "{buckets} buckets, {value}");
+                if got == last + 1 {
+                    let above = (value + 999) as usize * buckets;
+                    assert!(above >= got * VALUES && above - buckets < got * VALUES);
+                }
+                last = got;
+            }
+            assert_eq!(last, buckets - 1);
+        }
+        // With a bucket per value every value is an edge and starts its own bucket
+        for value in RANGE {
+            assert_eq!(bucket(MAX_BUCKETS, value), (value + 999) as usize);
+        }
+        // 0.0 is below the middle of two buckets, 0.1 above it
+        assert_eq!((bucket(2, 0), bucket(2, 1)), (0, 1));
+    }
+}
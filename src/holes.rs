@@ -0,0 +1,155 @@
+use std::{fs::File, io, ops::Range};
+
+/// A region of a sparse input that isn't backed by data and reads as zeros
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hole {
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Finds the filesystem holes in the first `len` bytes of `file` with `SEEK_HOLE`/`SEEK_DATA`.
+///
+/// Offsets are file offsets, so they stay exact for inputs larger than the address space.
+///
+/// Returns no holes on platforms or filesystems without support for the query.
+#[cfg(target_os = "linux")]
+pub fn find_holes(file: &File, len: u64) -> io::Result<Vec<Hole>> {
+    use std::os::fd::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let seek = |offset: u64, whence| -> io::Result<Option<u64>> {
+        // SAFETY: lseek only moves the file position, which the mmap doesn't care about
+        match unsafe { libc::lseek(fd, offset as libc::off_t, whence) } {
+            -1 => match io::Error::last_os_error() {
+                // No more data (or holes) past offset
+                err if err.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+                err => Err(err),
+            },
+            found => Ok(Some(found as u64)),
+        }
+    };
+    let mut holes = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let hole = match seek(offset, libc::SEEK_HOLE) {
+            Ok(Some(hole)) if hole < len => hole,
+            Ok(_) => break,
+            // Filesystems without hole support report EINVAL
+            Err(err) if err.raw_os_error() == Some(libc::EINVAL) => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let data = seek(hole, libc::SEEK_DATA)?.map_or(len, |data| data.min(len));
+        holes.push(Hole {
+            offset: hole,
+            len: data - hole,
+        });
+        offset = data;
+    }
+    Ok(holes)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn find_holes(_: &File, _: u64) -> io::Result<Vec<Hole>> {
+    Ok(Vec::new())
+}
+
+/// The data regions of `data`, which starts at file offset `offset`, between the `holes` that
+/// overlap it, with the zero padding that filesystems leave around the edges of a hole trimmed off
+pub fn data_segments(data: &[u8], offset: u64, holes: &[Hole]) -> Vec<Range<usize>> {
+    let end = offset + data.len() as u64;
+    // Window relative (start, end) of every overlapping hole, then an empty one at the end
+    let overlapping = holes
+        .iter()
+        .filter(|hole| hole.offset < end && hole.offset + hole.len > offset)
+        .map(|hole| {
+            let start = hole.offset.max(offset) - offset;
+            let end = (hole.offset + hole.len).min(end) - offset;
+            (start as usize, end as usize)
+        })
+        .chain([(data.len(), data.len())]);
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for (hole_start, hole_end) in overlapping {
+        let segment = &data[start..hole_start];
+        let leading = segment.iter().take_while(|&&b| b == 0).count();
+        let trailing = segment[leading..]
+            .iter()
+            .rev()
+            .take_while(|&&b| b == 0)
+            .count();
+        if leading + trailing < segment.len() {
+            segments.push(start + leading..hole_start - trailing);
+        }
+        start = hole_end;
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom, Write};
+
+    use super::*;
+    use crate::{aggregate, merge_maps, tests::TempFile, AggregateOptions, Stations};
+
+    #[test]
+    fn sparse_file() {
+        const SECOND: u64 = 1 << 20;
+        let (before, after) = (b"Oslo;1.0\nRome;20.5\n", b"Oslo;-3.0\nLima;15.0\n");
+        let path = TempFile::new("sparse");
+        let mut file = File::create(&path.0).unwrap();
+        file.write_all(before).unwrap();
+        // Leaves a hole up to the second data region
+        file.set_len(SECOND).unwrap();
+        file.seek(SeekFrom::Start(SECOND)).unwrap();
+        file.write_all(after).unwrap();
+        drop(file);
+
+        let file = File::open(&path.0).unwrap();
+        let len = file.metadata().unwrap().len();
+        let holes = find_holes(&file, len).unwrap();
+        if holes.is_empty() {
+            eprintln!("skipped, the temporary directory doesn't report holes");
+            return;
+        }
+        // The hole starts at a block boundary, the rest of the first block is zero padding
+        assert_eq!(holes.len(), 1);
+        let hole = holes[0];
+        assert!(hole.offset >= before.len() as u64);
+        assert_eq!(hole.offset + hole.len, SECOND);
+
+        let data = std::fs::read(&path.0).unwrap();
+        let segments = data_segments(&data, 0, &holes);
+        let second = SECOND as usize;
+        assert_eq!(segments, [0..before.len(), second..second + after.len()]);
+        // Windows that start or end inside the hole only see their own side of it
+        let segments_of = |start: usize, end: usize| {
+            let segments = data_segments(&data[start..end], start as u64, &holes);
+            segments
+                .into_iter()
+                .map(|segment| (segment.start, segment.end))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(segments_of(0, second), [(0, before.len())]);
+        assert_eq!(
+            segments_of(4096, data.len()),
+            [(second - 4096, data.len() - 4096)]
+        );
+
+        let mut stations = Stations::new();
+        for segment in segments {
+            merge_maps(
+                &mut stations,
+                aggregate(&data[segment], &AggregateOptions::default()).unwrap(),
+            );
+        }
+        let summary = |name: &[u8]| {
+            let rec = &stations[name];
+            (rec.count, rec.sum, rec.min, rec.max)
+        };
+        assert_eq!(stations.len(), 3);
+        assert_eq!(summary(b"Oslo"), (2, -20, -30, 10));
+        assert_eq!(summary(b"Rome"), (1, 205, 205, 205));
+        assert_eq!(summary(b"Lima"), (1, 150, 150, 150));
+    }
+}
@@ -0,0 +1,29 @@
+use rs::Interrupt;
+
+/// Requested by the first Ctrl-C
+pub static INTERRUPT: Interrupt = Interrupt::new();
+
+/// Exit status of a run that was interrupted, like a shell reports for SIGINT
+pub const EXIT_CODE: i32 = 130;
+
+/// Makes the first Ctrl-C end the run with whatever was aggregated so far instead of killing
+/// the process, a second one exits right away
+#[cfg(target_os = "linux")]
+pub fn catch() {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        if INTERRUPT.request() {
+            // SAFETY: _exit is async-signal-safe
+            unsafe { libc::_exit(EXIT_CODE) };
+        }
+    }
+    // SAFETY: the handler only touches atomics and calls _exit, both async-signal-safe
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        )
+    };
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn catch() {}
@@ -0,0 +1,2247 @@
+use std::{
+    alloc::{alloc, dealloc, Layout},
+    cell::{Cell, RefCell},
+    collections::hash_map::RandomState,
+    error::Error,
+    fmt,
+    hash::{BuildHasher, Hash, Hasher},
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Barrier, Mutex,
+    },
+    time::Instant,
+};
+
+use hashbrown::{hash_map::Entry, HashMap};
+
+pub mod backend;
+pub mod checked;
+pub mod cpus;
+pub mod estimate;
+pub mod ffi;
+pub mod groups;
+pub mod histogram;
+pub mod holes;
+pub mod limits;
+pub mod names;
+pub mod numa;
+pub mod sample;
+pub mod schedule;
+pub mod seed;
+pub mod shadow;
+#[cfg(target_arch = "x86_64")]
+mod simd;
+pub mod state;
+pub mod stats;
+pub mod table;
+pub mod timing;
+
+use backend::Backend;
+use checked::{Checked, ParseError};
+use histogram::Histogram;
+use limits::{LimitExceeded, Limits};
+use names::{Folder, NameFold};
+use numa::{Affinity, Topology};
+use sample::ChunkSample;
+use schedule::{Claim, Pass, Replay, Schedule, ScheduleMismatch};
+use seed::{StationList, Unknown, UnknownStation, Unknowns};
+use shadow::{ShadowCheck, ShadowMismatch};
+use stats::{ArenaUsage, WorkerStats};
+use table::{FlatKind, HashbrownKind, StationTable, Table, TableKind};
+
+const BUMP_CAP: usize = 1024 * 1024;
+const _: () = assert!(BUMP_CAP > 1024);
+// const SINGLE_BUMP_MAX: usize = 1024;
+
+const MIN_WORK_CHUNK: usize = 64 * 1024;
+const MAX_WORK_CHUNK: usize = 8 * 1024 * 1024;
+
+/// Workers of `aggregate` per merger, more workers than that get a merger thread per as many of
+/// them
+const WORKERS_PER_MERGER: usize = 8;
+
+/// Stations a worker's map holds, besides those of `--stations-file`, before it is handed to the
+/// mergers and the worker starts over with an empty one
+pub const FLUSH_STATIONS: usize = 64 * 1024;
+
+/// Picks a chunk size that gives every thread plenty of chunks to claim on small files without
+/// making the claims needlessly frequent on big ones. Files too small for a 64 KiB chunk per
+/// thread get smaller chunks, at least one per thread if there is a byte for each. 0 threads count
+/// as 1.
+pub fn auto_chunk_size(file_len: usize, threads: usize) -> usize {
+    let threads = threads.max(1);
+    let min = MIN_WORK_CHUNK.min(file_len / threads).max(1);
+    (file_len / (threads * 16)).clamp(min, MAX_WORK_CHUNK)
+}
+
+pub type Map<K, V> = HashMap<K, V>;
+
+/// Merged per-station aggregates, keyed by station name
+pub type Stations = Map<Box<[u8]>, MeasurementRecord>;
+
+/// Aggregate of one station, temperatures are in tenths of a degree
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MeasurementRecord {
+    pub count: usize,
+    pub sum: i64,
+    pub min: i16,
+    pub max: i16,
+    /// Only tracked with `--histogram`, boxed so the record stays small without it
+    pub histogram: Option<Histogram>,
+}
+
+/// Arena for station names, the names it hands out live as long as the arena
+pub struct BumpAlloc {
+    len: Cell<usize>,
+    ptr: Cell<*mut u8>,
+    /// Every chunk allocated so far, freed on drop
+    chunks: RefCell<Vec<*mut u8>>,
+    /// Bytes left unused at the end of every chunk but the current one
+    wasted: Cell<usize>,
+    /// Slices that don't fit in a chunk, each allocated on its own
+    large: RefCell<Vec<Box<[u8]>>>,
+}
+
+impl BumpAlloc {
+    #[inline]
+    pub fn new() -> Self {
+        let ptr = new_chunk();
+        Self {
+            len: Cell::new(0),
+            ptr: Cell::new(ptr),
+            chunks: RefCell::new(vec![ptr]),
+            wasted: Cell::new(0),
+            large: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// How much of the arena is in use
+    pub fn usage(&self) -> ArenaUsage {
+        let chunks = self.chunks.borrow().len();
+        let capacity = chunks * BUMP_CAP;
+        ArenaUsage {
+            chunks,
+            capacity,
+            used: capacity - self.wasted.get() - (BUMP_CAP - self.len.get()),
+        }
+    }
+    #[inline]
+    fn alloc(&self, len: usize) -> *mut u8 {
+        unsafe {
+            // // SAFETY: Technically required for the case where the length wouldn't fit in the
+            // // backing buffer, but we know all stations will be under 100 bytes
+            //
+            // if len > SINGLE_BUMP_MAX {
+            //     let ptr = alloc(Layout::array::<u8>(len).unwrap());
+            //     return std::slice::from_raw_parts_mut(ptr as *mut MaybeUninit<u8>, len);
+            // }
+            if (self.len.get() + len) > BUMP_CAP {
+                self.wasted
+                    .set(self.wasted.get() + BUMP_CAP - self.len.get());
+                self.ptr.set(new_chunk());
+                self.len.set(0);
+                self.chunks.borrow_mut().push(self.ptr.get());
+            }
+            let ptr = self.ptr.get().add(self.len.get());
+            self.len.set(self.len.get() + len);
+            ptr
+        }
+    }
+    pub(crate) fn alloc_slice(&self, slice: &[u8]) -> &[u8] {
+        if slice.len() > BUMP_CAP {
+            // Only the checked parser reads names this long
+            let mut large = self.large.borrow_mut();
+            large.push(slice.into());
+            let ptr = large.last().unwrap().as_ptr();
+            // SAFETY: the box's contents don't move when `large` grows, and it lives until the
+            // arena is dropped
+            return unsafe { std::slice::from_raw_parts(ptr, slice.len()) };
+        }
+        unsafe {
+            // Every allocation is a fresh range of a chunk that lives until the arena is dropped
+            let bytes = self.alloc(slice.len());
+            bytes.copy_from_nonoverlapping(slice.as_ptr(), slice.len());
+            std::slice::from_raw_parts(bytes, slice.len())
+        }
+    }
+}
+
+impl Default for BumpAlloc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for BumpAlloc {
+    fn drop(&mut self) {
+        for &chunk in self.chunks.get_mut().iter() {
+            unsafe { dealloc(chunk, Layout::array::<u8>(BUMP_CAP).unwrap_unchecked()) };
+        }
+    }
+}
+
+fn new_chunk() -> *mut u8 {
+    unsafe {
+        let ptr = alloc(Layout::array::<u8>(BUMP_CAP).unwrap_unchecked());
+        if ptr.is_null() {
+            std::alloc::handle_alloc_error(Layout::array::<u8>(BUMP_CAP).unwrap_unchecked())
+        };
+        ptr
+    }
+}
+
+/// Decodes a `[-]d{1,2}.d` temperature into tenths, given the bytes before the dot and the digit
+/// after it. The input is trusted, the shape is only checked in debug builds.
+///
+/// # Safety
+/// `before_dot` must be 1 to 3 bytes long, garbage digits only give a garbage value.
+#[inline(always)]
+pub unsafe fn parse_value(before_dot: &[u8], after_dot: u8) -> i16 {
+    match before_dot.len() {
+        1 => before_dot[0].wrapping_sub(b'0') as i16 * 10 + after_dot.wrapping_sub(b'0') as i16,
+        2 => {
+            if before_dot[0] == b'-' {
+                -(before_dot[1].wrapping_sub(b'0') as i16) * 10
+                    - after_dot.wrapping_sub(b'0') as i16
+            } else {
+                (before_dot[0].wrapping_sub(b'0') as i16 * 100)
+                    + (before_dot[1].wrapping_sub(b'0') as i16 * 10)
+                    + after_dot.wrapping_sub(b'0') as i16
+            }
+        }
+        3 => {
+            -(before_dot[1].wrapping_sub(b'0') as i16 * 100
+                + before_dot[2].wrapping_sub(b'0') as i16 * 10
+                + after_dot.wrapping_sub(b'0') as i16)
+        }
+        _ => {
+            #[cfg(debug_assertions)]
+            unreachable!();
+            #[cfg(not(debug_assertions))]
+            unsafe {
+                std::hint::unreachable_unchecked()
+            };
+        }
+    }
+}
+
+/// Decodes the `[-]d{1,2}.d` temperature at the start of `word`, the 8 input bytes from where the
+/// value starts read as a little-endian integer, without branching on its length or sign.
+/// Returns the value in tenths and its length, or `None` if it has no `RADIX` and is in whole
+/// degrees. The bytes after the value are ignored.
+///
+/// Digits have bit 4 set, and both radixes, `-` and `\n` don't. The first byte of the value is
+/// a sign or a digit, so the radix is the first byte after it without that bit.
+#[inline(always)]
+pub fn parse_value_swar<const RADIX: u8>(word: u64) -> Option<(i16, usize)> {
+    let radix = (!word & 0x1010_1000).trailing_zeros();
+    // Also rules out words without such a byte, the top nibble is never a radix
+    if (word >> (radix - 4)) as u8 != RADIX {
+        return None;
+    }
+    // All ones if the first byte is `-`
+    let negative = ((!word << 59) as i64 >> 63) as u64;
+    // Lines the digits up as tens, ones and tenths in bytes 1, 2 and 4, with a missing tens
+    // digit or the sign cleared to 0, then sums them up with their weights in one multiply
+    let digits = ((word & !(negative & 0xFF)) << (28 - radix)) & 0x0F_000F_0F00;
+    let abs = (digits.wrapping_mul(0x640A_0001) >> 32) & 0x3FF;
+    Some((
+        (abs ^ negative).wrapping_sub(negative) as i16,
+        radix as usize / 8 + 2,
+    ))
+}
+
+/// Decodes a `[-]d{1,2}` temperature in whole degrees into tenths. The input is trusted, the
+/// shape is only checked in debug builds.
+///
+/// # Safety
+/// `digits` must be 1 or 2 bytes long, or 3 starting with `-`, garbage digits only give a garbage
+/// value.
+#[inline(always)]
+pub unsafe fn parse_whole(digits: &[u8]) -> i16 {
+    let digit = |b: u8| b.wrapping_sub(b'0') as i16;
+    match digits {
+        [b'-', ones] => -digit(*ones) * 10,
+        [b'-', tens, ones] => -(digit(*tens) * 100 + digit(*ones) * 10),
+        [ones] => digit(*ones) * 10,
+        [tens, ones] => digit(*tens) * 100 + digit(*ones) * 10,
+        _ => {
+            #[cfg(debug_assertions)]
+            unreachable!();
+            #[cfg(not(debug_assertions))]
+            unsafe {
+                std::hint::unreachable_unchecked()
+            };
+        }
+    }
+}
+
+/// Names up to this long are stored inline in the map instead of in the arena
+pub const INLINE_KEY_LEN: usize = 16;
+
+/// A station name as a map key. Short names are kept inline so they never touch the arena and
+/// compare with a couple of word compares, longer ones point into the arena.
+///
+/// A name is inline if and only if it fits, so the two variants never hold the same name.
+#[derive(Debug, Clone, Copy)]
+pub enum StationKey<'a> {
+    /// The name zero padded to `INLINE_KEY_LEN` bytes
+    Inline {
+        len: u8,
+        bytes: [u8; INLINE_KEY_LEN],
+    },
+    Arena(&'a [u8]),
+}
+
+impl StationKey<'_> {
+    /// The inline key of `station`, `None` if it is too long to be inlined
+    #[inline(always)]
+    pub fn inline(station: &[u8]) -> Option<Self> {
+        if station.len() > INLINE_KEY_LEN {
+            return None;
+        }
+        Some(Self::Inline {
+            len: station.len() as u8,
+            bytes: load_padded(station),
+        })
+    }
+
+    #[inline(always)]
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Inline { len, bytes } => &bytes[..*len as usize],
+            Self::Arena(station) => station,
+        }
+    }
+}
+
+impl PartialEq for StationKey<'_> {
+    #[inline(always)]
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::Inline { len, bytes },
+                Self::Inline {
+                    len: other_len,
+                    bytes: other_bytes,
+                },
+            ) => len == other_len && bytes == other_bytes,
+            _ => self.as_bytes() == other.as_bytes(),
+        }
+    }
+}
+
+impl Eq for StationKey<'_> {}
+
+impl Hash for StationKey<'_> {
+    #[inline(always)]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            // Inline names hash as two words, which variant a name is in only depends on its
+            // length so equal names still hash the same
+            Self::Inline { len, bytes } => {
+                state.write_u8(*len);
+                state.write_u128(u128::from_ne_bytes(*bytes));
+            }
+            Self::Arena(station) => station.hash(state),
+        }
+    }
+}
+
+/// Copies a name of at most `INLINE_KEY_LEN` bytes into a zero padded array with two overlapping
+/// loads instead of a variable length copy
+#[inline(always)]
+fn load_padded(station: &[u8]) -> [u8; INLINE_KEY_LEN] {
+    let len = station.len();
+    debug_assert!(len <= INLINE_KEY_LEN);
+    let padded = if len >= 8 {
+        let lo = u64::from_le_bytes(station[..8].try_into().unwrap());
+        let hi = u64::from_le_bytes(station[len - 8..].try_into().unwrap());
+        // Drop the bytes of `hi` that `lo` already covers
+        lo as u128 | ((hi as u128) >> ((16 - len) * 8) << 64)
+    } else if len >= 4 {
+        let lo = u32::from_le_bytes(station[..4].try_into().unwrap());
+        let hi = u32::from_le_bytes(station[len - 4..].try_into().unwrap());
+        (lo as u128) | ((hi as u128) >> ((8 - len) * 8) << 32)
+    } else {
+        station
+            .iter()
+            .rev()
+            .fold(0, |padded, &b| padded << 8 | b as u128)
+    };
+    padded.to_le_bytes()
+}
+
+impl PartialOrd for StationKey<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for StationKey<'_> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+/// Adds one reading to `map`, copying the station name into `bump` the first time it is seen
+/// unless it is short enough to be stored inline. New stations get a histogram of `histogram`
+/// buckets, or none if it is 0.
+#[inline(always)]
+pub fn handle_entry<'a>(
+    map: &mut Map<StationKey<'a>, MeasurementRecord>,
+    bump: &'a BumpAlloc,
+    station: &[u8],
+    value: i16,
+    histogram: usize,
+) {
+    // _ = unsafe { dbg!(std::str::from_utf8_unchecked(station), value) };
+    let inline = StationKey::inline(station);
+    let hash = match &inline {
+        Some(inline) => map.hasher().hash_one(inline),
+        None => map.hasher().hash_one(station),
+    };
+    map.raw_entry_mut()
+        .from_hash(hash, |key| match &inline {
+            Some(inline) => key == inline,
+            None => key.as_bytes() == station,
+        })
+        .and_modify(|_, rec| rec.add(value))
+        .or_insert_with(|| {
+            (
+                inline.unwrap_or_else(|| StationKey::Arena(bump.alloc_slice(station))),
+                MeasurementRecord::first(value, histogram),
+            )
+        });
+}
+
+/// Adds a reading to `map`, or to `unknowns` if it is set and the station isn't in `map`
+#[inline(always)]
+fn add_reading<'a>(
+    map: &mut impl StationTable<'a>,
+    bump: &'a BumpAlloc,
+    unknowns: Option<&mut Unknowns>,
+    station: &[u8],
+    value: i16,
+    histogram: usize,
+) {
+    match unknowns {
+        None => map.upsert(bump, station, value, histogram),
+        Some(unknowns) => {
+            if !map.update(station, value) {
+                unknowns.add(station);
+            }
+        }
+    }
+}
+
+/// Parses every record that starts in `start..end`, extending past `end` to finish the last one.
+/// The record that `start` falls into belongs to the previous chunk and is skipped.
+///
+/// `DELIMITER` separates the station from the temperature, the first one in a record wins so
+/// station names can't contain it. `RADIX` is the byte between the integer and fractional digits
+/// of a temperature, `b'.'` or `b','` for decimal-comma inputs.
+///
+/// Temperatures without a fractional part are read as whole degrees. With `checked` set, every
+/// record is validated by the checked parser under those rules instead.
+/// `histogram` is the number of histogram buckets per station, 0 for none.
+/// With `unknowns` set, readings of stations that aren't in `map` yet are counted there instead
+/// of being added. With `folder` set, names are trimmed and case folded before they are looked up.
+///
+/// # Safety
+/// `avx2` may only be set if [`avx2_available`]. Unless `checked` is set, the records parsed must
+/// be well-formed: a station without `DELIMITER` or `\n`, `DELIMITER`, then a `[-]d{1,2}` or
+/// `[-]d{1,2}RADIXd` temperature, each record followed by a `\n` except the last one of `data`.
+/// Blank lines are allowed.
+#[inline(always)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn process_chunk<'a, const DELIMITER: u8, const RADIX: u8>(
+    data: &[u8],
+    mut start: usize,
+    end: usize,
+    map: &mut impl StationTable<'a>,
+    bump: &'a BumpAlloc,
+    avx2: bool,
+    checked: Option<Checked>,
+    mut unknowns: Option<&mut Unknowns>,
+    mut folder: Option<&mut Folder>,
+    histogram: usize,
+) -> Result<(), ParseError> {
+    if start != 0 {
+        let Some((first_newline, _)) = data
+            .iter()
+            .enumerate()
+            .take(end)
+            .skip(start)
+            .find(|(_, &b)| b == b'\n')
+        else {
+            return Ok(());
+        };
+        // the +1 is necessary to skip the first newline
+        start = first_newline + 1;
+    }
+    // The newline that ended the previous chunk was the last byte of the file
+    if start == data.len() {
+        return Ok(());
+    }
+    let end = data
+        .iter()
+        .enumerate()
+        .skip(end)
+        .find(|(_, &b)| b == b'\n')
+        .map(|(end, _)| end)
+        .unwrap_or(data.len());
+
+    let mut data = &data[start..end];
+
+    // _ = unsafe { dbg!(thread, std::str::from_utf8_unchecked(data)) };
+
+    let mut handle_entry = |station: &[u8], value: i16| match folder.as_deref_mut() {
+        None => add_reading(
+            map,
+            bump,
+            unknowns.as_deref_mut(),
+            station,
+            value,
+            histogram,
+        ),
+        // Only folded names need to know whether the station is new
+        Some(folder) => folder.add(station, |station| {
+            let len = map.len();
+            add_reading(
+                map,
+                bump,
+                unknowns.as_deref_mut(),
+                station,
+                value,
+                histogram,
+            );
+            map.len() > len
+        }),
+    };
+    if let Some(checked) = checked {
+        return checked::parse_records(data, start, DELIMITER, RADIX, checked, handle_entry);
+    }
+    #[cfg(target_arch = "x86_64")]
+    if avx2 {
+        // SAFETY: avx2 is only set when the CPU supports it, the values are well-formed
+        let consumed = unsafe {
+            simd::scan_records::<DELIMITER>(data, |station, value, word| {
+                // Decoded as a word like in the scalar loop, unless the chunk ends too early or
+                // the value is in whole degrees
+                let value = match word.and_then(parse_value_swar::<RADIX>) {
+                    Some((tenths, _len)) => {
+                        debug_assert_eq!(_len, value.len());
+                        tenths
+                    }
+                    None => match value {
+                        [.., radix, after_dot] if *radix == RADIX => {
+                            parse_value(&value[..value.len() - 2], *after_dot)
+                        }
+                        _ => parse_whole(value),
+                    },
+                };
+                handle_entry(station, value);
+            })
+        };
+        data = &data[consumed..];
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = avx2;
+    while !data.is_empty() {
+        // Skip blank lines
+        if data[0] == b'\n' {
+            data = &data[1..];
+            continue;
+        }
+        // Hamburg;12.0...
+        let delimiter = data.iter().position(|&b| b == DELIMITER);
+        #[cfg(debug_assertions)]
+        let delimiter = delimiter.unwrap();
+        #[cfg(not(debug_assertions))]
+        let delimiter = unsafe { delimiter.unwrap_unchecked() };
+
+        #[cfg(debug_assertions)]
+        let station = &data[..delimiter];
+        #[cfg(not(debug_assertions))]
+        let station = unsafe { data.get_unchecked(..delimiter) };
+        #[cfg(debug_assertions)]
+        let rem = &data[delimiter + 1..];
+        #[cfg(not(debug_assertions))]
+        let rem = unsafe { data.get_unchecked(delimiter + 1..) };
+        data = rem;
+
+        // Everywhere but in the last few bytes of the chunk the value can be decoded as a word
+        if let Some(word) = data.first_chunk::<8>() {
+            if let Some((value, len)) = parse_value_swar::<RADIX>(u64::from_le_bytes(*word)) {
+                handle_entry(station, value);
+                // The value is followed by a newline within the word
+                #[cfg(debug_assertions)]
+                let rem = &data[len + 1..];
+                #[cfg(not(debug_assertions))]
+                let rem = unsafe { data.get_unchecked(len + 1..) };
+                data = rem;
+                continue;
+            }
+        }
+
+        // The value ends at the dot, or at the newline or the end of the data if it is in whole
+        // degrees
+        let Some(dot) = data.iter().position(|&b| b == RADIX || b == b'\n') else {
+            handle_entry(station, parse_whole(data));
+            break;
+        };
+
+        #[cfg(debug_assertions)]
+        let before_dot = &data[..dot];
+        #[cfg(not(debug_assertions))]
+        let before_dot = unsafe { data.get_unchecked(..dot) };
+        if data[dot] == b'\n' {
+            handle_entry(station, parse_whole(before_dot));
+            data = &data[dot + 1..];
+            continue;
+        }
+        #[cfg(debug_assertions)]
+        let after_dot = data[dot + 1];
+        #[cfg(not(debug_assertions))]
+        let after_dot = unsafe { *data.get_unchecked(dot + 1) };
+
+        let value = parse_value(before_dot, after_dot);
+
+        handle_entry(station, value);
+
+        // The record ends one digit after the dot, followed by a newline unless it is the
+        // last record of the file
+        let Some(remainder) = data.get(dot + 3..) else {
+            break;
+        };
+        data = remainder;
+    }
+    Ok(())
+}
+
+/// Whether `process_chunk` can use the AVX2 record scanner on this CPU
+pub fn avx2_available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    return is_x86_feature_detected!("avx2");
+    #[cfg(not(target_arch = "x86_64"))]
+    return false;
+}
+
+/// Why `aggregate` gave up on an input
+#[derive(Debug, Clone)]
+pub enum AggregateError {
+    Parse(ParseError),
+    Shadow(ShadowMismatch),
+    Schedule(ScheduleMismatch),
+    Limit(LimitExceeded),
+    Unknown(UnknownStation),
+}
+
+impl AggregateError {
+    /// Position of the error in the input
+    pub fn offset(&self) -> usize {
+        match self {
+            Self::Parse(err) => err.offset,
+            Self::Shadow(err) => err.chunk.start,
+            // Found before any chunk is parsed
+            Self::Schedule(_) => 0,
+            Self::Limit(err) => err.offset.unwrap_or(0),
+            Self::Unknown(err) => err.chunk_offset,
+        }
+    }
+
+    /// Moves every offset in the error by `by`, for inputs that are aggregated piece by piece
+    pub fn shifted(mut self, by: usize) -> Self {
+        match &mut self {
+            Self::Parse(err) => err.offset += by,
+            Self::Shadow(err) => {
+                err.chunk = err.chunk.start + by..err.chunk.end + by;
+                if let Some(err) = &mut err.checked {
+                    err.offset += by;
+                }
+            }
+            Self::Schedule(_) => {}
+            Self::Limit(err) => {
+                if let Some(offset) = &mut err.offset {
+                    *offset += by;
+                }
+            }
+            Self::Unknown(err) => err.chunk_offset += by,
+        }
+        self
+    }
+}
+
+impl fmt::Display for AggregateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(err) => err.fmt(f),
+            Self::Shadow(err) => err.fmt(f),
+            Self::Schedule(err) => err.fmt(f),
+            Self::Limit(err) => err.fmt(f),
+            Self::Unknown(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for AggregateError {}
+
+impl From<ParseError> for AggregateError {
+    fn from(err: ParseError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+/// Hands out chunks of the input to the workers
+pub struct WorkQueue<'a> {
+    /// Length of the whole input
+    len: usize,
+    chunk_size: usize,
+    /// What is left of the input is split into this many shares at most, see
+    /// [`WorkQueue::tapering`]
+    shares: Option<usize>,
+    /// Consecutive parts of the input, each claimed from front to back
+    regions: Vec<Region>,
+    /// The region each worker claims from before stealing from the others, region 0 if missing
+    homes: Vec<usize>,
+    /// Every claim so far in the order it was made, when recording the schedule
+    recorded: Option<Mutex<Vec<Claim>>>,
+    /// Claims are handed out in a recorded order instead of from the regions
+    replay: Option<Replay<'a>>,
+}
+
+struct Region {
+    cursor: AtomicUsize,
+    end: usize,
+}
+
+impl Region {
+    fn new(start: usize, end: usize) -> Self {
+        Self {
+            cursor: AtomicUsize::new(start),
+            end,
+        }
+    }
+
+    /// Claims the chunk at the cursor, `grant` gives its size from where it starts
+    fn claim(&self, grant: impl Fn(usize) -> usize) -> Option<Range<usize>> {
+        let mut offset = self.cursor.load(Ordering::Acquire);
+        loop {
+            if offset >= self.end {
+                return None;
+            }
+            let end = (offset + grant(offset)).min(self.end);
+            match self.cursor.compare_exchange_weak(
+                offset,
+                end,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(offset..end),
+                Err(cursor) => offset = cursor,
+            }
+        }
+    }
+}
+
+impl<'a> WorkQueue<'a> {
+    pub fn new(len: usize, chunk_size: usize) -> Self {
+        Self {
+            len,
+            chunk_size,
+            shares: None,
+            regions: vec![Region::new(0, len)],
+            homes: Vec::new(),
+            recorded: None,
+            replay: None,
+        }
+    }
+
+    /// Only hands out the chunks from `start` to `end` of an input of `len` bytes. With `start`
+    /// being the start of a chunk of a queue over the whole input, the chunks are the same as
+    /// its chunks.
+    pub fn range(len: usize, start: usize, end: usize, chunk_size: usize) -> Self {
+        Self {
+            regions: vec![Region::new(start, end)],
+            ..Self::new(len, chunk_size)
+        }
+    }
+
+    /// Shrinks the chunks as the end of the input nears, to what is left split into two shares
+    /// per thread but at least 64 KiB, so the workers finish within a small chunk of each other
+    /// instead of waiting on the one that claimed a big chunk last. The size of a chunk only
+    /// depends on where it starts, the chunks are the same whichever worker claims them.
+    pub fn tapering(mut self, threads: usize) -> Self {
+        self.shares = Some(threads.max(1) * 2);
+        self
+    }
+
+    /// Size of the chunk starting at `offset`
+    fn grant(&self, offset: usize) -> usize {
+        match self.shares {
+            Some(shares) => ((self.len - offset) / shares)
+                .clamp(MIN_WORK_CHUNK.min(self.chunk_size), self.chunk_size),
+            None => self.chunk_size,
+        }
+    }
+
+    /// Splits the input into a region per node, sized by the number of workers it has. `homes`
+    /// holds the node of every worker, in ascending order so a worker's region covers its share
+    /// of [`prefault`].
+    pub fn per_node(mut self, homes: Vec<usize>) -> Self {
+        debug_assert!(homes.is_sorted());
+        let len = self.regions.last().map_or(0, |region| region.end);
+        let nodes = homes.last().map_or(0, |&node| node + 1);
+        let workers = homes.len().max(1);
+        let mut before = 0;
+        self.regions = (0..nodes)
+            .map(|node| {
+                let start = len * before / workers;
+                before += homes.iter().filter(|&&home| home == node).count();
+                Region::new(start, len * before / workers)
+            })
+            .collect();
+        self.homes = homes;
+        self
+    }
+
+    /// Also logs every claim, see [`WorkQueue::into_recorded`]
+    pub fn recording(mut self) -> Self {
+        self.recorded = Some(Mutex::new(Vec::new()));
+        self
+    }
+
+    /// Hands out exactly the claims of `pass`, in the same order and to the same threads
+    pub fn replaying(mut self, pass: &'a Pass) -> Self {
+        self.replay = Some(Replay::new(pass));
+        self
+    }
+
+    /// Claims the next chunk for worker `thread`, `None` once there is nothing left for it. Takes
+    /// chunks from the worker's own region first and only steals from the others once it is
+    /// drained.
+    pub fn claim(&self, thread: usize) -> Option<Range<usize>> {
+        if let Some(replay) = &self.replay {
+            return replay.claim(thread);
+        }
+        // Claims are made under the lock so the log has them in the order the cursors moved
+        let mut recorded = self.recorded.as_ref().map(|log| log.lock().unwrap());
+        let home = self.homes.get(thread).copied().unwrap_or(0);
+        let chunk = (home..self.regions.len())
+            .chain(0..home)
+            .find_map(|region| self.regions[region].claim(|offset| self.grant(offset)))?;
+        if let Some(recorded) = &mut recorded {
+            recorded.push(Claim {
+                thread,
+                start: chunk.start,
+                end: chunk.end,
+            });
+        }
+        Some(chunk)
+    }
+
+    /// Stops the other workers from claiming any more chunks
+    pub fn stop(&self) {
+        for region in &self.regions {
+            region.cursor.store(region.end, Ordering::Release);
+        }
+        if let Some(replay) = &self.replay {
+            replay.stop();
+        }
+    }
+
+    /// The claims logged by a recording queue
+    pub fn into_recorded(self) -> Vec<Claim> {
+        self.recorded
+            .map(|log| log.into_inner().unwrap())
+            .unwrap_or_default()
+    }
+}
+
+/// Claims chunks from `queue` as worker `thread` until the input is exhausted and aggregates them
+/// into a map. Fails once the map holds more than `options.limits.max_stations` stations, or a
+/// chunk ends after `options.limits.max_run_time` is up.
+/// Chunks left out of `options.sample` are claimed but not parsed.
+///
+/// The map starts out with every station of `options.stations`. Shadow checked chunks add
+/// unknown stations whatever `options.stations.unknown` says.
+///
+/// Names live in the worker's own arena while parsing and are copied out into owned keys at the
+/// end, so the arena is freed as soon as the worker returns. With `flush` set, a map that found
+/// [`FLUSH_STATIONS`] stations is sent through it and the worker goes on with a fresh map and
+/// arena, so neither grows without bound on inputs with huge numbers of stations.
+///
+/// Chunks are only parsed with the fast parser if `trusted` is set and neither `options.strict`
+/// nor `options.quoted_names` is, the caller vouching that the records are well-formed. The
+/// checked parser reads all other chunks, skipping the lines it can't parse without `strict`.
+///
+/// The worker's counters are only gathered with `options.stats` set.
+pub(crate) fn work<const DELIMITER: u8, const RADIX: u8, T: TableKind>(
+    data: &[u8],
+    queue: &WorkQueue,
+    thread: usize,
+    options: &AggregateOptions,
+    trusted: bool,
+    flush: Option<&Mergers>,
+) -> Result<(Stations, Option<WorkerStats>), AggregateError> {
+    let avx2 = avx2_available();
+    let (strict, histogram) = (options.strict, options.histogram);
+    let seeds = options
+        .stations
+        .as_deref()
+        .map_or(&[][..], StationList::names);
+    let unknown = options
+        .stations
+        .as_ref()
+        .map_or(Unknown::Add, |stations| stations.unknown);
+    let mut unknowns = Unknowns::default();
+    let mut folder = options.names.as_deref().map(NameFold::folder);
+    let (mut chunks, mut bytes, mut rows) = (0, 0, 0);
+    let (mut arena, mut map_capacity) = (ArenaUsage::default(), 0);
+    // How long parsing every chunk took, in claim order
+    let mut chunk_times = Vec::new();
+    let started = Instant::now();
+    let map = loop {
+        let bump = BumpAlloc::new();
+        let mut map = T::Table::with_capacity(seeds.len().max(1024 * 8));
+        for station in seeds {
+            let Some(folder) = &mut folder else {
+                let key = StationKey::inline(station).unwrap_or(StationKey::Arena(station));
+                map.merge(key, MeasurementRecord::empty(histogram));
+                continue;
+            };
+            // Seeds are added before the first chunk, their spellings come first
+            folder.add(station, |station| {
+                let len = map.len();
+                let key = StationKey::inline(station)
+                    .unwrap_or_else(|| StationKey::Arena(bump.alloc_slice(station)));
+                map.merge(key, MeasurementRecord::empty(histogram));
+                map.len() > len
+            });
+        }
+        let exhausted = loop {
+            // Checked once per chunk, the chunk being parsed is always finished
+            if options.interrupt.is_some_and(Interrupt::is_requested) {
+                queue.stop();
+                break true;
+            }
+            let Some(Range { start: offset, end }) = queue.claim(thread) else {
+                break true;
+            };
+            if options.sample.is_some_and(|sample| !sample.samples(offset)) {
+                continue;
+            }
+            chunks += 1;
+            bytes += end - offset;
+            if let Some(folder) = &mut folder {
+                folder.start_chunk(offset);
+            }
+            let parsing = options.stats.is_some().then(Instant::now);
+            let result = if !strict
+                && options
+                    .shadow_check
+                    .is_some_and(|shadow| shadow.samples(offset))
+            {
+                shadow_chunk::<DELIMITER, RADIX>(
+                    data,
+                    offset..end,
+                    &mut map,
+                    &bump,
+                    avx2,
+                    folder.as_mut(),
+                    histogram,
+                )
+            } else {
+                // Quoted names are only understood by the checked parser, and without `strict` it
+                // reads records as leniently as the fast parser does
+                let checked = (strict || options.quoted_names || !trusted).then_some(Checked {
+                    max_station_len: match strict {
+                        true => options.limits.max_station_len,
+                        false => usize::MAX,
+                    },
+                    whole_degrees: !strict,
+                    quoted_names: options.quoted_names,
+                    skip_invalid: !strict,
+                });
+                // SAFETY: `avx2` was detected, the fast parser only reads trusted input
+                unsafe {
+                    process_chunk::<DELIMITER, RADIX>(
+                        data,
+                        offset,
+                        end,
+                        &mut map,
+                        &bump,
+                        avx2,
+                        checked,
+                        (unknown != Unknown::Add).then_some(&mut unknowns),
+                        folder.as_mut(),
+                        histogram,
+                    )
+                }
+                .map_err(AggregateError::from)
+            };
+            if let Some(parsing) = parsing {
+                chunk_times.push(parsing.elapsed());
+            }
+            let result = result.and_then(|()| match unknowns.first.take() {
+                Some(station) if unknown == Unknown::Error => {
+                    Err(AggregateError::Unknown(UnknownStation {
+                        station,
+                        chunk_offset: offset,
+                    }))
+                }
+                _ => Ok(()),
+            });
+            let result = result.and_then(|()| {
+                let limits = &options.limits;
+                limits
+                    .check_stations(map.len(), Some(offset))
+                    .and_then(|()| {
+                        limits.check_run_time(options.started.unwrap_or(started), Some(offset))
+                    })
+                    .map_err(AggregateError::Limit)
+            });
+            if let Err(err) = result {
+                queue.stop();
+                return Err(err);
+            }
+            if flush.is_some() && map.len() >= seeds.len() + FLUSH_STATIONS {
+                break false;
+            }
+        };
+        if options.stats.is_some() {
+            rows += map.records().map(|rec| rec.count).sum::<usize>();
+            arena.add(&bump.usage());
+            map_capacity = map_capacity.max(map.capacity());
+        }
+        let map: Stations = map
+            .into_iter()
+            .map(|(station, rec)| (station.as_bytes().into(), rec))
+            .collect();
+        match flush {
+            Some(flush) if !exhausted => flush.send(map),
+            _ => break map,
+        }
+    };
+    if let Some(interrupt) = options.interrupt {
+        interrupt.processed.fetch_add(bytes, Ordering::Relaxed);
+    }
+    if let Some(stations) = &options.stations {
+        stations.add_skipped(unknowns.readings);
+    }
+    if let Some(folder) = folder {
+        folder.finish();
+    }
+    let stats = options.stats.is_some().then(|| {
+        let first_chunk = chunk_times.first().copied().unwrap_or_default();
+        chunk_times.sort_unstable();
+        WorkerStats {
+            thread,
+            chunks,
+            bytes,
+            rows,
+            arena,
+            map_capacity,
+            busy: started.elapsed(),
+            first_chunk,
+            median_chunk: chunk_times
+                .get(chunk_times.len() / 2)
+                .copied()
+                .unwrap_or_default(),
+        }
+    });
+    Ok((map, stats))
+}
+
+/// Aggregates a chunk with both the checked and the fast parser and adds it to `map` if they agree
+fn shadow_chunk<'a, const DELIMITER: u8, const RADIX: u8>(
+    data: &[u8],
+    Range { start, end }: Range<usize>,
+    map: &mut impl StationTable<'a>,
+    bump: &'a BumpAlloc,
+    avx2: bool,
+    mut folder: Option<&mut Folder>,
+    histogram: usize,
+) -> Result<(), AggregateError> {
+    let mismatch = |checked| {
+        AggregateError::Shadow(ShadowMismatch {
+            chunk: start..end,
+            checked,
+        })
+    };
+    // The checked parser goes first, the fast one must not see input it would reject
+    let mut checked: Map<StationKey, MeasurementRecord> = Map::new();
+    // Only how the fast parser reads the input is checked, it accepts whole degrees
+    let rules = Some(Checked {
+        max_station_len: usize::MAX,
+        whole_degrees: true,
+        quoted_names: false,
+        skip_invalid: false,
+    });
+    // SAFETY: `avx2` was detected, the checked parser doesn't need well-formed input
+    unsafe {
+        process_chunk::<DELIMITER, RADIX>(
+            data,
+            start,
+            end,
+            &mut checked,
+            bump,
+            avx2,
+            rules,
+            None,
+            folder.as_deref_mut(),
+            histogram,
+        )
+    }
+    .map_err(|err| mismatch(Some(err)))?;
+    let mut fast: Map<StationKey, MeasurementRecord> = Map::with_capacity(checked.len());
+    // SAFETY: the checked parser accepted the chunk, so it is well-formed
+    unsafe {
+        process_chunk::<DELIMITER, RADIX>(
+            data, start, end, &mut fast, bump, avx2, None, None, folder, histogram,
+        )
+    }?;
+    ShadowMismatch::check(start..end, &fast, &checked).map_err(AggregateError::Shadow)?;
+    for (station, record) in fast {
+        map.merge(station, record);
+    }
+    Ok(())
+}
+
+impl MeasurementRecord {
+    /// The record of a station's first reading, with a histogram of `histogram` buckets unless
+    /// it is 0
+    #[inline(always)]
+    pub fn first(value: i16, histogram: usize) -> Self {
+        let histogram = (histogram != 0).then(|| {
+            let mut histogram = Histogram::new(histogram);
+            histogram.add(value);
+            histogram
+        });
+        Self {
+            count: 1,
+            sum: value as i64,
+            min: value,
+            max: value,
+            histogram,
+        }
+    }
+
+    /// The record of a station without any readings yet, which merges with others as if it
+    /// wasn't there
+    pub fn empty(histogram: usize) -> Self {
+        Self {
+            count: 0,
+            sum: 0,
+            min: i16::MAX,
+            max: i16::MIN,
+            histogram: (histogram != 0).then(|| Histogram::new(histogram)),
+        }
+    }
+
+    #[inline(always)]
+    pub fn add(&mut self, value: i16) {
+        self.count += 1;
+        self.sum += value as i64;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if let Some(histogram) = &mut self.histogram {
+            histogram.add(value);
+        }
+    }
+
+    /// The mean in tenths, rounded half away from zero
+    pub fn mean(&self) -> i64 {
+        self.mean_at(1)
+    }
+
+    /// The mean in units of `10^-decimals` degrees, rounded half away from zero. `decimals` is at
+    /// least 1, the precision of the readings.
+    pub fn mean_at(&self, decimals: u32) -> i64 {
+        debug_assert!(decimals >= 1);
+        let (sum, count) = (self.sum * 10i64.pow(decimals - 1), self.count as i64);
+        if sum ^ count >= 0 {
+            (sum + (count / 2)) / count
+        } else {
+            (sum - (count / 2)) / count
+        }
+    }
+    #[inline]
+    pub fn merge(&mut self, other: &Self) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        if let (Some(histogram), Some(other)) = (&mut self.histogram, &other.histogram) {
+            histogram.merge(other);
+        }
+    }
+}
+
+pub fn merge_maps<K: Hash + Eq>(
+    into: &mut Map<K, MeasurementRecord>,
+    from: Map<K, MeasurementRecord>,
+) {
+    from.into_iter()
+        .for_each(|(station, data)| match into.entry(station) {
+            Entry::Occupied(mut rec) => rec.get_mut().merge(&data),
+            Entry::Vacant(slot) => {
+                slot.insert(data);
+            }
+        })
+}
+
+/// The channels to the mergers of an `aggregate` call. Stations are sharded by the hash of their
+/// name, every merger only sees the stations of its own shard.
+#[derive(Clone)]
+pub(crate) struct Mergers {
+    senders: Vec<Sender<Stations>>,
+    hasher: RandomState,
+}
+
+impl Mergers {
+    /// Channels to `shards` mergers, and their receiving ends
+    fn new(shards: usize) -> (Self, Vec<Receiver<Stations>>) {
+        let (senders, receivers) = (0..shards).map(|_| mpsc::channel()).unzip();
+        let hasher = RandomState::new();
+        (Self { senders, hasher }, receivers)
+    }
+
+    /// Splits `map` by shard and sends every part to its merger
+    pub(crate) fn send(&self, map: Stations) {
+        // The mergers only hang up once every worker is done
+        if let [sender] = &self.senders[..] {
+            sender.send(map).unwrap();
+            return;
+        }
+        let shards = self.senders.len();
+        let mut parts: Vec<Stations> = (0..shards)
+            .map(|_| Stations::with_capacity(map.len() / shards))
+            .collect();
+        for (station, rec) in map {
+            parts[self.hasher.hash_one(&station) as usize % shards].insert(station, rec);
+        }
+        for (sender, part) in self.senders.iter().zip(parts) {
+            if !part.is_empty() {
+                sender.send(part).unwrap();
+            }
+        }
+    }
+}
+
+/// Merges the maps sent to one merger, until every worker hung up
+fn merge_shard(maps: Receiver<Stations>) -> Stations {
+    let mut merged = Stations::default();
+    for mut map in maps {
+        // Merge the smaller map into the larger one
+        if map.len() > merged.len() {
+            std::mem::swap(&mut map, &mut merged);
+        }
+        merge_maps(&mut merged, map);
+    }
+    merged
+}
+
+/// Touches one byte of every page in this worker's share of the mapping, so the page tables are
+/// populated by all workers concurrently instead of lazily by whichever one claims a chunk first
+pub fn prefault(data: &[u8], index: usize, threads: usize) {
+    const PAGE_SIZE: usize = 4096;
+    let share = data.len().div_ceil(threads);
+    let start = (share * index).min(data.len());
+    let end = (start + share).min(data.len());
+    for page in (start..end).step_by(PAGE_SIZE) {
+        // SAFETY: page is in bounds, the volatile read keeps the load from being optimized out
+        unsafe { std::ptr::read_volatile(data.as_ptr().add(page)) };
+    }
+}
+
+/// Ends `aggregate` early, e.g. from a signal handler. Workers stop claiming chunks once it is
+/// requested and return what they aggregated so far.
+#[derive(Debug, Default)]
+pub struct Interrupt {
+    requested: AtomicBool,
+    /// Bytes of the chunks parsed by every call that was passed this interrupt
+    processed: AtomicUsize,
+}
+
+impl Interrupt {
+    pub const fn new() -> Self {
+        Self {
+            requested: AtomicBool::new(false),
+            processed: AtomicUsize::new(0),
+        }
+    }
+
+    /// Requests the interrupt, returns whether it already was. Only touches atomics, so this is
+    /// safe to call from a signal handler.
+    pub fn request(&self) -> bool {
+        self.requested.swap(true, Ordering::Relaxed)
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+
+    pub fn processed(&self) -> usize {
+        self.processed.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AggregateOptions {
+    /// Number of workers, including the calling thread, 0 is treated as 1
+    pub threads: usize,
+    /// Bytes claimed by a worker at a time, `None` picks one from the input size
+    pub chunk_size: Option<usize>,
+    /// Validate every record with the checked parser
+    pub strict: bool,
+    /// Byte between the station and the temperature, one of [`DELIMITERS`]
+    pub delimiter: u8,
+    /// Temperatures use `,` instead of `.` as the decimal separator
+    pub decimal_comma: bool,
+    /// Station names may be quoted, see [`checked::split_quoted`]. Parsed with the checked parser,
+    /// which without `strict` accepts whole degrees and skips the lines it can't parse.
+    pub quoted_names: bool,
+    /// Compare the fast parser against the checked one on a sample of chunks
+    pub shadow_check: Option<ShadowCheck>,
+    /// Fault in the whole input on all workers before parsing starts
+    pub prefault: bool,
+    /// Record the order of the chunk claims into, or replay them from, this schedule
+    pub schedule: Option<Arc<Schedule>>,
+    /// Bounds on the input, the station name length is only checked with `strict`
+    pub limits: Limits,
+    /// When the run `limits.max_run_time` counts from, the start of the call if not set
+    pub started: Option<Instant>,
+    /// Buckets of the per-station temperature histograms, 0 to not track any
+    pub histogram: usize,
+    /// Every worker of every call adds its counters here
+    pub stats: Option<Arc<Mutex<Vec<WorkerStats>>>>,
+    /// Pin the workers to these nodes and give each node its own region of the input
+    pub numa: Option<Topology>,
+    /// Only parse this share of the chunks and skip the others
+    pub sample: Option<ChunkSample>,
+    /// What the workers aggregate into
+    pub table: Table,
+    /// Stop claiming chunks once this is requested and return the partial result
+    pub interrupt: Option<&'static Interrupt>,
+    /// Where the workers run
+    pub backend: Backend,
+    /// Stations every worker's map starts out with, and what to do with the others
+    pub stations: Option<Arc<StationList>>,
+    /// Trim and case fold names as they are parsed
+    pub names: Option<Arc<NameFold>>,
+}
+
+impl Default for AggregateOptions {
+    fn default() -> Self {
+        Self {
+            threads: 0,
+            chunk_size: None,
+            strict: false,
+            delimiter: b';',
+            decimal_comma: false,
+            quoted_names: false,
+            shadow_check: None,
+            prefault: false,
+            schedule: None,
+            limits: Limits::NONE,
+            started: None,
+            histogram: 0,
+            stats: None,
+            numa: None,
+            sample: None,
+            table: Table::Hashbrown,
+            interrupt: None,
+            backend: Backend::Native,
+            stations: None,
+            names: None,
+        }
+    }
+}
+
+/// The delimiters `process_chunk` is instantiated for
+pub const DELIMITERS: &[u8] = b";,\t|:";
+
+type WorkFn = fn(
+    &[u8],
+    &WorkQueue,
+    usize,
+    &AggregateOptions,
+    bool,
+    Option<&Mergers>,
+) -> Result<(Stations, Option<WorkerStats>), AggregateError>;
+
+/// Picks the `work` instantiation for the input format and table
+///
+/// # Panics
+/// If `delimiter` isn't one of [`DELIMITERS`].
+fn work_for(delimiter: u8, decimal_comma: bool, table: Table) -> WorkFn {
+    match (delimiter, decimal_comma) {
+        (b';', false) => work_with::<b';', b'.'>(table),
+        (b';', true) => work_with::<b';', b','>(table),
+        (b',', false) => work_with::<b',', b'.'>(table),
+        (b',', true) => work_with::<b',', b','>(table),
+        (b'\t', false) => work_with::<b'\t', b'.'>(table),
+        (b'\t', true) => work_with::<b'\t', b','>(table),
+        (b'|', false) => work_with::<b'|', b'.'>(table),
+        (b'|', true) => work_with::<b'|', b','>(table),
+        (b':', false) => work_with::<b':', b'.'>(table),
+        (b':', true) => work_with::<b':', b','>(table),
+        (delimiter, _) => panic!("unsupported delimiter {:?}", delimiter as char),
+    }
+}
+
+fn work_with<const DELIMITER: u8, const RADIX: u8>(table: Table) -> WorkFn {
+    match table {
+        Table::Hashbrown => work::<DELIMITER, RADIX, HashbrownKind>,
+        Table::Flat => work::<DELIMITER, RADIX, FlatKind>,
+    }
+}
+
+/// Aggregates `data` on `options.threads` workers and returns the merged per-station map.
+///
+/// On malformed input in strict mode, or a chunk failing its shadow check, the error with the
+/// lowest offset is returned, as is going over `options.limits`. When replaying a schedule, the
+/// call must match the configuration of the next recorded pass. Once `options.interrupt` is
+/// requested only the chunks claimed so far are aggregated.
+///
+/// With a single thread nothing is spawned, the calling thread claims every chunk in file order.
+/// That is always the case on wasm. Otherwise every worker runs on a thread of its own and sends
+/// its map to the mergers once it runs out of chunks, or whenever it holds [`FLUSH_STATIONS`]
+/// stations. The mergers merge the maps as they arrive, while the other workers are still
+/// parsing. There is a merger per 8 workers, each merging the stations of one shard, the calling
+/// thread being the first of them.
+/// See [`Backend`] for running the workers on a rayon pool instead.
+///
+/// Every record is read with the checked parser, lines it can't parse are skipped unless
+/// `options.strict` is set. See [`aggregate_unchecked`] for the fast parser.
+///
+/// # Panics
+/// If `options.delimiter` isn't one of [`DELIMITERS`].
+pub fn aggregate(data: &[u8], options: &AggregateOptions) -> Result<Stations, AggregateError> {
+    aggregate_with(data, options, false)
+}
+
+/// Like [`aggregate`], but the records are read with the fast parser unless `options.strict` or
+/// `options.quoted_names` is set.
+///
+/// # Safety
+/// Without `options.strict` or `options.quoted_names`, every record of `data` must be well-formed:
+/// a station without the delimiter or `\n`, the delimiter, then a `[-]d{1,2}` or `[-]d{1,2}.d`
+/// temperature, its dot being a comma with `options.decimal_comma`. Blank lines are allowed and so
+/// is a missing final `\n`.
+///
+/// # Panics
+/// If `options.delimiter` isn't one of [`DELIMITERS`].
+pub unsafe fn aggregate_unchecked(
+    data: &[u8],
+    options: &AggregateOptions,
+) -> Result<Stations, AggregateError> {
+    aggregate_with(data, options, true)
+}
+
+fn aggregate_with(
+    data: &[u8],
+    options: &AggregateOptions,
+    trusted: bool,
+) -> Result<Stations, AggregateError> {
+    let result = run_workers(data, options, trusted);
+    if let Some(names) = &options.names {
+        names.finish_call();
+    }
+    result
+}
+
+fn run_workers(
+    data: &[u8],
+    options: &AggregateOptions,
+    trusted: bool,
+) -> Result<Stations, AggregateError> {
+    #[cfg(feature = "rayon")]
+    if options.backend == Backend::Rayon {
+        return backend::aggregate_rayon(data, options, trusted);
+    }
+    // Threads can't be spawned on wasm
+    let threads = match options.threads {
+        _ if cfg!(target_family = "wasm") => 1,
+        threads => threads.max(1),
+    };
+    let chunk_size = options
+        .chunk_size
+        .unwrap_or_else(|| auto_chunk_size(data.len(), threads));
+    let work = work_for(options.delimiter, options.decimal_comma, options.table);
+    let queue = WorkQueue::new(data.len(), chunk_size).tapering(threads);
+    let queue = match options.schedule.as_deref() {
+        Some(Schedule::Record(_)) => queue.recording(),
+        Some(schedule @ Schedule::Replay { .. }) => queue.replaying(
+            schedule
+                .next_pass(data.len(), chunk_size, threads)
+                .map_err(AggregateError::Schedule)?,
+        ),
+        None => queue,
+    };
+    let homes = options
+        .numa
+        .as_ref()
+        .map(|topology| topology.homes(threads));
+    let queue = match &homes {
+        Some(homes) => queue.per_node(homes.clone()),
+        None => queue,
+    };
+    // A single worker runs on the calling thread, which gets its own affinity back afterwards
+    let affinity = homes.as_ref().and_then(|_| Affinity::current().ok());
+    let prefaulted = Barrier::new(threads);
+
+    let (mergers, shards) = Mergers::new(threads.div_ceil(WORKERS_PER_MERGER));
+    let mut merged = Stations::default();
+    let error: Mutex<Option<AggregateError>> = Mutex::new(None);
+    std::thread::scope(|s| {
+        let worker = |index: usize, mergers: Mergers| {
+            if let (Some(topology), Some(homes)) = (&options.numa, &homes) {
+                // Only costs locality if it fails, e.g. when the node's CPUs aren't allowed
+                let _ = Affinity::of(&topology.nodes[homes[index]]).apply();
+            }
+            if options.prefault {
+                prefault(data, index, threads);
+                prefaulted.wait();
+            }
+            // Flushing only pays off with a merger running next to the workers
+            let flush = (threads > 1).then_some(&mergers);
+            match work(data, &queue, index, options, trusted, flush) {
+                Ok((map, stats)) => {
+                    if let (Some(sink), Some(stats)) = (&options.stats, stats) {
+                        sink.lock().unwrap().push(stats);
+                    }
+                    mergers.send(map);
+                }
+                Err(err) => {
+                    // Report the earliest error if several workers hit one
+                    let mut error = error.lock().unwrap();
+                    if error
+                        .as_ref()
+                        .is_none_or(|first| err.offset() < first.offset())
+                    {
+                        *error = Some(err);
+                    }
+                }
+            }
+        };
+        if threads == 1 {
+            worker(0, mergers);
+        } else {
+            for index in 0..threads {
+                let mergers = mergers.clone();
+                s.spawn(move || worker(index, mergers));
+            }
+            drop(mergers);
+        }
+        // Every merger but the first runs on a thread of its own, they end once every worker
+        // dropped its senders
+        let mut shards = shards.into_iter();
+        let first = shards.next().unwrap();
+        let others: Vec<_> = shards.map(|maps| s.spawn(|| merge_shard(maps))).collect();
+        merged = merge_shard(first);
+        // The shards have no station in common
+        for other in others {
+            let mut other = other.join().unwrap();
+            if other.len() > merged.len() {
+                std::mem::swap(&mut other, &mut merged);
+            }
+            merged.extend(other);
+        }
+    });
+    if let Some(affinity) = affinity {
+        let _ = affinity.apply();
+    }
+    // Kept even if the pass failed, that is when the schedule is most useful
+    if let Some(Schedule::Record(passes)) = options.schedule.as_deref() {
+        passes.lock().unwrap().push(Pass {
+            len: data.len(),
+            chunk_size,
+            threads,
+            claims: queue.into_recorded(),
+        });
+    }
+    if let Some(err) = error.into_inner().unwrap() {
+        return Err(err);
+    }
+    options
+        .limits
+        .check_stations(merged.len(), None)
+        .map_err(AggregateError::Limit)?;
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::Write, path::PathBuf};
+
+    use super::*;
+
+    /// splitmix64, like the one generating the benchmark inputs
+    pub(crate) struct Rng(pub(crate) u64);
+
+    impl Rng {
+        pub(crate) fn next(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        }
+        pub(crate) fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    /// A path in the temporary directory, removed again when dropped
+    pub(crate) struct TempFile(pub(crate) PathBuf);
+
+    impl TempFile {
+        pub(crate) fn new(name: &str) -> Self {
+            let file = format!("1brc-{}-{name}", std::process::id());
+            Self(std::env::temp_dir().join(file))
+        }
+        pub(crate) fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// `(count, sum, min, max)` per station
+    pub(crate) type Summary = BTreeMap<Vec<u8>, (usize, i64, i16, i16)>;
+
+    pub(crate) fn summarize(stations: Stations) -> Summary {
+        stations
+            .into_iter()
+            .map(|(name, rec)| (name.into_vec(), (rec.count, rec.sum, rec.min, rec.max)))
+            .collect()
+    }
+
+    /// Random records of up to `stations` stations, with their expected summary. With `whole`, a
+    /// quarter of the values are in whole degrees like `-5`, which `strict` rejects.
+    pub(crate) fn generate(
+        rng: &mut Rng,
+        stations: u64,
+        records: usize,
+        whole: bool,
+    ) -> (Vec<u8>, Summary) {
+        let names: Vec<Vec<u8>> = (0..stations)
+            .map(|_| {
+                let len = 1 + rng.below(40) as usize;
+                // Anything but the delimiter and newlines, including non-ASCII bytes
+                (0..len)
+                    .map(|_| match rng.below(256) as u8 {
+                        b';' | b'\n' => b'x',
+                        b => b,
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut input = Vec::new();
+        let mut expected = Summary::new();
+        for _ in 0..records {
+            let name = &names[rng.below(stations) as usize];
+            input.extend_from_slice(name);
+            input.push(b';');
+            let value = if whole && rng.below(4) == 0 {
+                let degrees = rng.below(199) as i16 - 99;
+                writeln!(input, "{degrees}").unwrap();
+                degrees * 10
+            } else {
+                let value = rng.below(1999) as i16 - 999;
+                if value < 0 {
+                    input.push(b'-');
+                }
+                writeln!(input, "{}.{}", value.abs() / 10, value.abs() % 10).unwrap();
+                value
+            };
+            let (count, sum, min, max) =
+                expected
+                    .entry(name.clone())
+                    .or_insert((0, 0, i16::MAX, i16::MIN));
+            *count += 1;
+            *sum += value as i64;
+            *min = (*min).min(value);
+            *max = (*max).max(value);
+        }
+        (input, expected)
+    }
+
+    /// Aggregates well-formed `input` with the fast parser, unless `strict` is set
+    fn run(input: &[u8], threads: usize, chunk_size: usize, strict: bool) -> Summary {
+        let options = AggregateOptions {
+            threads,
+            chunk_size: Some(chunk_size),
+            strict,
+            ..Default::default()
+        };
+        // SAFETY: the callers only pass well-formed input
+        summarize(unsafe { aggregate_unchecked(input, &options) }.unwrap())
+    }
+
+    #[test]
+    fn random_inputs_match_oracle() {
+        let mut rng = Rng(0x1b7c);
+        for case in 0..40 {
+            let stations = 1 + rng.below(50);
+            let records = rng.below(400) as usize;
+            // Strict mode only reads the inputs without whole degrees
+            let whole = case % 4 >= 2;
+            let (mut input, expected) = generate(&mut rng, stations, records, whole);
+            // The newline that ends the file is optional
+            if case % 2 == 1 {
+                input.pop();
+            }
+            let chunk_size = 1 + rng.below(64) as usize;
+            for threads in [1, 2, 3, 8] {
+                for strict in [false, !whole] {
+                    let got = run(&input, threads, chunk_size, strict);
+                    assert_eq!(
+                        got, expected,
+                        "case {case}, {threads} threads, chunk size {chunk_size}, strict {strict}"
+                    );
+                }
+                let options = AggregateOptions {
+                    threads,
+                    chunk_size: Some(chunk_size),
+                    ..Default::default()
+                };
+                let got = summarize(aggregate(&input, &options).unwrap());
+                assert_eq!(got, expected, "case {case}, {threads} threads, checked");
+            }
+        }
+    }
+
+    #[test]
+    fn flat_table_matches_hashbrown() {
+        let mut rng = Rng(0x299);
+        for case in 0..40 {
+            // Up to a few thousand stations, so the flat table grows several times
+            let stations = 1 + rng.below(3000);
+            let records = rng.below(6000) as usize;
+            let (input, expected) = generate(&mut rng, stations, records, true);
+            let chunk_size = 1 + rng.below(4096) as usize;
+            for threads in [1, 3] {
+                let aggregate = |table| {
+                    let options = AggregateOptions {
+                        threads,
+                        chunk_size: Some(chunk_size),
+                        table,
+                        ..Default::default()
+                    };
+                    // SAFETY: `generate` only writes well-formed records
+                    unsafe { aggregate_unchecked(&input, &options) }.unwrap()
+                };
+                let hashbrown = aggregate(Table::Hashbrown);
+                let flat = aggregate(Table::Flat);
+                assert_eq!(flat, hashbrown, "case {case}, {threads} threads");
+                assert_eq!(summarize(flat), expected, "case {case}, {threads} threads");
+            }
+        }
+    }
+
+    #[test]
+    fn tab_and_pipe_delimiters() {
+        let mut rng = Rng(0x288);
+        for case in 0..20 {
+            let stations = 1 + rng.below(50);
+            let records = rng.below(400) as usize;
+            let (input, _) = generate(&mut rng, stations, records, case % 2 == 1);
+            // Names can't hold the delimiter either
+            let input: Vec<u8> = input
+                .into_iter()
+                .map(|b| if b == b'\t' || b == b'|' { b'x' } else { b })
+                .collect();
+            let chunk_size = 1 + rng.below(64) as usize;
+            let aggregate = |input: &[u8], delimiter, trusted| {
+                let options = AggregateOptions {
+                    threads: 3,
+                    chunk_size: Some(chunk_size),
+                    delimiter,
+                    ..Default::default()
+                };
+                let result = match trusted {
+                    // SAFETY: `generate` only writes well-formed records
+                    true => unsafe { aggregate_unchecked(input, &options) },
+                    false => aggregate(input, &options),
+                };
+                summarize(result.unwrap())
+            };
+            let expected = aggregate(&input, b';', false);
+            for delimiter in [b'\t', b'|'] {
+                let other: Vec<u8> = input
+                    .iter()
+                    .map(|&b| if b == b';' { delimiter } else { b })
+                    .collect();
+                for trusted in [false, true] {
+                    let got = aggregate(&other, delimiter, trusted);
+                    assert_eq!(got, expected, "case {case}, {:?}", delimiter as char);
+                }
+            }
+        }
+    }
+
+    /// Summary of `records` given as `(station, tenths)`
+    fn expect(records: &[(&str, i16)]) -> Summary {
+        let mut expected = Summary::new();
+        for &(name, value) in records {
+            let (count, sum, min, max) =
+                expected
+                    .entry(name.as_bytes().to_vec())
+                    .or_insert((0, 0, i16::MAX, i16::MIN));
+            *count += 1;
+            *sum += value as i64;
+            *min = (*min).min(value);
+            *max = (*max).max(value);
+        }
+        expected
+    }
+
+    /// Runs `process_chunk` over every `chunk_size` bytes of `input` in turn
+    fn process(input: &[u8], chunk_size: usize, avx2: bool) -> Summary {
+        let bump = BumpAlloc::new();
+        let mut map: Map<StationKey, MeasurementRecord> = Map::new();
+        for start in (0..input.len()).step_by(chunk_size) {
+            let end = (start + chunk_size).min(input.len());
+            // SAFETY: `avx2` was detected and the callers only pass well-formed input
+            unsafe {
+                process_chunk::<b';', b'.'>(
+                    input, start, end, &mut map, &bump, avx2, None, None, None, 0,
+                )
+            }
+            .unwrap();
+        }
+        map.drain()
+            .map(|(name, rec)| {
+                let summary = (rec.count, rec.sum, rec.min, rec.max);
+                (name.as_bytes().to_vec(), summary)
+            })
+            .collect()
+    }
+
+    /// Checks every way of splitting `input` into chunks, with the scalar and AVX2 loops and with
+    /// `work` in both modes unless `strict` would reject the input
+    fn check_fixture(input: &[u8], expected: &Summary, strict: bool) {
+        for chunk_size in 1..=input.len().max(1) {
+            for avx2 in [false, avx2_available()] {
+                assert_eq!(&process(input, chunk_size, avx2), expected, "{chunk_size}");
+            }
+            for threads in [1, 3] {
+                assert_eq!(&run(input, threads, chunk_size, false), expected);
+                if strict {
+                    assert_eq!(&run(input, threads, chunk_size, true), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_input() {
+        check_fixture(b"", &Summary::new(), true);
+        check_fixture(b"\n", &Summary::new(), false);
+    }
+
+    #[test]
+    fn missing_final_newline() {
+        let expected = expect(&[("Hamburg", 120), ("Bulawayo", 89)]);
+        check_fixture(b"Hamburg;12.0\nBulawayo;8.9", &expected, true);
+        let expected = expect(&[("Hamburg", 120), ("Bulawayo", -80)]);
+        check_fixture(b"Hamburg;12.0\nBulawayo;-8", &expected, false);
+    }
+
+    #[test]
+    fn whole_degrees() {
+        // Every width of whole degrees, next to values with a dot and at the end of the input
+        let input = b"Oslo;-3\nHamburg;12.0\nA;99\nB;-99\nC;0\nD;-0.5\nOslo;7\nE;-10\nF;5";
+        let expected = expect(&[
+            ("Oslo", -30),
+            ("Hamburg", 120),
+            ("A", 990),
+            ("B", -990),
+            ("C", 0),
+            ("D", -5),
+            ("Oslo", 70),
+            ("E", -100),
+            ("F", 50),
+        ]);
+        check_fixture(input, &expected, false);
+        check_fixture(&[&input[..], b"\n"].concat(), &expected, false);
+        // Strict mode requires the digit after the dot
+        for input in [&b"Oslo;-3\n"[..], b"Hamburg;12.0\nOslo;7"] {
+            let options = AggregateOptions {
+                strict: true,
+                ..Default::default()
+            };
+            assert!(aggregate(input, &options).is_err());
+        }
+    }
+
+    #[test]
+    fn blank_lines() {
+        let expected = expect(&[("Hamburg", 120), ("Bulawayo", 89), ("Hamburg", -34)]);
+        let input = b"\nHamburg;12.0\n\nBulawayo;8.9\n\n\n\nHamburg;-3.4\n\n";
+        check_fixture(input, &expected, false);
+        let options = AggregateOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let Err(AggregateError::Parse(err)) = aggregate(input, &options) else {
+            panic!("blank lines are rejected with --strict");
+        };
+        assert_eq!((err.offset, err.reason), (0, "blank line"));
+    }
+
+    #[test]
+    fn record_straddling_chunks() {
+        // Every chunk size up to the input length puts an edge inside some record
+        let input = b"Hamburg;12.0\nSt. John's;-15.2\nA;0.0\nBulawayo;8\nHamburg;99.9\n";
+        let expected = expect(&[
+            ("Hamburg", 120),
+            ("St. John's", -152),
+            ("A", 0),
+            ("Bulawayo", 80),
+            ("Hamburg", 999),
+        ]);
+        check_fixture(input, &expected, false);
+        let input = b"Hamburg;12.0\nSt. John's;-15.2\nA;0.0\nHamburg;99.9\n";
+        let expected = expect(&[
+            ("Hamburg", 120),
+            ("St. John's", -152),
+            ("A", 0),
+            ("Hamburg", 999),
+        ]);
+        check_fixture(input, &expected, true);
+    }
+
+    #[test]
+    fn malformed_input_is_skipped_by_aggregate() {
+        let input = b"no delimiter here\nHamburg;12.0\nOslo;\nBerlin;abc\n;\nHamburg;-3\nx;1.2.3";
+        for threads in [1, 3] {
+            for chunk_size in [1, 7, input.len()] {
+                let options = AggregateOptions {
+                    threads,
+                    chunk_size: Some(chunk_size),
+                    ..Default::default()
+                };
+                let got = summarize(aggregate(input, &options).unwrap());
+                assert_eq!(got, expect(&[("Hamburg", 120), ("Hamburg", -30)]));
+            }
+        }
+    }
+
+    #[test]
+    fn names_longer_than_an_arena_chunk() {
+        let long = vec![b'a'; BUMP_CAP + 1];
+        let mut input = long.clone();
+        input.extend_from_slice(b";1.5\nHamburg;12.0\n");
+        input.extend_from_slice(&long);
+        input.extend_from_slice(b";-2.5\n");
+        let got = summarize(aggregate(&input, &Default::default()).unwrap());
+        let mut expected = expect(&[("Hamburg", 120)]);
+        expected.insert(long, (2, -10, -25, 15));
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn tapered_queue_covers_input_once() {
+        let lens = [0, 1, 100, 65_535, 1 << 20, 10_000_000, 123_456_789];
+        let chunk_sizes = [1, 1000, MIN_WORK_CHUNK, 1 << 20, MAX_WORK_CHUNK];
+        for len in lens {
+            for chunk_size in chunk_sizes {
+                // Keeps the number of claims manageable
+                if len / chunk_size > 200_000 {
+                    continue;
+                }
+                for threads in [1, 2, 3, 8] {
+                    let queue = WorkQueue::new(len, chunk_size).tapering(threads);
+                    let mut chunks: Vec<Range<usize>> = std::thread::scope(|s| {
+                        let workers: Vec<_> = (0..threads)
+                            .map(|thread| {
+                                let queue = &queue;
+                                s.spawn(move || {
+                                    std::iter::from_fn(|| queue.claim(thread)).collect::<Vec<_>>()
+                                })
+                            })
+                            .collect();
+                        workers
+                            .into_iter()
+                            .flat_map(|worker| worker.join().unwrap())
+                            .collect()
+                    });
+                    chunks.sort_by_key(|chunk| chunk.start);
+                    let mut covered = 0;
+                    for chunk in &chunks {
+                        assert_eq!(chunk.start, covered, "{len} {chunk_size} {threads}");
+                        assert!(chunk.end > chunk.start && chunk.end - chunk.start <= chunk_size);
+                        covered = chunk.end;
+                    }
+                    assert_eq!(covered, len, "{len} {chunk_size} {threads}");
+                }
+            }
+        }
+    }
+
+    /// Every `[-]d{1,2}.d` temperature with its value in tenths, including `-0.0`
+    fn all_values() -> Vec<(String, i16)> {
+        let mut values = vec![("-0.0".to_owned(), 0)];
+        for tenths in -999..=999i16 {
+            let sign = if tenths < 0 { "-" } else { "" };
+            let abs = tenths.abs();
+            values.push((format!("{sign}{}.{}", abs / 10, abs % 10), tenths));
+        }
+        values
+    }
+
+    #[test]
+    fn swar_matches_parse_value() {
+        for (text, tenths) in all_values() {
+            let (before_dot, after_dot) = text.as_bytes().split_at(text.len() - 2);
+            // SAFETY: every value is `[-]d{1,2}.d`
+            let value = unsafe { parse_value(before_dot, after_dot[1]) };
+            assert_eq!(value, tenths, "{text}");
+            // Followed by the next record, which must be ignored
+            let word = |text: &str| {
+                let bytes = format!("{text}\nHamburg;");
+                u64::from_le_bytes(bytes.as_bytes()[..8].try_into().unwrap())
+            };
+            let expected = Some((tenths, text.len()));
+            assert_eq!(parse_value_swar::<b'.'>(word(&text)), expected, "{text}");
+            let comma = text.replace('.', ",");
+            assert_eq!(parse_value_swar::<b','>(word(&comma)), expected, "{comma}");
+            // Whole degrees have no radix
+            let whole = &text[..text.len() - 2];
+            assert_eq!(parse_value_swar::<b'.'>(word(whole)), None, "{whole}");
+            assert_eq!(parse_value_swar::<b','>(word(whole)), None, "{whole}");
+        }
+    }
+
+    #[test]
+    fn values_at_end_of_chunk() {
+        for (text, tenths) in all_values() {
+            let whole = &text[..text.len() - 2];
+            let whole_tenths = whole.parse::<i16>().unwrap() * 10;
+            for (value, tenths) in [(&text[..], tenths), (whole, whole_tenths)] {
+                // The bytes past the end of the data would change the value if they were read
+                let record = format!("Hamburg;1.0\nA;{value}");
+                let buffer = format!("{record}.7\nB;1.0\n");
+                let data = &buffer.as_bytes()[..record.len()];
+                let expected = expect(&[("Hamburg", 10), ("A", tenths)]);
+                for avx2 in [false, avx2_available()] {
+                    assert_eq!(process(data, data.len(), avx2), expected, "{value}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn station_keys_around_inline_limit() {
+        let hasher = Map::<StationKey, ()>::new().hasher().clone();
+        for len in [1, 4, 8, 15, INLINE_KEY_LEN, INLINE_KEY_LEN + 1, 100] {
+            let name: Vec<u8> = (0..len).map(|i| b'A' + (i % 26) as u8).collect();
+            let key = StationKey::inline(&name);
+            assert_eq!(key.is_some(), len <= INLINE_KEY_LEN, "{len}");
+            let key = key.unwrap_or(StationKey::Arena(&name));
+            assert_eq!(key.as_bytes(), name, "{len}");
+            // An equal name elsewhere is the same key
+            let copy = name.clone();
+            let other = StationKey::inline(&copy).unwrap_or(StationKey::Arena(&copy));
+            assert_eq!(key, other);
+            assert_eq!(hasher.hash_one(key), hasher.hash_one(other));
+        }
+        // Every length takes one of the loads, they must not leave bytes of other names behind
+        let mut rng = Rng(0x5e7);
+        for len in 0..=INLINE_KEY_LEN {
+            let name: Vec<u8> = (0..len).map(|_| rng.below(256) as u8).collect();
+            let key = StationKey::inline(&name).unwrap();
+            assert_eq!(key.as_bytes(), name);
+            let StationKey::Inline { bytes, .. } = key else {
+                unreachable!()
+            };
+            assert!(bytes[len..].iter().all(|&b| b == 0), "{len}");
+        }
+    }
+
+    #[test]
+    fn arena_keys_sharing_a_prefix() {
+        let prefix = [b'p'; INLINE_KEY_LEN];
+        let a = [&prefix[..], b"-Hamburg, a station with a long name"].concat();
+        let b = [&prefix[..], b"-Bulawayo, a station with a long name"].concat();
+        assert_ne!(StationKey::Arena(&a), StationKey::Arena(&b));
+        let hasher = Map::<StationKey, ()>::new().hasher().clone();
+        assert_ne!(
+            hasher.hash_one(StationKey::Arena(&a)),
+            hasher.hash_one(StationKey::Arena(&b))
+        );
+        let input = [
+            &a[..],
+            b";1.0\n",
+            &b,
+            b";2.0\n",
+            &prefix,
+            b";3.0\n",
+            &a,
+            b";4.0\n",
+            &prefix[..1],
+            b";5.0\n",
+            &prefix[..INLINE_KEY_LEN - 1],
+            b";6.0\n",
+        ]
+        .concat();
+        let name = |name: &[u8]| String::from_utf8(name.to_vec()).unwrap();
+        let expected = expect(&[
+            (&name(&a), 10),
+            (&name(&b), 20),
+            (&name(&prefix), 30),
+            (&name(&a), 40),
+            (&name(&prefix[..1]), 50),
+            (&name(&prefix[..INLINE_KEY_LEN - 1]), 60),
+        ]);
+        for chunk_size in [7, input.len()] {
+            assert_eq!(process(&input, chunk_size, false), expected);
+            assert_eq!(run(&input, 2, chunk_size, false), expected);
+        }
+    }
+
+    #[test]
+    fn skewed_input_with_flushes() {
+        // Half the records are one station, the others are all different and more than each
+        // worker holds before it flushes its map
+        let unique = 3 * FLUSH_STATIONS;
+        let mut rng = Rng(0x5ca1e);
+        let mut input = Vec::new();
+        for i in 0..unique {
+            let value = rng.below(1999) as i32 - 999;
+            let sign = if value < 0 { "-" } else { "" };
+            let (int, frac) = (value.abs() / 10, value.abs() % 10);
+            writeln!(input, "Hamburg;{sign}{int}.{frac}").unwrap();
+            writeln!(input, "station {i};{sign}{frac}.{}", int % 10).unwrap();
+        }
+        let single = run(&input, 1, 256 * 1024, false);
+        assert_eq!(single.len(), unique + 1);
+        assert_eq!(single[&b"Hamburg"[..]].0, unique);
+        for threads in [2, 3, 4] {
+            assert_eq!(run(&input, threads, 256 * 1024, false), single, "{threads}");
+        }
+    }
+
+    #[test]
+    fn auto_chunk_size_leaves_no_thread_idle() {
+        let lens = [1, 7, 100, 4096, 65_536, 1 << 20, 50_000_000, 13_000_000_000];
+        for len in lens {
+            // No threads is taken as one
+            assert_eq!(auto_chunk_size(len, 0), auto_chunk_size(len, 1));
+            for threads in [1, 2, 3, 8, 32, 64, 256] {
+                let chunk_size = auto_chunk_size(len, threads);
+                assert!((1..=MAX_WORK_CHUNK).contains(&chunk_size));
+                if len < threads {
+                    continue;
+                }
+                // Tapering only ever shrinks the chunks
+                let queue = WorkQueue::new(len, chunk_size).tapering(threads);
+                let chunks = std::iter::from_fn(|| queue.claim(0)).take(threads).count();
+                assert_eq!(chunks, threads, "{len} bytes, {threads} threads");
+            }
+        }
+    }
+
+    #[test]
+    fn many_threads_on_a_small_input() {
+        let mut rng = Rng(0x278);
+        let (input, expected) = generate(&mut rng, 20, 300, true);
+        assert_eq!(run(&input, 1, input.len(), false), expected);
+        // 64 workers contend for every claim, and with 1000 byte chunks most of them finish
+        // without a record, their maps racing each other to the merger
+        for round in 0..20 {
+            for chunk_size in [None, Some(1), Some(16), Some(1000)] {
+                let options = AggregateOptions {
+                    threads: 64,
+                    chunk_size,
+                    ..Default::default()
+                };
+                let got = summarize(aggregate(&input, &options).unwrap());
+                assert_eq!(got, expected, "round {round}, chunk size {chunk_size:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn many_threads_with_every_station() {
+        // Each of 64 workers replays two chunks of its own, both holding a reading of every
+        // station, so they all send a full map to the mergers at the same time
+        const THREADS: usize = 64;
+        let mut rng = Rng(0x2782);
+        let mut input = Vec::new();
+        let mut claims = Vec::new();
+        for chunk in 0..2 * THREADS {
+            let start = input.len();
+            for station in 0..20 {
+                let value = rng.below(1999) as i32 - 999;
+                let sign = if value < 0 { "-" } else { "" };
+                let (int, frac) = (value.abs() / 10, value.abs() % 10);
+                writeln!(input, "station {station};{sign}{int}.{frac}").unwrap();
+            }
+            // A chunk holds the records that start after a newline in it
+            let (start, end) = (start.saturating_sub(1), input.len() - 1);
+            claims.push(Claim {
+                thread: chunk % THREADS,
+                start,
+                end,
+            });
+        }
+        let expected = run(&input, 1, input.len(), false);
+        assert_eq!(expected.len(), 20);
+        let pass = Pass {
+            len: input.len(),
+            chunk_size: input.len(),
+            threads: THREADS,
+            claims,
+        };
+
+        let queue = WorkQueue::new(input.len(), input.len()).replaying(&pass);
+        let options = AggregateOptions::default();
+        let maps: Vec<Stations> = std::thread::scope(|s| {
+            let (input, queue, options) = (&input, &queue, &options);
+            let workers: Vec<_> = (0..THREADS)
+                .map(|thread| {
+                    s.spawn(move || {
+                        work::<b';', b'.', HashbrownKind>(input, queue, thread, options, true, None)
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap().unwrap().0)
+                .collect()
+        });
+        for (thread, map) in maps.iter().enumerate() {
+            assert_eq!(map.len(), 20, "{thread}");
+            let count: usize = map.values().map(|rec| rec.count).sum();
+            assert_eq!(count, 40, "{thread}");
+        }
+
+        // The same claims through `aggregate`, with a merger per 8 workers
+        let rounds = 20;
+        let schedule = Arc::new(Schedule::replay(vec![pass; rounds]));
+        for round in 0..rounds {
+            let options = AggregateOptions {
+                threads: THREADS,
+                chunk_size: Some(input.len()),
+                schedule: Some(Arc::clone(&schedule)),
+                ..Default::default()
+            };
+            // SAFETY: the input is well-formed
+            let got = summarize(unsafe { aggregate_unchecked(&input, &options) }.unwrap());
+            assert_eq!(got, expected, "round {round}");
+        }
+        schedule.finish().unwrap();
+    }
+}
@@ -0,0 +1,357 @@
+//! Resource limits for parsing input that may be hostile, bundled by `--untrusted`.
+//!
+//! With the checked parser every accepted line is a station of at most `max_station_len` bytes,
+//! the delimiter and a `[-]d{1,2}.d` temperature, which also bounds the length of a line. Worker
+//! maps are checked against `max_stations` after every chunk, so no map grows past it by more
+//! than the stations of one chunk, and the merged result never holds more than `max_stations`.
+//! The output is one line per station, so its size is bounded by both limits as well, and
+//! `max_output_size` caps it whatever the histograms add. Workers check the run time after every
+//! chunk, so a run fails at most one chunk after going over `max_run_time`.
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Longest station name in bytes, only enforced by the checked parser
+    pub max_station_len: usize,
+    /// Most distinct stations
+    pub max_stations: usize,
+    /// Largest input file in bytes
+    pub max_input_size: u64,
+    /// Largest report in bytes, see [`CappedWriter`]
+    pub max_output_size: u64,
+    /// Longest time aggregating an input may take
+    pub max_run_time: Duration,
+}
+
+impl Limits {
+    pub const NONE: Self = Self {
+        max_station_len: usize::MAX,
+        max_stations: usize::MAX,
+        max_input_size: u64::MAX,
+        max_output_size: u64::MAX,
+        max_run_time: Duration::MAX,
+    };
+
+    /// The bounds of the challenge: names of up to 100 bytes, at most 10,000 stations and inputs
+    /// of up to 16 GiB, a little over the size of the official billion rows. Their report is
+    /// about 1.3 MB, 16 MiB leave room for histograms, and 10 minutes for parsing them on a
+    /// single slow core.
+    pub const UNTRUSTED: Self = Self {
+        max_station_len: 100,
+        max_stations: 10_000,
+        max_input_size: 16 << 30,
+        max_output_size: 16 << 20,
+        max_run_time: Duration::from_secs(10 * 60),
+    };
+
+    pub fn check_stations(
+        &self,
+        stations: usize,
+        offset: Option<usize>,
+    ) -> Result<(), LimitExceeded> {
+        if stations <= self.max_stations {
+            return Ok(());
+        }
+        Err(LimitExceeded {
+            limit: Limit::Stations,
+            max: self.max_stations as u64,
+            found: stations as u64,
+            offset,
+        })
+    }
+
+    pub fn check_input_size(&self, len: u64) -> Result<(), LimitExceeded> {
+        if len <= self.max_input_size {
+            return Ok(());
+        }
+        Err(LimitExceeded {
+            limit: Limit::InputSize,
+            max: self.max_input_size,
+            found: len,
+            offset: None,
+        })
+    }
+
+    /// Fails if more than `max_run_time` passed since `started`. `offset` is the start of the
+    /// chunk that was parsed last.
+    pub fn check_run_time(
+        &self,
+        started: Instant,
+        offset: Option<usize>,
+    ) -> Result<(), LimitExceeded> {
+        // Spares the workers reading the clock after every chunk
+        if self.max_run_time == Duration::MAX {
+            return Ok(());
+        }
+        let elapsed = started.elapsed();
+        if elapsed <= self.max_run_time {
+            return Ok(());
+        }
+        Err(LimitExceeded {
+            limit: Limit::RunTime,
+            max: self.max_run_time.as_millis() as u64,
+            // Rounded up, going over by less than a millisecond still shows
+            found: elapsed.as_micros().div_ceil(1000) as u64,
+            offset,
+        })
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Stations,
+    InputSize,
+    OutputSize,
+    RunTime,
+}
+
+impl Limit {
+    /// The option that sets the limit
+    pub fn flag(&self) -> &'static str {
+        match self {
+            Self::Stations => "--max-stations",
+            Self::InputSize => "--max-input-size",
+            Self::OutputSize => "--max-output-size",
+            Self::RunTime => "--timeout",
+        }
+    }
+}
+
+/// An input that went over one of the `Limits`. Station names over `max_station_len` are
+/// reported by the checked parser instead, as a `ParseError` on the offending line.
+#[derive(Debug, Clone)]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    pub max: u64,
+    pub found: u64,
+    /// Start of the chunk where the limit was exceeded, if it was found while parsing
+    pub offset: Option<usize>,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (what, unit) = match self.limit {
+            Limit::Stations => ("distinct stations", ""),
+            Limit::InputSize => ("bytes of input", ""),
+            Limit::OutputSize => ("bytes of output", ""),
+            Limit::RunTime => ("ms of run time", "ms"),
+        };
+        write!(
+            f,
+            "{} {what} exceed {} {}{unit}",
+            self.found,
+            self.limit.flag(),
+            self.max
+        )?;
+        if let Some(offset) = self.offset {
+            write!(f, " in the chunk at byte {offset}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for LimitExceeded {}
+
+/// A writer that fails with [`Limit::OutputSize`] instead of writing past `max` bytes. Whatever
+/// fits under the limit is written before it fails.
+pub struct CappedWriter<W> {
+    inner: W,
+    written: u64,
+    max: u64,
+}
+
+impl<W: Write> CappedWriter<W> {
+    pub fn new(inner: W, max: u64) -> Self {
+        Self {
+            inner,
+            written: 0,
+            max,
+        }
+    }
+}
+
+impl<W: Write> Write for CappedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let left = self.max - self.written;
+        if buf.len() as u64 > left {
+            if left > 0 {
+                let written = self.inner.write(&buf[..left as usize])?;
+                self.written += written as u64;
+                return Ok(written);
+            }
+            return Err(io::Error::other(LimitExceeded {
+                limit: Limit::OutputSize,
+                max: self.max,
+                found: self.written + buf.len() as u64,
+                offset: None,
+            }));
+        }
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aggregate,
+        tests::{Rng, TempFile},
+        AggregateError, AggregateOptions, Stations,
+    };
+
+    /// Aggregates `input` in strict mode under `limits`, on one and several threads
+    fn untrusted(input: &[u8], limits: Limits) -> Result<Stations, AggregateError> {
+        let mut result = None;
+        for threads in [1, 4] {
+            let options = AggregateOptions {
+                threads,
+                chunk_size: Some(64),
+                strict: true,
+                limits,
+                ..Default::default()
+            };
+            let got = aggregate(input, &options);
+            // Both succeed or fail alike
+            if let Some(first) = &result {
+                assert_eq!(format!("{first:?}"), format!("{got:?}"));
+            }
+            result = Some(got);
+        }
+        result.unwrap()
+    }
+
+    #[test]
+    fn oversized_name() {
+        let name = "x".repeat(100);
+        let input = format!("{name};1.0\n{name}y;2.0\n");
+        let Err(AggregateError::Parse(err)) = untrusted(input.as_bytes(), Limits::UNTRUSTED) else {
+            panic!("a 101 byte name was accepted");
+        };
+        assert_eq!(err.offset, name.len() + 5);
+        assert_eq!(err.reason, "station name longer than --max-station-len");
+
+        let stations = untrusted(&input.as_bytes()[..name.len() + 5], Limits::UNTRUSTED).unwrap();
+        assert_eq!(stations[name.as_bytes()].count, 1);
+    }
+
+    #[test]
+    fn garbage_input() {
+        let mut rng = Rng(0x292);
+        for _ in 0..50 {
+            let len = rng.below(2000) as usize;
+            let mut input: Vec<u8> = (0..len).map(|_| rng.below(256) as u8).collect();
+            // Starts with a valid record, so the error has to be found past it
+            input.splice(0..0, *b"Oslo;1.0\n");
+            let Err(AggregateError::Parse(err)) = untrusted(&input, Limits::UNTRUSTED) else {
+                panic!("random bytes were accepted");
+            };
+            assert!(err.offset >= 9);
+            assert!(err.line.len() <= 121);
+        }
+    }
+
+    #[test]
+    fn cardinality_cap() {
+        let input: String = (0..20).map(|i| format!("s{i};1.0\n")).collect();
+        let limits = Limits {
+            max_stations: 19,
+            ..Limits::UNTRUSTED
+        };
+        let Err(AggregateError::Limit(err)) = untrusted(input.as_bytes(), limits) else {
+            panic!("20 stations went over no limit of 19");
+        };
+        assert_eq!((err.limit, err.max), (Limit::Stations, 19));
+        assert!(err.found > 19);
+
+        let limits = Limits {
+            max_stations: 20,
+            ..limits
+        };
+        assert_eq!(untrusted(input.as_bytes(), limits).unwrap().len(), 20);
+    }
+
+    #[test]
+    fn over_limit_file() {
+        let file = TempFile::new("over-limit");
+        std::fs::write(&file.0, "Oslo;10.0\n".repeat(10)).unwrap();
+        let len = std::fs::metadata(&file.0).unwrap().len();
+        let limits = Limits {
+            max_input_size: 99,
+            ..Limits::UNTRUSTED
+        };
+        let err = limits.check_input_size(len).unwrap_err();
+        assert_eq!((err.limit, err.max, err.found), (Limit::InputSize, 99, 100));
+        assert_eq!(
+            err.to_string(),
+            "100 bytes of input exceed --max-input-size 99"
+        );
+        assert!(Limits {
+            max_input_size: 100,
+            ..limits
+        }
+        .check_input_size(len)
+        .is_ok());
+    }
+
+    #[test]
+    fn run_time() {
+        let input = "Oslo;1.0\n".repeat(100);
+        let limits = Limits {
+            max_run_time: Duration::from_secs(60),
+            ..Limits::UNTRUSTED
+        };
+        let options = AggregateOptions {
+            chunk_size: Some(64),
+            limits,
+            // Started long enough ago to be over the limit after the first chunk
+            started: Instant::now().checked_sub(Duration::from_secs(61)),
+            ..Default::default()
+        };
+        let Err(AggregateError::Limit(err)) = aggregate(input.as_bytes(), &options) else {
+            panic!("the run time wasn't checked");
+        };
+        assert_eq!(
+            (err.limit, err.max, err.offset),
+            (Limit::RunTime, 60_000, Some(0))
+        );
+
+        let options = AggregateOptions {
+            started: Some(Instant::now()),
+            ..options
+        };
+        assert_eq!(aggregate(input.as_bytes(), &options).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn capped_output() {
+        let mut output = CappedWriter::new(Vec::new(), 10);
+        output.write_all(b"Oslo;1.0\n").unwrap();
+        let err = output.write_all(b"Rome;2.0\n").unwrap_err();
+        let err = err
+            .into_inner()
+            .unwrap()
+            .downcast::<LimitExceeded>()
+            .unwrap();
+        assert_eq!((err.limit, err.max), (Limit::OutputSize, 10));
+        // What fits is written
+        assert_eq!(output.inner, b"Oslo;1.0\nR");
+    }
+}
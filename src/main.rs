@@ -1,281 +1,819 @@
 use std::{
-    alloc::{alloc, Layout},
     error::Error,
     fs::File,
-    io::{stdout, BufWriter, Write},
-    mem::MaybeUninit,
-    sync::atomic::AtomicUsize,
+    io::{self, stderr, stdout, BufWriter, Read, Seek, SeekFrom, Write},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use hashbrown::HashMap;
-use memmap2::Mmap;
+#[cfg(not(target_family = "wasm"))]
+use memmap2::{Mmap, MmapOptions};
+use rs::{
+    aggregate_unchecked,
+    backend::Backend,
+    cpus::CpuLimit,
+    estimate::{Estimate, Sample},
+    groups::Groups,
+    histogram::Histogram,
+    holes::{self, Hole},
+    limits::{CappedWriter, Limits},
+    merge_maps,
+    names::{self, NameFold},
+    numa::Topology,
+    schedule::{self, Schedule},
+    seed::StationList,
+    state,
+    stats::RunStats,
+    timing::Timing,
+    AggregateOptions, Interrupt, MeasurementRecord, Stations,
+};
+
+use interrupt::INTERRUPT;
+
+mod cli;
+mod diff;
+mod follow;
+mod interrupt;
+#[cfg(feature = "serve")]
+mod serve;
+
+use cli::{Options, StatsFormat};
+use diff::Diff;
+
+/// Replaces min/mean/max with the percentile rank (in tenths of a percent) of that value among the
+/// same column of all stations, and rounds counts up to a power of two, so the output keeps its
+/// shape without revealing any of the measured values.
+fn redact_values(stations: &mut [(Box<[u8]>, MeasurementRecord)]) {
+    let n = stations.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    let mut percentiles = |key: fn(&MeasurementRecord) -> i64| {
+        order.sort_unstable_by_key(|&i| key(&stations[i].1));
+        let mut ranks = vec![0; n];
+        let mut rank = 0;
+        for (sorted, &i) in order.iter().enumerate() {
+            // Ties share the rank of the first station with that value
+            if sorted > 0 && key(&stations[order[sorted - 1]].1) != key(&stations[i].1) {
+                rank = sorted;
+            }
+            // rank < n, so this is always below 100.0%
+            ranks[i] = (rank * 1000 / n) as i16;
+        }
+        ranks
+    };
+    let min = percentiles(|rec| rec.min as i64);
+    let mean = percentiles(MeasurementRecord::mean);
+    let max = percentiles(|rec| rec.max as i64);
+    for (i, (_, rec)) in stations.iter_mut().enumerate() {
+        let count = rec.count.next_power_of_two();
+        *rec = MeasurementRecord {
+            count,
+            sum: mean[i] as i64 * count as i64,
+            min: min[i],
+            max: max[i],
+            histogram: None,
+        };
+    }
+}
 
-const BUMP_CAP: usize = 1024 * 1024;
-const _: () = assert!(BUMP_CAP > 1024);
-// const SINGLE_BUMP_MAX: usize = 1024;
+/// Window size used when the platform can't map the whole input
+const WINDOW_32BIT: usize = 256 * 1024 * 1024;
 
-const WORK_CHUNK: usize = 1024 * 1024 * 2;
+/// Aggregates bytes `start..len` of `file`, mapping at most `window` bytes of it at a time and
+/// never touching the `holes`. Returns the merged map and the number of bytes parsed.
+///
+/// Every window but the last ends after its last complete record and the next one is mapped
+/// starting right after it, so a record split by a window edge is parsed whole from the next
+/// window. Windows are unmapped before the next one is mapped, which bounds the resident size of
+/// the input to one window. A window without any newline is grown until it has one.
+#[allow(clippy::too_many_arguments)]
+fn aggregate_file(
+    file: &File,
+    start: u64,
+    len: u64,
+    window: u64,
+    holes: &[Hole],
+    populate: bool,
+    options: &AggregateOptions,
+    timing: &mut Timing,
+) -> Result<(Stations, u64), Box<dyn Error>> {
+    let mut map = Stations::new();
+    let mut parsed = 0;
+    let mut offset = start;
+    while offset < len {
+        if options.interrupt.is_some_and(Interrupt::is_requested) {
+            break;
+        }
+        if let Some(hole) = holes
+            .iter()
+            .find(|hole| (hole.offset..hole.offset + hole.len).contains(&offset))
+        {
+            offset = hole.offset + hole.len;
+            continue;
+        }
+        let mut size = window.clamp(1, len - offset);
+        let (data, cut) = loop {
+            let data = Timing::time(&mut timing.map, || {
+                map_window(file, offset, size as usize, populate)
+            })?;
+            let end = offset + size;
+            if end == len {
+                break (data, size as usize);
+            }
+            // A window ending inside a hole is cut where the hole starts
+            if let Some(hole) = holes
+                .iter()
+                .find(|hole| hole.offset < end && end < hole.offset + hole.len)
+            {
+                break (data, (hole.offset - offset) as usize);
+            }
+            match data.iter().rposition(|&b| b == b'\n') {
+                Some(newline) => break (data, newline + 1),
+                None => size = (size * 2).min(len - offset),
+            }
+        };
+        for segment in holes::data_segments(&data[..cut], offset, holes) {
+            let start = offset as usize + segment.start;
+            parsed += segment.len() as u64;
+            // SAFETY: without --strict the input is assumed to be well-formed, as the usage says
+            let segment = Timing::time(&mut timing.parse, || unsafe {
+                aggregate_unchecked(&data[segment], options)
+            })
+            .map_err(|err| err.shifted(start))?;
+            Timing::time(&mut timing.merge, || merge_maps(&mut map, segment));
+        }
+        offset += cut as u64;
+    }
+    Ok((map, parsed))
+}
 
-type Map<K, V> = HashMap<K, V>;
+/// `size` bytes of `file` from `offset`
+#[cfg(not(target_family = "wasm"))]
+fn map_window(file: &File, offset: u64, size: usize, populate: bool) -> io::Result<Mmap> {
+    let mut options = MmapOptions::new();
+    options.offset(offset).len(size);
+    if populate {
+        options.populate();
+    }
+    unsafe { options.map(file) }
+}
 
-#[derive(Debug, Clone, Copy, Default)]
-struct MeasurementRecord {
-    count: usize,
-    sum: i64,
-    min: i16,
-    max: i16,
+/// `size` bytes of `file` from `offset`, read into memory since there is no mmap on wasm
+#[cfg(target_family = "wasm")]
+fn map_window(mut file: &File, offset: u64, size: usize, _populate: bool) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; size];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
 }
 
-struct BumpAlloc {
-    len: usize,
-    ptr: *mut u8,
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
 }
 
-impl BumpAlloc {
-    #[inline]
-    pub fn new() -> Self {
-        Self {
-            len: 0,
-            ptr: new_chunk(),
-        }
-    }
-    #[inline]
-    pub fn alloc(&mut self, len: usize) -> &'static mut [MaybeUninit<u8>] {
-        unsafe {
-            // // SAFETY: Technically required for the case where the length wouldn't fit in the
-            // // backing buffer, but we know all stations will be under 100 bytes
-            //
-            // if len > SINGLE_BUMP_MAX {
-            //     let ptr = alloc(Layout::array::<u8>(len).unwrap());
-            //     return std::slice::from_raw_parts_mut(ptr as *mut MaybeUninit<u8>, len);
-            // }
-            if (self.len + len) > BUMP_CAP {
-                self.ptr = new_chunk();
-                self.len = 0;
+fn run() -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(std::env::args().skip(1))?;
+    let cpus = CpuLimit::detect();
+    let available = cpus.threads();
+    let mut threads = match options.threads {
+        // There are no threads to spawn on wasm
+        _ if cfg!(target_family = "wasm") => 1,
+        0 => available,
+        threads => threads,
+    };
+    // More workers than cores only adds contention, a few times more is most likely a typo
+    if threads > available * 4 {
+        eprintln!("warning: --threads {threads} is far more than the {available} available cores");
+    }
+
+    // Load the mapping before the long part of the run so a bad file fails fast
+    let groups = options
+        .group_by
+        .as_deref()
+        .map(|path| {
+            let unmapped = (!options.drop_unmapped).then_some(options.default_group.as_str());
+            Groups::load(path, unmapped)
+        })
+        .transpose()?;
+    let saved: Vec<Stations> = options
+        .merge_state
+        .iter()
+        .map(|path| state::load(path).map_err(|err| format!("{path}: {err}")))
+        .collect::<Result<_, _>>()?;
+
+    let mut timing = Timing {
+        cpus: Some(cpus),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    let file = File::open(&options.path).map_err(|err| format!("{}: {err}", options.path))?;
+    let metadata = file.metadata()?;
+    timing.open = started.elapsed();
+    // Pipes report a length of 0 and can't be mapped
+    if !metadata.is_file() {
+        return Err(format!("{}: not a regular file", options.path).into());
+    }
+    let len = metadata.len();
+    let limits = options.limits();
+    limits
+        .check_input_size(len)
+        .map_err(|err| format!("{}: {err}", options.path))?;
+
+    let schedule = if let Some(path) = &options.replay_schedule {
+        let (recorded_len, passes) =
+            schedule::load(path).map_err(|err| format!("{path}: {err}"))?;
+        if recorded_len != len {
+            return Err(format!(
+                "{path}: recorded on a {recorded_len} byte input, {} has {len} bytes",
+                options.path
+            )
+            .into());
+        }
+        // Replays run on as many workers as the recording did, whatever this machine has
+        if let Some(pass) = passes.first() {
+            threads = pass.threads;
+        }
+        Some(Arc::new(Schedule::replay(passes)))
+    } else {
+        options
+            .record_schedule
+            .as_ref()
+            .map(|_| Arc::new(Schedule::record()))
+    };
+
+    // In follow mode the last line may still be being written, it is parsed by the next pass
+    let end = match options.sample_bytes {
+        _ if options.follow => complete_len(&file, 0, len)?,
+        Some(sample) if (sample as u64) < len => complete_len(&file, 0, sample as u64)?,
+        _ => len,
+    };
+    let holes = find_holes(&file, &options.path, options.strict, 0, end)?;
+
+    // A 32-bit address space can't map large inputs whole
+    let window = options
+        .max_memory
+        .or(cfg!(target_pointer_width = "32").then_some(WINDOW_32BIT))
+        .map_or(len, |window| window as u64);
+    let aggregate_options = AggregateOptions {
+        threads,
+        chunk_size: options.chunk_size,
+        strict: options.strict,
+        delimiter: options.delimiter,
+        decimal_comma: options.decimal_comma,
+        quoted_names: options.quoted_names,
+        shadow_check: options.shadow_check(),
+        prefault: options.prefault_parallel,
+        schedule: schedule.clone(),
+        limits,
+        started: Some(started),
+        histogram: options.histogram,
+        stats: (options.stats || options.timing).then(Default::default),
+        numa: options.numa.then(Topology::detect).transpose()?.flatten(),
+        sample: options.sample(),
+        table: options.table,
+        interrupt: Some(&INTERRUPT),
+        backend: options.backend,
+        stations: options
+            .stations_file
+            .as_deref()
+            .map(|path| StationList::load(path, options.unknown_stations))
+            .transpose()?
+            .map(Arc::new),
+        names: (options.trim || options.fold_case.is_some())
+            .then(|| Arc::new(NameFold::new(options.trim, options.fold_case))),
+    };
+    let names = aggregate_options.names.as_deref();
+    #[cfg(feature = "rayon")]
+    if options.backend == Backend::Rayon {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
+    interrupt::catch();
+    let estimate = if options.estimate || options.estimate_and_run {
+        let samples = calibrate(&file, end, &holes, &aggregate_options)?;
+        let estimate = Estimate::new(&samples, end, threads, window)
+            .ok_or_else(|| format!("{}: no complete lines to calibrate on", options.path))?;
+        eprintln!("{estimate}");
+        if !options.estimate_and_run {
+            return Ok(());
+        }
+        Some(estimate)
+    } else {
+        None
+    };
+    let started = Instant::now();
+    let result = aggregate_file(
+        &file,
+        0,
+        end,
+        window,
+        &holes,
+        options.populate,
+        &aggregate_options,
+        &mut timing,
+    );
+    if let Some(schedule) = &schedule {
+        if let Some(path) = &options.record_schedule {
+            schedule::save(path, len, &schedule.recorded())
+                .map_err(|err| format!("{path}: {err}"))?;
+        }
+        // An interrupted replay never gets to the later passes
+        if result.is_ok() && !INTERRUPT.is_requested() {
+            schedule.finish()?;
+        }
+    }
+    let (mut map, parsed) = result?;
+    let mut elapsed = started.elapsed();
+    // An interrupted run didn't get to every hole
+    if !holes.is_empty() && !INTERRUPT.is_requested() {
+        eprintln!(
+            "warning: skipped {} bytes of holes and zero padding in {}",
+            end - parsed,
+            options.path
+        );
+    }
+    warn_skipped(&aggregate_options, &options);
+
+    Timing::time(&mut timing.merge, || {
+        for saved in saved {
+            merge_maps(&mut map, saved);
+        }
+    });
+    let interrupted = warn_interrupted(end, &options.path);
+    let sampled = options.is_sampled(end < len).then(|| {
+        let parsed = INTERRUPT.processed();
+        eprintln!(
+            "note: sampled {parsed} of {len} bytes ({:.2}%), the results are approximate",
+            parsed as f64 * 100.0 / len.max(1) as f64
+        );
+        len
+    });
+    #[cfg(feature = "serve")]
+    if options.serve {
+        if interrupted {
+            std::process::exit(interrupt::EXIT_CODE);
+        }
+        limits.check_stations(map.len(), None)?;
+        let stations = prepare(map, groups.as_ref(), &options, names, &mut timing)?;
+        return serve::serve(&options.listen, options.precision, stations, || {
+            let map = aggregate_path(&options.path, true, &options, &aggregate_options, window)?;
+            limits.check_stations(map.len(), None)?;
+            prepare(
+                map,
+                groups.as_ref(),
+                &options,
+                names,
+                &mut Timing::default(),
+            )
+        });
+    }
+    if let Some(new_path) = options.other_path.as_deref().filter(|_| options.diff) {
+        let new = aggregate_path(new_path, false, &options, &aggregate_options, window)?;
+        if interrupted || INTERRUPT.is_requested() {
+            eprintln!("warning: interrupted, not comparing partial results");
+            std::process::exit(interrupt::EXIT_CODE);
+        }
+        limits.check_stations(map.len(), None)?;
+        limits.check_stations(new.len(), None)?;
+        let old = prepare(map, groups.as_ref(), &options, names, &mut timing)?;
+        let new = prepare(new, groups.as_ref(), &options, names, &mut timing)?;
+        let diff = Diff::new(&old, &new, options.threshold);
+        let output = CappedWriter::new(stdout().lock(), limits.max_output_size);
+        match options.diff_format {
+            StatsFormat::Text => {
+                diff.write_text(output, &options.path, new_path, options.precision)?
             }
-            let ptr = self.ptr.add(self.len);
-            self.len += len;
-            std::slice::from_raw_parts_mut(ptr as *mut MaybeUninit<u8>, len)
+            StatsFormat::Json => diff.write_json(output, options.precision)?,
         }
+        if !diff.is_empty() {
+            std::process::exit(1);
+        }
+        return Ok(());
     }
-    pub fn alloc_slice(&mut self, slice: &[u8]) -> &'static mut [u8] {
-        unsafe {
-            let bytes = self.alloc(slice.len());
-            bytes
-                .as_mut_ptr()
-                .copy_from_nonoverlapping(slice.as_ptr() as *const MaybeUninit<u8>, slice.len());
-            std::mem::transmute(bytes)
+    if !options.follow || interrupted {
+        let stations = map.values().filter(|record| record.count != 0).count();
+        report(map, &limits, groups.as_ref(), &options, names, &mut timing)?;
+        if let Some(estimate) = estimate {
+            eprintln!(
+                "actual time:        {:.2} s, {:.2}x the estimate",
+                elapsed.as_secs_f64(),
+                elapsed.as_secs_f64() / estimate.seconds[1]
+            );
+        }
+        print_stats(
+            &aggregate_options,
+            elapsed,
+            stations,
+            sampled,
+            &mut timing,
+            &options,
+        )?;
+        if interrupted {
+            std::process::exit(interrupt::EXIT_CODE);
         }
+        return Ok(());
     }
-}
 
-fn new_chunk() -> *mut u8 {
-    unsafe {
-        let ptr = alloc(Layout::array::<u8>(BUMP_CAP).unwrap_unchecked());
-        if ptr.is_null() {
-            std::alloc::handle_alloc_error(Layout::array::<u8>(BUMP_CAP).unwrap_unchecked())
+    report(
+        map.clone(),
+        &limits,
+        groups.as_ref(),
+        &options,
+        names,
+        &mut timing,
+    )?;
+    let mut offset = end;
+    let mut interrupted = false;
+    while follow::sleep(options.interval) {
+        let len = file.metadata()?.len();
+        if len < offset {
+            return Err(format!(
+                "{}: truncated from {offset} to {len} bytes while following it",
+                options.path
+            )
+            .into());
+        }
+        let end = complete_len(&file, offset, len)?;
+        if end == offset {
+            continue;
+        }
+        limits
+            .check_input_size(end)
+            .map_err(|err| format!("{}: {err}", options.path))?;
+        let holes = find_holes(&file, &options.path, options.strict, offset, end)?;
+        let started = Instant::now();
+        // Every pass gets its own --timeout
+        let pass_options = AggregateOptions {
+            started: Some(started),
+            ..aggregate_options.clone()
         };
-        ptr
+        let (appended, _) = aggregate_file(
+            &file,
+            offset,
+            end,
+            window,
+            &holes,
+            options.populate,
+            &pass_options,
+            &mut timing,
+        )?;
+        elapsed += started.elapsed();
+        merge_maps(&mut map, appended);
+        offset = end;
+        // The final report below covers an interrupted pass
+        interrupted = warn_interrupted(end, &options.path);
+        if interrupted {
+            break;
+        }
+        // Reports are separated by a blank line
+        println!();
+        report(
+            map.clone(),
+            &limits,
+            groups.as_ref(),
+            &options,
+            names,
+            &mut timing,
+        )?;
+    }
+    // Interrupted, end with the final state
+    warn_skipped(&aggregate_options, &options);
+    println!();
+    let stations = map.values().filter(|record| record.count != 0).count();
+    report(map, &limits, groups.as_ref(), &options, names, &mut timing)?;
+    let sampled = options.is_sampled(false).then_some(offset);
+    print_stats(
+        &aggregate_options,
+        elapsed,
+        stations,
+        sampled,
+        &mut timing,
+        &options,
+    )?;
+    if interrupted {
+        std::process::exit(interrupt::EXIT_CODE);
     }
+    Ok(())
 }
 
-fn work(data: &[u8], cursor: &AtomicUsize) -> Map<&'static [u8], MeasurementRecord> {
-    #[inline(always)]
-    fn process_chunk(
-        data: &[u8],
-        mut start: usize,
-        end: usize,
-        map: &mut Map<&'static [u8], MeasurementRecord>,
-    ) {
-        let mut bump = BumpAlloc::new();
-        if start != 0 {
-            let Some((first_newline, _)) = data
+/// Opens and aggregates the file at `path`, for `serve` to refresh its results and `diff` to
+/// aggregate its second input. A `growing` file is only aggregated up to its last complete line.
+fn aggregate_path(
+    path: &str,
+    growing: bool,
+    options: &Options,
+    aggregate_options: &AggregateOptions,
+    window: u64,
+) -> Result<Stations, Box<dyn Error>> {
+    let file = File::open(path).map_err(|err| format!("{path}: {err}"))?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Err(format!("{path}: not a regular file").into());
+    }
+    let len = metadata.len();
+    aggregate_options
+        .limits
+        .check_input_size(len)
+        .map_err(|err| format!("{path}: {err}"))?;
+    let end = match options.sample_bytes {
+        Some(sample) if (sample as u64) < len => complete_len(&file, 0, sample as u64)?,
+        _ if growing => complete_len(&file, 0, len)?,
+        _ => len,
+    };
+    let holes = find_holes(&file, path, options.strict, 0, end)?;
+    // Every input gets its own --timeout
+    let aggregate_options = AggregateOptions {
+        started: Some(Instant::now()),
+        ..aggregate_options.clone()
+    };
+    let (map, _) = aggregate_file(
+        &file,
+        0,
+        end,
+        window,
+        &holes,
+        options.populate,
+        &aggregate_options,
+        &mut Timing::default(),
+    )?;
+    Ok(map)
+}
+
+/// Warns that the results only cover part of the first `len` bytes if Ctrl-C was pressed while
+/// aggregating them, and returns whether it was
+fn warn_interrupted(len: u64, path: &str) -> bool {
+    if !INTERRUPT.is_requested() {
+        return false;
+    }
+    eprintln!(
+        "warning: interrupted, the results only cover {} of {len} bytes of {path}",
+        INTERRUPT.processed()
+    );
+    true
+}
+
+/// Warns about the readings left out by `--stations-strict=skip` so far
+fn warn_skipped(aggregate_options: &AggregateOptions, options: &Options) {
+    let skipped = aggregate_options
+        .stations
+        .as_ref()
+        .map_or(0, |stations| stations.skipped());
+    if let (Some(path), 1..) = (&options.stations_file, skipped) {
+        eprintln!("warning: skipped {skipped} readings of stations missing from {path}");
+    }
+}
+
+/// Times single-threaded parses of up to 8 evenly spaced 1 MiB samples of the first `len` bytes
+/// of `file` for `--estimate`. Every sample is mapped on its own, so reading it from storage is
+/// part of its time. Samples touching a hole are skipped.
+fn calibrate(
+    file: &File,
+    len: u64,
+    holes: &[Hole],
+    options: &AggregateOptions,
+) -> Result<Vec<Sample>, Box<dyn Error>> {
+    const SAMPLES: u64 = 8;
+    const SAMPLE_LEN: u64 = 1 << 20;
+    let options = AggregateOptions {
+        threads: 1,
+        chunk_size: None,
+        prefault: false,
+        schedule: None,
+        stats: None,
+        numa: None,
+        sample: None,
+        interrupt: None,
+        backend: Backend::Native,
+        // The spellings of the samples aren't the first ones of the file
+        names: options
+            .names
+            .as_ref()
+            .map(|names| Arc::new(NameFold::new(names.trim, names.fold_case))),
+        ..options.clone()
+    };
+    let mut seen = Stations::new();
+    let mut samples = Vec::new();
+    for i in 0..SAMPLES {
+        let start = len * i / SAMPLES;
+        let end = (start + SAMPLE_LEN).min(len);
+        if start >= end
+            || holes
                 .iter()
-                .enumerate()
-                .take(end)
-                .skip(start)
-                .find(|(_, &b)| b == b'\n')
-            else {
-                return;
-            };
-            // the +1 is necessary to skip the first newline
-            start = first_newline + 1;
-        }
-        let end = data
-            .iter()
-            .enumerate()
-            .skip(end)
-            .find(|(_, &b)| b == b'\n')
-            .map(|(end, _)| end)
-            .unwrap_or(data.len());
-
-        let mut data = &data[start..end];
-
-        // _ = unsafe { dbg!(thread, std::str::from_utf8_unchecked(data)) };
-
-        let mut handle_entry = |station: &[u8], value: i16| {
-            // let station: &'static [u8] =
-            // _ = unsafe { dbg!(std::str::from_utf8_unchecked(station), value) };
-            map.raw_entry_mut()
-                .from_key(station)
-                .and_modify(|_, rec| {
-                    rec.count += 1;
-                    rec.sum += value as i64;
-                    rec.min = rec.min.min(value);
-                    rec.max = rec.max.max(value);
-                })
-                .or_insert_with(|| {
-                    (
-                        bump.alloc_slice(station),
-                        MeasurementRecord {
-                            count: 1,
-                            sum: value as i64,
-                            min: value,
-                            max: value,
-                        },
-                    )
-                });
+                .any(|hole| hole.offset < end && start < hole.offset + hole.len)
+        {
+            continue;
+        }
+        let data = map_window(file, start, (end - start) as usize, false)?;
+        // Only whole lines are parsed
+        let first = match start {
+            0 => Some(0),
+            _ => data
+                .iter()
+                .position(|&b| b == b'\n')
+                .map(|newline| newline + 1),
         };
-        while !data.is_empty() {
-            // Hamburg;12.0...
-            let semicolon = data.iter().position(|&b| b == b';');
-            #[cfg(debug_assertions)]
-            let semicolon = semicolon.unwrap();
-            #[cfg(not(debug_assertions))]
-            let semicolon = unsafe { semicolon.unwrap_unchecked() };
-
-            #[cfg(debug_assertions)]
-            let station = &data[..semicolon];
-            #[cfg(not(debug_assertions))]
-            let station = unsafe { data.get_unchecked(..semicolon) };
-            #[cfg(debug_assertions)]
-            let rem = &data[semicolon + 1..];
-            #[cfg(not(debug_assertions))]
-            let rem = unsafe { data.get_unchecked(semicolon + 1..) };
-            data = rem;
-
-            let dot = data.iter().position(|&b| b == b'.');
-            #[cfg(debug_assertions)]
-            let dot = dot.unwrap();
-            #[cfg(not(debug_assertions))]
-            let dot = unsafe { dot.unwrap_unchecked() };
-
-            #[cfg(debug_assertions)]
-            let before_dot = &data[..dot];
-            #[cfg(not(debug_assertions))]
-            let before_dot = unsafe { data.get_unchecked(..dot) };
-            #[cfg(debug_assertions)]
-            let after_dot = data[dot + 1];
-            #[cfg(not(debug_assertions))]
-            let after_dot = unsafe { data.get_unchecked(dot + 1) };
-
-            let value = match before_dot.len() {
-                1 => {
-                    before_dot[0].wrapping_sub(b'0') as i16 * 10
-                        + after_dot.wrapping_sub(b'0') as i16
-                }
-                2 => {
-                    if before_dot[0] == b'-' {
-                        -(before_dot[1].wrapping_sub(b'0') as i16) * 10
-                            - after_dot.wrapping_sub(b'0') as i16
-                    } else {
-                        (before_dot[0].wrapping_sub(b'0') as i16 * 100)
-                            + (before_dot[1].wrapping_sub(b'0') as i16 * 10)
-                            + after_dot.wrapping_sub(b'0') as i16
-                    }
-                }
-                3 => {
-                    -(before_dot[1].wrapping_sub(b'0') as i16 * 100
-                        + before_dot[2].wrapping_sub(b'0') as i16 * 10
-                        + after_dot.wrapping_sub(b'0') as i16)
-                }
-                _ => {
-                    #[cfg(debug_assertions)]
-                    unreachable!();
-                    #[cfg(not(debug_assertions))]
-                    unsafe {
-                        std::hint::unreachable_unchecked()
-                    };
-                }
-            };
+        let last = match end == len {
+            true => Some(data.len()),
+            false => data
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map(|newline| newline + 1),
+        };
+        let (Some(first), Some(last)) = (first, last) else {
+            continue;
+        };
+        if first >= last {
+            continue;
+        }
+        let started = Instant::now();
+        // SAFETY: without --strict the input is assumed to be well-formed, as the usage says
+        let map = unsafe { aggregate_unchecked(&data[first..last], &options) }
+            .map_err(|err| err.shifted(start as usize + first))?;
+        let elapsed = started.elapsed();
+        merge_maps(&mut seen, map);
+        samples.push(Sample {
+            bytes: last - first,
+            elapsed,
+            // Stations of --stations-file count once they are found
+            stations: seen.values().filter(|record| record.count != 0).count(),
+        });
+    }
+    Ok(samples)
+}
+
+/// Prints the `--stats` summary to stderr, `elapsed` only counts the time spent aggregating
+fn print_stats(
+    aggregate_options: &AggregateOptions,
+    elapsed: Duration,
+    stations: usize,
+    sampled: Option<u64>,
+    timing: &mut Timing,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    let Some(workers) = &aggregate_options.stats else {
+        return Ok(());
+    };
+    let mut stats = RunStats::new(&workers.lock().unwrap(), elapsed, stations);
+    stats.numa = aggregate_options.numa.clone();
+    stats.sampled = sampled;
+    stats.cpus = timing.cpus;
+    if options.stats {
+        match options.stats_format {
+            StatsFormat::Text => stats.write_text(stderr().lock())?,
+            StatsFormat::Json => stats.write_json(stderr().lock())?,
+        }
+    }
+    if options.timing {
+        timing.busy = stats.threads.iter().map(|thread| thread.busy).collect();
+        match options.timing_format {
+            StatsFormat::Text => timing.write_text(stderr().lock())?,
+            StatsFormat::Json => timing.write_json(stderr().lock())?,
+        }
+    }
+    Ok(())
+}
+
+/// Finds the holes of `file` and warns about those in `start..end`, or fails on the first one
+/// with `--strict`. Holes in sparse files read as zeros, only the regions around them are parsed.
+fn find_holes(
+    file: &File,
+    path: &str,
+    strict: bool,
+    start: u64,
+    end: u64,
+) -> Result<Vec<Hole>, Box<dyn Error>> {
+    let holes: Vec<_> = holes::find_holes(file, end)?
+        .into_iter()
+        .filter(|hole| hole.offset + hole.len > start)
+        .collect();
+    if let Some(hole) = holes.first().filter(|_| strict) {
+        return Err(format!("{path}: hole of {} bytes at byte {}", hole.len, hole.offset).into());
+    }
+    const SHOWN: usize = 8;
+    for hole in holes.iter().take(SHOWN) {
+        eprintln!(
+            "warning: skipping hole of {} bytes at byte {}",
+            hole.len, hole.offset
+        );
+    }
+    if holes.len() > SHOWN {
+        eprintln!("warning: and {} more holes", holes.len() - SHOWN);
+    }
+    Ok(holes)
+}
 
-            handle_entry(station, value);
+/// Writes `n` units of `10^-decimals` with `decimals` fractional digits, `format_fixed` covers
+/// the common case of tenths without going through `fmt`
+fn write_scaled(mut output: impl Write, n: i64, decimals: u32) -> io::Result<()> {
+    let scale = 10u64.pow(decimals);
+    let sign = if n < 0 { "-" } else { "" };
+    let (whole, fraction) = (n.unsigned_abs() / scale, n.unsigned_abs() % scale);
+    write!(
+        output,
+        "{sign}{whole}.{fraction:0width$}",
+        width = decimals as usize
+    )
+}
 
-            let Some(remainder) = data.get(dot + 3..) else {
-                break;
-            };
-            data = remainder;
+/// Writes `bytes` as a JSON string, invalid UTF-8 is replaced with U+FFFD
+fn write_json_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.push(b'"');
+    for c in String::from_utf8_lossy(bytes).chars() {
+        match c {
+            '"' => out.extend_from_slice(b"\\\""),
+            '\\' => out.extend_from_slice(b"\\\\"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
         }
     }
+    out.push(b'"');
+}
 
-    let mut map: Map<&[u8], MeasurementRecord> = Map::with_capacity(1024 * 8);
-    loop {
-        let offset = cursor.fetch_add(WORK_CHUNK, std::sync::atomic::Ordering::Release);
-        let end = offset + WORK_CHUNK;
-        let end = end.min(data.len());
-        if offset >= data.len() {
-            break;
+/// The offset right after the last newline in `start..len` of `file`, or `start` if there is none
+fn complete_len(mut file: &File, start: u64, len: u64) -> io::Result<u64> {
+    const BLOCK: u64 = 64 * 1024;
+    let mut buf = vec![0; BLOCK as usize];
+    let mut end = len;
+    while end > start {
+        let block_start = end.saturating_sub(BLOCK).max(start);
+        let block = &mut buf[..(end - block_start) as usize];
+        file.seek(SeekFrom::Start(block_start))?;
+        file.read_exact(block)?;
+        if let Some(newline) = block.iter().rposition(|&b| b == b'\n') {
+            return Ok(block_start + newline as u64 + 1);
         }
-        process_chunk(data, offset, end, &mut map)
+        end = block_start;
     }
-    map
+    Ok(start)
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let threads = std::thread::available_parallelism().unwrap().get();
-
-    // let path = std::env::args().nth(1);
-    // let path = path.as_deref().unwrap_or("measurements.txt");
-    let path = "measurements.txt";
-
-    let file = File::open(path).unwrap();
-    let data = unsafe { Mmap::map(&file).unwrap() };
-    let data = &data[..];
-
-    let cursor = AtomicUsize::new(0);
-
-    let map = std::thread::scope(|s| {
-        let mut handles = Vec::with_capacity(threads);
-        let cursor = &cursor;
-        for _ in 1..threads {
-            let thread = s.spawn(move || work(data, cursor));
-            handles.push(thread);
-        }
-        let mut map = work(data, cursor);
-        handles.into_iter().for_each(|h| {
-            let res = h.join().unwrap();
-            res.into_iter().for_each(|(station, data)| {
-                map.entry(station)
-                    .and_modify(|rec| {
-                        rec.count += data.count;
-                        rec.sum += data.sum;
-                        rec.max = rec.max.max(data.max);
-                        rec.min = rec.min.min(data.min);
-                    })
-                    .or_insert(data);
-            })
-        });
-        map
-    });
+/// Stations in the output order
+type Results = Vec<(Box<[u8]>, MeasurementRecord)>;
+
+/// Normalizes names, groups stations and sorts them in the output order. `names` holds the
+/// spellings found while parsing.
+fn prepare(
+    map: Stations,
+    groups: Option<&Groups>,
+    options: &Options,
+    names: Option<&NameFold>,
+    timing: &mut Timing,
+) -> Result<Results, Box<dyn Error>> {
+    let started = Instant::now();
+    let mut map = map;
+    // Stations of --stations-file that were never found
+    if !options.emit_empty {
+        map.retain(|_, record| record.count != 0);
+    }
+    let mut map = names::normalize_stations(map, options.name_policy(), names)?;
+    if let Some(groups) = groups {
+        map = groups.apply(map);
+    }
+    timing.merge += started.elapsed();
     let mut stations: Vec<_> = map.into_iter().collect();
-    stations.sort_unstable_by_key(|&(s, _)| s);
-    let mut output = BufWriter::with_capacity(1024 * 512, stdout().lock());
-    for (
-        station,
-        MeasurementRecord {
-            count,
-            sum,
-            min,
-            max,
-        },
-    ) in stations
-    {
-        let mean = if sum ^ (count as i64) >= 0 {
-            (sum + ((count as i64) / 2)) / (count as i64)
-        } else {
-            (sum - ((count as i64) / 2)) / (count as i64)
-        };
-        output.write_all(station)?;
+    Timing::time(&mut timing.sort, || {
+        names::sort_stations(&mut stations, options.collate);
+        if options.redact_values {
+            redact_values(&mut stations);
+        }
+    });
+    Ok(stations)
+}
+
+/// Checks the merged aggregates against the limits, saves them with `--save-state`, and prints
+/// them after normalizing names and grouping stations
+fn report(
+    map: Stations,
+    limits: &Limits,
+    groups: Option<&Groups>,
+    options: &Options,
+    names: Option<&NameFold>,
+    timing: &mut Timing,
+) -> Result<(), Box<dyn Error>> {
+    // Every window and saved state can be within the limit on its own
+    limits.check_stations(map.len(), None)?;
+    if let Some(path) = &options.save_state {
+        state::save(path, &map).map_err(|err| format!("{path}: {err}"))?;
+    }
+
+    let stations = prepare(map, groups, options, names, timing)?;
+    let started = Instant::now();
+    let output = CappedWriter::new(stdout().lock(), limits.max_output_size);
+    let mut output = BufWriter::with_capacity(1024 * 512, output);
+    for (station, record) in stations {
+        output.write_all(&station)?;
+        if record.count == 0 {
+            output.write_all(b";;;")?;
+            for count in record.histogram.iter().flat_map(Histogram::counts) {
+                write!(output, ";{count}")?;
+            }
+            output.write_all(b"\n")?;
+            continue;
+        }
+        let mean = record.mean_at(options.precision);
         fn format_fixed(buf: &mut [u8; 5], n: i64) -> &[u8] {
             let todigit = |n| n as u8 + b'0';
             match n {
@@ -322,17 +860,198 @@ fn main() -> Result<(), Box<dyn Error>> {
         let mut buf = [0; 5];
         _ = output.write(b";")?;
 
-        let min = format_fixed(&mut buf, min as i64);
+        let min = format_fixed(&mut buf, record.min as i64);
         output.write_all(min)?;
         _ = output.write(b";")?;
 
-        let mean = format_fixed(&mut buf, mean);
-        output.write_all(mean)?;
+        if options.precision == 1 {
+            let mean = format_fixed(&mut buf, mean);
+            output.write_all(mean)?;
+        } else {
+            write_scaled(&mut output, mean, options.precision)?;
+        }
         _ = output.write(b";")?;
 
-        let max = format_fixed(&mut buf, max as i64);
+        let max = format_fixed(&mut buf, record.max as i64);
         output.write_all(max)?;
+        for count in record.histogram.iter().flat_map(Histogram::counts) {
+            write!(output, ";{count}")?;
+        }
         _ = output.write(b"\n")?;
     }
+    output.flush()?;
+    timing.write += started.elapsed();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fmt::Write as _, path::PathBuf};
+
+    use super::*;
+
+    /// A path in the temporary directory, removed again when dropped
+    pub(crate) struct TempFile(pub(crate) PathBuf);
+
+    impl TempFile {
+        pub(crate) fn new(name: &str) -> Self {
+            let file = format!("1brc-bin-{}-{name}", std::process::id());
+            Self(std::env::temp_dir().join(file))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn tiny_windows_match_one_mapping() {
+        let mut input = String::new();
+        for i in 0..500u32 {
+            // Names of 1 to 30 bytes, so records straddle the edges of every window size
+            let name = "abcdefghijklmnopqrstuvwxyz0123".get(..1 + (i * 7 % 30) as usize);
+            let value = (i * 37 % 1999) as i32 - 999;
+            let sign = if value < 0 { "-" } else { "" };
+            let value = value.abs();
+            writeln!(
+                input,
+                "{};{sign}{}.{}",
+                name.unwrap(),
+                value / 10,
+                value % 10
+            )
+            .unwrap();
+        }
+        let path = TempFile::new("windows");
+        std::fs::write(&path.0, &input).unwrap();
+        let file = File::open(&path.0).unwrap();
+        let len = input.len() as u64;
+        let options = AggregateOptions {
+            threads: 2,
+            chunk_size: Some(16),
+            ..Default::default()
+        };
+        let aggregate = |window| {
+            aggregate_file(
+                &file,
+                0,
+                len,
+                window,
+                &[],
+                false,
+                &options,
+                &mut Timing::default(),
+            )
+            .unwrap()
+        };
+        let (whole, parsed) = aggregate(len);
+        assert_eq!((whole.len(), parsed), (30, len));
+        for window in [1, 2, 7, 13, 64, 4096, len - 1] {
+            assert_eq!(aggregate(window), (whole.clone(), len), "window {window}");
+        }
+    }
+
+    /// The mean of `readings` in tenths at `decimals`, as it is printed
+    fn mean_text(readings: &[i16], decimals: u32) -> String {
+        let mut record = MeasurementRecord::first(readings[0], 0);
+        for &value in &readings[1..] {
+            record.add(value);
+        }
+        let mut out = Vec::new();
+        write_scaled(&mut out, record.mean_at(decimals), decimals).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn precise_means() {
+        assert_eq!(mean_text(&[10, 11], 2), "1.05");
+        assert_eq!(mean_text(&[10, 11], 1), "1.1");
+        assert_eq!(mean_text(&[10, 11], 3), "1.050");
+        assert_eq!(mean_text(&[10, 11, 11], 3), "1.067");
+        assert_eq!(mean_text(&[0, 0, 1], 2), "0.03");
+        // Halves round away from zero, on both sides
+        assert_eq!(mean_text(&[-10, -11], 2), "-1.05");
+        assert_eq!(mean_text(&[-10, -11], 1), "-1.1");
+        assert_eq!(mean_text(&[-1, 0], 1), "-0.1");
+        assert_eq!(mean_text(&[-1, 0, 0, 0], 2), "-0.03");
+        assert_eq!(mean_text(&[1, 0, 0, 0], 2), "0.03");
+        assert_eq!(mean_text(&[-1, 0, 0, 0, 0, 0, 0, 0], 2), "-0.01");
+        assert_eq!(mean_text(&[-1, 1], 2), "0.00");
+    }
+
+    /// Stations with `(count, min, mean, max)` in tenths, the mean being exact
+    fn stations(records: &[(usize, i16, i16, i16)]) -> Vec<(Box<[u8]>, MeasurementRecord)> {
+        records
+            .iter()
+            .enumerate()
+            .map(|(i, &(count, min, mean, max))| {
+                let rec = MeasurementRecord {
+                    count,
+                    sum: mean as i64 * count as i64,
+                    min,
+                    max,
+                    histogram: None,
+                };
+                (format!("s{i}").into_bytes().into(), rec)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn redacted_ranks_keep_the_order() {
+        let original = stations(&[
+            (3, -456, -123, 12),
+            (5, 123, 234, 345),
+            (6, -456, 55, 789),
+            (7, 11, 234, 789),
+            (1, 77, 77, 77),
+        ]);
+        let mut redacted = original.clone();
+        redact_values(&mut redacted);
+        let columns: [fn(&MeasurementRecord) -> i64; 3] = [
+            |rec| rec.min as i64,
+            MeasurementRecord::mean,
+            |rec| rec.max as i64,
+        ];
+        for column in columns {
+            for ((_, a), (_, redacted_a)) in original.iter().zip(&redacted) {
+                assert!((0..1000).contains(&column(redacted_a)));
+                for ((_, b), (_, redacted_b)) in original.iter().zip(&redacted) {
+                    // Ranks compare like the values, ties share theirs
+                    assert_eq!(
+                        column(a).cmp(&column(b)),
+                        column(redacted_a).cmp(&column(redacted_b))
+                    );
+                }
+            }
+        }
+        let counts: Vec<_> = redacted.iter().map(|(_, rec)| rec.count).collect();
+        assert_eq!(counts, [4, 8, 8, 8, 1]);
+        // The lowest value of a column ranks 0, the two maxima of 78.9 share 60%
+        assert_eq!(redacted[0].1.min, 0);
+        assert_eq!((redacted[2].1.max, redacted[3].1.max), (600, 600));
+    }
+
+    #[test]
+    fn redacted_values_are_gone() {
+        let values = [-456, -123, 12, 123, 234, 345, 789];
+        let mut redacted = stations(&[
+            (3, -456, -123, 12),
+            (5, 123, 234, 345),
+            (6, -123, 12, 789),
+            (9, 12, 234, 789),
+        ]);
+        redact_values(&mut redacted);
+        for (name, rec) in &redacted {
+            // Ranks of four stations are multiples of 25%
+            for value in [rec.min as i64, rec.mean(), rec.max as i64] {
+                assert!(!values.contains(&value), "{name:?} kept {value}");
+                assert_eq!(value % 250, 0);
+            }
+            assert!(rec.count.is_power_of_two());
+            assert_eq!(rec.sum % rec.count as i64, 0);
+        }
+    }
+}
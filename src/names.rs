@@ -0,0 +1,673 @@
+use std::{borrow::Cow, cmp::Ordering, error::Error, fmt, sync::Mutex};
+
+use hashbrown::hash_map::Entry;
+
+use crate::{Map, MeasurementRecord, Stations};
+
+/// A station name that isn't valid UTF-8, rejected under `--validate-utf8 --strict`
+#[derive(Debug, Clone)]
+pub struct InvalidStation {
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Display for InvalidStation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "station name is not valid UTF-8:")?;
+        for byte in &self.bytes {
+            write!(f, " {byte:02x}")?;
+        }
+        write!(f, " ({:?})", String::from_utf8_lossy(&self.bytes))
+    }
+}
+
+impl Error for InvalidStation {}
+
+/// How station names are checked and rewritten once all workers are merged
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NamePolicy {
+    /// Check that every name is valid UTF-8
+    pub validate_utf8: bool,
+    /// Reject invalid names instead of replacing invalid sequences with U+FFFD
+    pub strict: bool,
+    /// Normalize names to NFC so byte-different spellings of the same text merge
+    pub nfc: bool,
+    /// Strip ASCII whitespace from both ends of names
+    pub trim: bool,
+    /// Merge names that only differ in case
+    pub fold_case: Option<FoldCase>,
+}
+
+/// Which name `--fold-case` shows for stations merged by case
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldCase {
+    /// The spelling of the first reading in the input
+    First,
+    /// The case folded name
+    Lower,
+}
+
+/// Trimming and case folding of names as they are parsed, so a station's spellings share one
+/// key in the workers' maps and arenas. Shared by every `aggregate` call of a run, which gather
+/// the first spelling of every folded name here for [`FoldCase::First`].
+#[derive(Debug, Default)]
+pub struct NameFold {
+    pub trim: bool,
+    pub fold_case: Option<FoldCase>,
+    spellings: Mutex<Spellings>,
+}
+
+#[derive(Debug, Default)]
+struct Spellings {
+    /// First spelling of every folded name found by an earlier call
+    first: Map<Box<[u8]>, Box<[u8]>>,
+    /// First spelling of every folded name found by the current call
+    call: FirstSpellings,
+}
+
+/// The first spelling of every folded name, with the offset of the chunk it was found in
+type FirstSpellings = Map<Box<[u8]>, (usize, Box<[u8]>)>;
+
+impl NameFold {
+    pub fn new(trim: bool, fold_case: Option<FoldCase>) -> Self {
+        Self {
+            trim,
+            fold_case,
+            spellings: Mutex::default(),
+        }
+    }
+
+    /// The spelling of the first reading of the station stored as `folded`, if it was parsed
+    pub fn spelling(&self, folded: &[u8]) -> Option<Box<[u8]>> {
+        self.spellings.lock().unwrap().first.get(folded).cloned()
+    }
+
+    /// A worker's side of the folding
+    pub(crate) fn folder(&self) -> Folder<'_> {
+        Folder {
+            fold: self,
+            folded: Vec::new(),
+            chunk: 0,
+            spellings: Map::new(),
+        }
+    }
+
+    /// Keeps the spellings of an `aggregate` call once all of its workers are done, those of
+    /// earlier calls come first
+    pub(crate) fn finish_call(&self) {
+        let spellings = &mut *self.spellings.lock().unwrap();
+        for (folded, (_, spelling)) in spellings.call.drain() {
+            spellings.first.entry(folded).or_insert(spelling);
+        }
+    }
+}
+
+/// Folds the names of one worker, see [`NameFold`]
+pub struct Folder<'a> {
+    fold: &'a NameFold,
+    /// The current name, trimmed and folded
+    folded: Vec<u8>,
+    /// Offset of the chunk being parsed
+    chunk: usize,
+    /// First spelling of every name the worker added
+    spellings: FirstSpellings,
+}
+
+impl Folder<'_> {
+    pub(crate) fn start_chunk(&mut self, offset: usize) {
+        self.chunk = offset;
+    }
+
+    /// Trims and folds `station` and calls `add` with the result, which returns whether the
+    /// station is new to the worker's map
+    #[inline]
+    pub(crate) fn add(&mut self, station: &[u8], add: impl FnOnce(&[u8]) -> bool) {
+        let station = match self.fold.trim {
+            true => station.trim_ascii(),
+            false => station,
+        };
+        let Some(fold) = self.fold.fold_case else {
+            add(station);
+            return;
+        };
+        self.folded.clear();
+        fold_case_into(station, &mut self.folded);
+        if add(&self.folded) && fold == FoldCase::First {
+            self.spellings
+                .entry_ref(&self.folded[..])
+                .or_insert_with(|| (self.chunk, station.into()));
+        }
+    }
+
+    /// Hands the worker's spellings to the call, the one found in the earliest chunk wins
+    pub(crate) fn finish(self) {
+        let call = &mut self.fold.spellings.lock().unwrap().call;
+        for (folded, (chunk, spelling)) in self.spellings {
+            match call.entry(folded) {
+                Entry::Occupied(mut first) if chunk < first.get().0 => {
+                    first.insert((chunk, spelling));
+                }
+                Entry::Occupied(_) => {}
+                Entry::Vacant(slot) => {
+                    slot.insert((chunk, spelling));
+                }
+            }
+        }
+    }
+}
+
+impl NamePolicy {
+    pub fn is_identity(&self) -> bool {
+        !self.validate_utf8 && !self.nfc && !self.trim && self.fold_case.is_none()
+    }
+}
+
+/// Applies `policy` to every distinct station of the merged map, merging the records of names
+/// that end up identical.
+///
+/// This runs once per distinct station rather than per row, so the default path stays untouched.
+/// Trimming and case folding already happened while parsing with `names`, they are repeated for
+/// the names of saved states. With [`FoldCase::First`] a station shows the spelling `names`
+/// found first, or if it was only found in saved states the first of its spellings there in byte
+/// order.
+pub fn normalize_stations(
+    map: Stations,
+    policy: NamePolicy,
+    names: Option<&NameFold>,
+) -> Result<Stations, InvalidStation> {
+    if policy.is_identity() {
+        return Ok(map);
+    }
+    let mut normalized = Map::with_capacity(map.len());
+    // Spellings of the folded names of saved states
+    let mut spellings: Map<Box<[u8]>, Box<[u8]>> = Map::new();
+    for (station, rec) in map {
+        let station = normalize_name(station, policy)?;
+        let Some(fold) = policy.fold_case else {
+            merge_into(&mut normalized, station, rec);
+            continue;
+        };
+        let folded = fold_case(&station);
+        if fold == FoldCase::First {
+            match spellings.entry(folded.clone()) {
+                Entry::Occupied(mut first) if station < *first.get() => {
+                    first.insert(station);
+                }
+                Entry::Occupied(_) => {}
+                Entry::Vacant(slot) => {
+                    slot.insert(station);
+                }
+            }
+        }
+        merge_into(&mut normalized, folded, rec);
+    }
+    if policy.fold_case != Some(FoldCase::First) {
+        return Ok(normalized);
+    }
+    normalized
+        .into_iter()
+        .map(|(folded, rec)| {
+            let spelling = match names.and_then(|names| names.spelling(&folded)) {
+                Some(parsed) => normalize_name(parsed, policy)?,
+                None => spellings.remove(&folded).unwrap_or(folded),
+            };
+            Ok((spelling, rec))
+        })
+        .collect()
+}
+
+/// Trims, validates and normalizes a single name
+fn normalize_name(station: Box<[u8]>, policy: NamePolicy) -> Result<Box<[u8]>, InvalidStation> {
+    let station = match station.trim_ascii() {
+        trimmed if policy.trim && trimmed.len() < station.len() => trimmed.into(),
+        _ => station,
+    };
+    let name = match std::str::from_utf8(&station) {
+        Ok(name) => Cow::Borrowed(name),
+        // Without validation only valid names can be normalized
+        Err(_) if !policy.validate_utf8 => return Ok(station),
+        Err(_) if policy.strict => {
+            return Err(InvalidStation {
+                bytes: station.into(),
+            })
+        }
+        Err(_) => String::from_utf8_lossy(&station),
+    };
+    let name = if policy.nfc { nfc(name) } else { name };
+    // Names that came through unchanged keep their allocation
+    Ok(match name {
+        Cow::Borrowed(_) => station,
+        Cow::Owned(name) => name.into_bytes().into_boxed_slice(),
+    })
+}
+
+fn fold_case(name: &[u8]) -> Box<[u8]> {
+    let mut folded = Vec::with_capacity(name.len());
+    fold_case_into(name, &mut folded);
+    folded.into_boxed_slice()
+}
+
+/// Appends `name` with its case folded to `out`. Folds ASCII letters, and with the unicode feature
+/// every letter of a valid UTF-8 name by simple case folding.
+pub(crate) fn fold_case_into(name: &[u8], out: &mut Vec<u8>) {
+    #[cfg(feature = "unicode")]
+    if let (false, Ok(name)) = (name.is_ascii(), std::str::from_utf8(name)) {
+        for c in name.chars().map(simple_fold) {
+            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+        return;
+    }
+    out.extend(name.iter().map(u8::to_ascii_lowercase));
+}
+
+/// The simple case folding of `c` from Unicode's CaseFolding.txt, its `C` and `S` mappings. Those
+/// are the lowercase letter but for the exceptions below, e.g. final sigma folds to sigma and the
+/// dotted capital I, which only has a full folding, folds to itself.
+#[cfg(feature = "unicode")]
+fn simple_fold(c: char) -> char {
+    match c {
+        '\u{b5}' => '\u{3bc}',
+        '\u{130}' => c,
+        '\u{17f}' => 's',
+        '\u{345}' | '\u{1fbe}' => '\u{3b9}',
+        '\u{3c2}' => '\u{3c3}',
+        '\u{3d0}' => '\u{3b2}',
+        '\u{3d1}' => '\u{3b8}',
+        '\u{3d5}' => '\u{3c6}',
+        '\u{3d6}' => '\u{3c0}',
+        '\u{3f0}' => '\u{3ba}',
+        '\u{3f1}' => '\u{3c1}',
+        '\u{3f5}' => '\u{3b5}',
+        // Cherokee folds to its uppercase letters
+        '\u{13a0}'..='\u{13f5}' => c,
+        '\u{13f8}'..='\u{13fd}' => char::from_u32(c as u32 - 8).unwrap(),
+        '\u{ab70}'..='\u{abbf}' => char::from_u32(c as u32 - 0xab70 + 0x13a0).unwrap(),
+        '\u{1c80}' => '\u{432}',
+        '\u{1c81}' => '\u{434}',
+        '\u{1c82}' => '\u{43e}',
+        '\u{1c83}' => '\u{441}',
+        '\u{1c84}' | '\u{1c85}' => '\u{442}',
+        '\u{1c86}' => '\u{44a}',
+        '\u{1c87}' => '\u{463}',
+        '\u{1c88}' => '\u{a64b}',
+        '\u{1e9b}' => '\u{1e61}',
+        '\u{1fd3}' => '\u{390}',
+        '\u{1fe3}' => '\u{3b0}',
+        '\u{fb05}' => '\u{fb06}',
+        c => {
+            let mut lower = c.to_lowercase();
+            match (lower.next(), lower.next()) {
+                (Some(lower), None) => lower,
+                _ => c,
+            }
+        }
+    }
+}
+
+fn merge_into(map: &mut Stations, station: Box<[u8]>, rec: MeasurementRecord) {
+    match map.entry(station) {
+        Entry::Occupied(mut existing) => existing.get_mut().merge(&rec),
+        Entry::Vacant(slot) => {
+            slot.insert(rec);
+        }
+    }
+}
+
+#[cfg(feature = "unicode")]
+fn nfc(name: Cow<'_, str>) -> Cow<'_, str> {
+    use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+    if is_nfc_quick(name.chars()) == IsNormalized::Yes {
+        return name;
+    }
+    Cow::Owned(name.nfc().collect())
+}
+
+#[cfg(not(feature = "unicode"))]
+fn nfc(_: Cow<'_, str>) -> Cow<'_, str> {
+    unreachable!("--nfc is rejected when built without the unicode feature")
+}
+
+/// Order of the stations in the output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Collation {
+    /// Raw bytes, the order of the reference implementation
+    #[default]
+    Bytes,
+    /// Code points of the names decoded as UTF-8, which only differs from byte order in putting
+    /// names that aren't valid UTF-8 after all valid ones, ordered by their lossy decoding
+    Unicode,
+    /// Letters first: names compare by their letters with accents and case removed, then by
+    /// accents and then by case, lowercase first, so `Århus` sorts among the names starting with
+    /// `A`. This is not the Unicode Collation Algorithm or any locale's order: letters of
+    /// different scripts, punctuation and digits still compare by code point. Invalid names go
+    /// last in byte order.
+    AccentInsensitive,
+}
+
+/// Sorts `stations` by name in `collation` order. Names that only differ in ways the collation
+/// ignores, e.g. composed and decomposed spellings, are ordered by their bytes.
+pub fn sort_stations(stations: &mut [(Box<[u8]>, MeasurementRecord)], collation: Collation) {
+    match collation {
+        Collation::Bytes => stations.sort_unstable_by(|(a, _), (b, _)| a.cmp(b)),
+        Collation::Unicode => {
+            stations.sort_unstable_by(|(a, _), (b, _)| unicode_order(a, b).then_with(|| a.cmp(b)))
+        }
+        // Computing the keys is what's expensive, so each is computed only once
+        Collation::AccentInsensitive => {
+            stations.sort_by_cached_key(|(station, _)| alphabetic_key(station))
+        }
+    }
+}
+
+fn unicode_order(a: &[u8], b: &[u8]) -> Ordering {
+    match (std::str::from_utf8(a), std::str::from_utf8(b)) {
+        (Ok(a), Ok(b)) => a.cmp(b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => String::from_utf8_lossy(a).cmp(&String::from_utf8_lossy(b)),
+    }
+}
+
+/// Whether the name is invalid, its letters, letters with accents, which of them are uppercase
+/// and finally its bytes
+type AlphabeticKey = (bool, Vec<char>, Vec<char>, Vec<bool>, Box<[u8]>);
+
+#[cfg(feature = "unicode")]
+fn alphabetic_key(station: &[u8]) -> AlphabeticKey {
+    use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+    let Ok(name) = std::str::from_utf8(station) else {
+        return (true, Vec::new(), Vec::new(), Vec::new(), station.into());
+    };
+    // Accents are separate combining marks after decomposing
+    let decomposed: Vec<char> = name.nfd().collect();
+    let accented: Vec<char> = decomposed.iter().flat_map(|c| c.to_lowercase()).collect();
+    let letters = accented
+        .iter()
+        .copied()
+        .filter(|&c| !is_combining_mark(c))
+        .collect();
+    let uppercase = decomposed.iter().map(|c| c.is_uppercase()).collect();
+    (false, letters, accented, uppercase, station.into())
+}
+
+#[cfg(not(feature = "unicode"))]
+fn alphabetic_key(_: &[u8]) -> AlphabeticKey {
+    unreachable!("--collate accent-insensitive is rejected when built without the unicode feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        aggregate,
+        seed::{StationList, Unknown},
+        AggregateOptions,
+    };
+
+    /// Aggregates `input` with names trimmed and folded by `fold_case` while parsing, and
+    /// normalizes the result. `stations` is the contents of a stations file.
+    fn fold(input: &[u8], fold_case: FoldCase, stations: Option<&[u8]>) -> Stations {
+        let policy = NamePolicy {
+            trim: true,
+            fold_case: Some(fold_case),
+            ..Default::default()
+        };
+        let mut folded = None;
+        for threads in [1, 3] {
+            for chunk_size in [1, 16, input.len()] {
+                let names = Arc::new(NameFold::new(true, Some(fold_case)));
+                let options = AggregateOptions {
+                    threads,
+                    chunk_size: Some(chunk_size),
+                    names: Some(Arc::clone(&names)),
+                    stations: stations
+                        .map(|stations| Arc::new(StationList::parse(stations, Unknown::Add))),
+                    ..Default::default()
+                };
+                let map = aggregate(input, &options).unwrap();
+                let map = normalize_stations(map, policy, Some(&names)).unwrap();
+                assert_eq!(folded.get_or_insert_with(|| map.clone()), &map);
+            }
+        }
+        folded.unwrap()
+    }
+
+    #[test]
+    fn variants_collapse_to_one_station() {
+        let input = b"Hamburg;10.0\nhamburg;-5.5\nHamburg ;20.0\n HAMBURG;7\n";
+        let stations = fold(input, FoldCase::First, None);
+        assert_eq!(stations.len(), 1);
+        let rec = &stations[&b"Hamburg"[..]];
+        assert_eq!((rec.count, rec.sum), (4, 10 * 10 - 55 + 200 + 70));
+        assert_eq!((rec.min, rec.max), (-55, 200));
+
+        let stations = fold(input, FoldCase::Lower, None);
+        assert_eq!(stations[&b"hamburg"[..]], *rec);
+    }
+
+    #[test]
+    fn first_spelling_wins() {
+        // The first spelling has a single reading, the others have more
+        let input = b"hAMBURG;1.0\nBulawayo;8.9\nHamburg;2.0\nHamburg;3.0\nHAMBURG;4.0\n";
+        let stations = fold(input, FoldCase::First, None);
+        let mut names: Vec<_> = stations.keys().map(|name| &name[..]).collect();
+        names.sort();
+        assert_eq!(names, [&b"Bulawayo"[..], b"hAMBURG"]);
+        assert_eq!(stations[&b"hAMBURG"[..]].count, 4);
+    }
+
+    #[test]
+    fn seeded_spelling_comes_first() {
+        let list = b"Hamburg\n";
+        let stations = fold(b"HAMBURG;1.0\nhamburg;3.0\n", FoldCase::First, Some(list));
+        let names: Vec<&[u8]> = stations.keys().map(|name| &name[..]).collect();
+        assert_eq!(names, [b"Hamburg"]);
+        assert_eq!(stations[&b"Hamburg"[..]].count, 2);
+    }
+
+    #[test]
+    fn saved_spellings_merge_in_byte_order() {
+        // Names of a saved state, not seen while parsing
+        let mut map = Stations::new();
+        for (name, value) in [("hamburg", 10), ("Hamburg", -20), ("HAMBURG ", 30)] {
+            map.insert(name.as_bytes().into(), MeasurementRecord::first(value, 0));
+        }
+        let policy = NamePolicy {
+            trim: true,
+            fold_case: Some(FoldCase::First),
+            ..Default::default()
+        };
+        let stations = normalize_stations(map, policy, None).unwrap();
+        let names: Vec<&[u8]> = stations.keys().map(|name| &name[..]).collect();
+        assert_eq!(names, [b"HAMBURG"]);
+        let rec = &stations[&b"HAMBURG"[..]];
+        assert_eq!((rec.count, rec.min, rec.max), (3, -20, 30));
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn simple_case_folding() {
+        let folded = |name: &str| String::from_utf8(fold_case(name.as_bytes()).into()).unwrap();
+        // Final sigma folds to sigma, where lowercasing keeps it final
+        assert_eq!(folded("ΣΟΦΟΣ"), "σοφοσ");
+        assert_eq!(folded("σοφος"), "σοφοσ");
+        assert_eq!(folded("Straſse"), "strasse");
+        assert_eq!(folded("ÅRHUS"), "århus");
+        // Only the full folding maps the dotted capital I to two characters
+        assert_eq!(folded("İstanbul"), "İstanbul");
+        assert_eq!(folded("\u{13a0}\u{ab70}"), "\u{13a0}\u{13a0}");
+        // Invalid names only have their ASCII letters folded
+        assert_eq!(&*fold_case(b"AB\xffC"), b"ab\xffc");
+
+        let input = "ΣΟΦΟΣ;1.0\nσοφος;2.0\nΣοφοσ;3.0\n";
+        let stations = fold(input.as_bytes(), FoldCase::First, None);
+        assert_eq!(stations.len(), 1);
+        assert_eq!(stations["ΣΟΦΟΣ".as_bytes()].count, 3);
+    }
+
+    /// `(name, count, min, max)` of every station
+    type Summary = Vec<(Vec<u8>, usize, i16, i16)>;
+
+    /// Normalizes stations given as `(name, tenths)` and summarizes them sorted by name
+    fn normalize(stations: &[(&[u8], i16)], policy: NamePolicy) -> Result<Summary, InvalidStation> {
+        let mut map = Stations::new();
+        for &(name, value) in stations {
+            merge_into(&mut map, name.into(), MeasurementRecord::first(value, 0));
+        }
+        let mut stations: Vec<_> = normalize_stations(map, policy, None)?
+            .into_iter()
+            .map(|(name, rec)| (name.into_vec(), rec.count, rec.min, rec.max))
+            .collect();
+        stations.sort();
+        Ok(stations)
+    }
+
+    #[test]
+    fn invalid_utf8_names() {
+        let stations: [(&[u8], i16); 3] = [(b"Oslo\xff", 10), (b"Oslo\xfe", 20), (b"Oslo", 30)];
+        // Both invalid names decode to the same replacement and merge
+        let lenient = NamePolicy {
+            validate_utf8: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(&stations, lenient).unwrap(),
+            [
+                (b"Oslo".to_vec(), 1, 30, 30),
+                ("Oslo\u{fffd}".as_bytes().to_vec(), 2, 10, 20)
+            ]
+        );
+
+        let strict = NamePolicy {
+            strict: true,
+            ..lenient
+        };
+        let err = normalize(&stations, strict).unwrap_err();
+        assert!(err.bytes == b"Oslo\xff" || err.bytes == b"Oslo\xfe");
+        assert_eq!(
+            InvalidStation {
+                bytes: b"Oslo\xff".to_vec()
+            }
+            .to_string(),
+            "station name is not valid UTF-8: 4f 73 6c 6f ff (\"Oslo\u{fffd}\")"
+        );
+
+        // Without validation they are kept as they are
+        let trim = NamePolicy {
+            trim: true,
+            ..Default::default()
+        };
+        assert_eq!(normalize(&stations, trim).unwrap().len(), 3);
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn nfc_equal_names() {
+        let stations: [(&[u8], i16); 5] = [
+            ("Zu\u{308}rich".as_bytes(), 10),
+            ("Zürich".as_bytes(), 20),
+            // The angstrom sign and the ring above decompose to the same A
+            ("\u{212b}rhus".as_bytes(), 30),
+            ("A\u{30a}rhus".as_bytes(), 40),
+            ("Århus".as_bytes(), 50),
+        ];
+        let nfc = NamePolicy {
+            nfc: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(&stations, nfc).unwrap(),
+            [
+                ("Zürich".as_bytes().to_vec(), 2, 10, 20),
+                ("Århus".as_bytes().to_vec(), 3, 30, 50),
+            ]
+        );
+        // Without --nfc every spelling is a station of its own
+        let validate = NamePolicy {
+            validate_utf8: true,
+            ..Default::default()
+        };
+        assert_eq!(normalize(&stations, validate).unwrap().len(), 5);
+    }
+
+    /// Sorts names in `collation` order
+    fn sorted(collation: Collation) -> Vec<String> {
+        let names: [&[u8]; 10] = [
+            b"Zagreb",
+            "Édéa".as_bytes(),
+            b"Ab\xff",
+            b"abha",
+            "E\u{301}de".as_bytes(),
+            b"Eden",
+            "Århus".as_bytes(),
+            b"Abha",
+            "Éde".as_bytes(),
+            b"Ede",
+        ];
+        let mut stations: Vec<_> = names
+            .iter()
+            .map(|&name| (name.into(), MeasurementRecord::first(0, 0)))
+            .collect();
+        sort_stations(&mut stations, collation);
+        stations
+            .iter()
+            .map(|(name, _)| String::from_utf8_lossy(name).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn byte_order() {
+        let expected = [
+            "Abha",
+            "Ab\u{fffd}",
+            "Ede",
+            "Eden",
+            "E\u{301}de",
+            "Zagreb",
+            "abha",
+            "Århus",
+            "Éde",
+            "Édéa",
+        ];
+        assert_eq!(sorted(Collation::Bytes), expected);
+    }
+
+    #[test]
+    fn code_point_order() {
+        // Only the invalid name moves, to the end
+        let expected = [
+            "Abha",
+            "Ede",
+            "Eden",
+            "E\u{301}de",
+            "Zagreb",
+            "abha",
+            "Århus",
+            "Éde",
+            "Édéa",
+            "Ab\u{fffd}",
+        ];
+        assert_eq!(sorted(Collation::Unicode), expected);
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn accent_insensitive_order() {
+        // Lowercase before uppercase, unaccented before accented, and the composed and
+        // decomposed `Éde` only differ in their bytes
+        let expected = [
+            "abha",
+            "Abha",
+            "Århus",
+            "Ede",
+            "E\u{301}de",
+            "Éde",
+            "Édéa",
+            "Eden",
+            "Zagreb",
+            "Ab\u{fffd}",
+        ];
+        assert_eq!(sorted(Collation::AccentInsensitive), expected);
+    }
+}
@@ -0,0 +1,168 @@
+//! NUMA topology detection and worker pinning for `--numa`, only available on Linux with the `numa`
+//! feature.
+//!
+//! Each node gets a contiguous region of the input in proportion to its workers, which claim
+//! chunks from their own region first. When the input isn't in the page cache yet, its pages are
+//! faulted in by a worker of the node that parses them and stay local to it. Locality can be
+//! checked by comparing the `numa_hit` and `numa_foreign` counters of `numastat` before and after
+//! a run with and without `--numa`.
+
+use std::{fmt, io};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topology {
+    /// The CPUs of every node that has any
+    pub nodes: Vec<Vec<usize>>,
+}
+
+impl Topology {
+    /// Reads the topology from sysfs, `None` on machines with a single node or when it isn't
+    /// available
+    #[cfg(all(feature = "numa", target_os = "linux"))]
+    pub fn detect() -> io::Result<Option<Self>> {
+        let entries = match std::fs::read_dir("/sys/devices/system/node") {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut nodes = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(node) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("node"))
+                .and_then(|node| node.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            let cpus = parse_cpu_list(&std::fs::read_to_string(entry.path().join("cpulist"))?)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid cpulist"))?;
+            if !cpus.is_empty() {
+                nodes.push((node, cpus));
+            }
+        }
+        nodes.sort_unstable();
+        if nodes.len() < 2 {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            nodes: nodes.into_iter().map(|(_, cpus)| cpus).collect(),
+        }))
+    }
+
+    #[cfg(not(all(feature = "numa", target_os = "linux")))]
+    pub fn detect() -> io::Result<Option<Self>> {
+        Ok(None)
+    }
+
+    /// The node of each of `threads` workers, spread over the nodes in proportion to their CPUs and
+    /// in ascending order
+    pub fn homes(&self, threads: usize) -> Vec<usize> {
+        let mut assigned = vec![0; self.nodes.len()];
+        let mut homes: Vec<usize> = (0..threads)
+            .map(|_| {
+                // The node with the fewest workers per CPU so far
+                let node = (0..self.nodes.len())
+                    .min_by_key(|&node| (assigned[node] * 1024 / self.nodes[node].len(), node))
+                    .unwrap();
+                assigned[node] += 1;
+                node
+            })
+            .collect();
+        homes.sort_unstable();
+        homes
+    }
+}
+
+impl fmt::Display for Topology {
+    /// Lists the CPUs of every node in the sysfs format, e.g. `node 0: 0-15, node 1: 16-31`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (node, cpus) in self.nodes.iter().enumerate() {
+            if node > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "node {node}: ")?;
+            let mut rest = &cpus[..];
+            while let [first, ..] = rest {
+                // The run of consecutive CPUs starting at `first`
+                let run = rest
+                    .iter()
+                    .enumerate()
+                    .take_while(|&(i, &cpu)| cpu == first + i)
+                    .count();
+                if rest.len() < cpus.len() {
+                    write!(f, ",")?;
+                }
+                match run {
+                    1 => write!(f, "{first}")?,
+                    _ => write!(f, "{first}-{}", rest[run - 1])?,
+                }
+                rest = &rest[run..];
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a sysfs CPU list like `0-15,32-47`
+#[cfg(target_os = "linux")]
+pub(crate) fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        cpus.extend(first.parse::<usize>().ok()?..=last.parse().ok()?);
+    }
+    Some(cpus)
+}
+
+/// CPU affinity of the calling thread
+#[cfg(all(feature = "numa", target_os = "linux"))]
+pub struct Affinity(libc::cpu_set_t);
+
+#[cfg(all(feature = "numa", target_os = "linux"))]
+impl Affinity {
+    pub fn current() -> io::Result<Self> {
+        // SAFETY: an all zero cpu_set_t is a valid empty set, the kernel fills it in
+        let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+        match unsafe { libc::sched_getaffinity(0, size_of_val(&set), &mut set) } {
+            0 => Ok(Self(set)),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    pub fn of(cpus: &[usize]) -> Self {
+        // SAFETY: an all zero cpu_set_t is a valid empty set
+        let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+        for &cpu in cpus {
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+        Self(set)
+    }
+
+    /// Restricts the calling thread to these CPUs
+    pub fn apply(&self) -> io::Result<()> {
+        match unsafe { libc::sched_setaffinity(0, size_of_val(&self.0), &self.0) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+#[cfg(not(all(feature = "numa", target_os = "linux")))]
+pub struct Affinity;
+
+#[cfg(not(all(feature = "numa", target_os = "linux")))]
+impl Affinity {
+    pub fn current() -> io::Result<Self> {
+        Ok(Self)
+    }
+
+    pub fn of(_: &[usize]) -> Self {
+        Self
+    }
+
+    pub fn apply(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+//! Seeded chunk sampling, shared by `--shadow-check` and `--sample-random`.
+
+/// Picks a seeded share of the chunks by their offset
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkSample {
+    /// Share of chunks that are picked, in percent
+    pub percent: f64,
+    pub seed: u64,
+}
+
+impl ChunkSample {
+    /// Whether the chunk claimed at `offset` is in the sample. The same seed and chunk size pick
+    /// the same chunks on every run.
+    pub fn samples(&self, offset: usize) -> bool {
+        // splitmix64 of the offset, so neighbouring chunks are picked independently
+        let mut z = (self.seed ^ offset as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        ((z >> 11) as f64 / (1u64 << 53) as f64) * 100.0 < self.percent
+    }
+}
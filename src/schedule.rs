@@ -0,0 +1,372 @@
+//! Recording and replaying the order in which workers claim chunks, so a failure that depends on
+//! one particular interleaving can be reproduced.
+//!
+//! A recording is a text file: a `1brc-schedule 1 FILE_LEN` header, then for every `aggregate`
+//! call a `pass LEN CHUNK_SIZE THREADS` line followed by one `THREAD START END` line per claim, in
+//! the order the claims were made.
+
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+};
+
+const HEADER: &str = "1brc-schedule";
+/// Bumped on any change to the layout
+const VERSION: u32 = 1;
+
+/// One chunk claimed by a worker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Claim {
+    pub thread: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The claims made during one `aggregate` call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pass {
+    /// Length of the input of the call
+    pub len: usize,
+    pub chunk_size: usize,
+    pub threads: usize,
+    pub claims: Vec<Claim>,
+}
+
+/// Shared by every `aggregate` call of a run, either collecting its claims or dictating them
+#[derive(Debug)]
+pub enum Schedule {
+    Record(Mutex<Vec<Pass>>),
+    Replay {
+        passes: Vec<Pass>,
+        next: AtomicUsize,
+    },
+}
+
+impl Schedule {
+    pub fn record() -> Self {
+        Self::Record(Mutex::new(Vec::new()))
+    }
+
+    pub fn replay(passes: Vec<Pass>) -> Self {
+        Self::Replay {
+            passes,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// The passes recorded so far, empty when replaying
+    pub fn recorded(&self) -> Vec<Pass> {
+        match self {
+            Self::Record(passes) => passes.lock().unwrap().clone(),
+            Self::Replay { .. } => Vec::new(),
+        }
+    }
+
+    /// Checks that a replay used every recorded pass
+    pub fn finish(&self) -> Result<(), ScheduleMismatch> {
+        match self {
+            Self::Replay { passes, next } if next.load(Ordering::Acquire) < passes.len() => {
+                Err(ScheduleMismatch::UnusedPasses {
+                    used: next.load(Ordering::Acquire),
+                    recorded: passes.len(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes the next recorded pass for an `aggregate` call with the given configuration
+    pub(crate) fn next_pass(
+        &self,
+        len: usize,
+        chunk_size: usize,
+        threads: usize,
+    ) -> Result<&Pass, ScheduleMismatch> {
+        let Self::Replay { passes, next } = self else {
+            unreachable!("only replays hand out passes")
+        };
+        let index = next.fetch_add(1, Ordering::AcqRel);
+        let pass = passes
+            .get(index)
+            .ok_or(ScheduleMismatch::MissingPass { pass: index })?;
+        for (field, recorded, actual) in [
+            ("an input length", pass.len, len),
+            ("a chunk size", pass.chunk_size, chunk_size),
+            ("a thread count", pass.threads, threads),
+        ] {
+            if recorded != actual {
+                return Err(ScheduleMismatch::Pass {
+                    pass: index,
+                    field,
+                    recorded,
+                    actual,
+                });
+            }
+        }
+        Ok(pass)
+    }
+}
+
+/// A run that doesn't match the schedule it replays
+#[derive(Debug, Clone)]
+pub enum ScheduleMismatch {
+    MissingPass {
+        pass: usize,
+    },
+    UnusedPasses {
+        used: usize,
+        recorded: usize,
+    },
+    Pass {
+        pass: usize,
+        field: &'static str,
+        recorded: usize,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for ScheduleMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingPass { pass } => {
+                write!(f, "the recorded schedule has no pass {pass}")
+            }
+            Self::UnusedPasses { used, recorded } => write!(
+                f,
+                "the recorded schedule has {recorded} passes, the run only made {used}"
+            ),
+            Self::Pass {
+                pass,
+                field,
+                recorded,
+                actual,
+            } => write!(
+                f,
+                "pass {pass} of the schedule was recorded with {field} of {recorded}, \
+                 the run has {actual}"
+            ),
+        }
+    }
+}
+
+impl Error for ScheduleMismatch {}
+
+/// Hands out the claims of a recorded pass in their recorded order, blocking every worker until
+/// the claim before its next one has been handed out
+#[derive(Debug)]
+pub(crate) struct Replay<'a> {
+    claims: &'a [Claim],
+    /// Index of the next claim to hand out, `usize::MAX` once stopped
+    next: Mutex<usize>,
+    turn: Condvar,
+    /// Index of the last claim of every thread
+    last: Vec<Option<usize>>,
+}
+
+impl<'a> Replay<'a> {
+    pub fn new(pass: &'a Pass) -> Self {
+        let mut last = vec![None; pass.threads];
+        for (index, claim) in pass.claims.iter().enumerate() {
+            last[claim.thread] = Some(index);
+        }
+        Self {
+            claims: &pass.claims,
+            next: Mutex::new(0),
+            turn: Condvar::new(),
+            last,
+        }
+    }
+
+    pub fn claim(&self, thread: usize) -> Option<Range<usize>> {
+        let mut next = self.next.lock().unwrap();
+        loop {
+            if self.last[thread].is_none_or(|last| *next > last) {
+                return None;
+            }
+            let claim = self.claims[*next];
+            if claim.thread == thread {
+                *next += 1;
+                self.turn.notify_all();
+                return Some(claim.start..claim.end);
+            }
+            next = self.turn.wait(next).unwrap();
+        }
+    }
+
+    /// Releases every waiting worker without handing out the remaining claims
+    pub fn stop(&self) {
+        *self.next.lock().unwrap() = usize::MAX;
+        self.turn.notify_all();
+    }
+}
+
+pub fn save(path: &str, file_len: u64, passes: &[Pass]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "{HEADER} {VERSION} {file_len}")?;
+    for pass in passes {
+        writeln!(
+            out,
+            "pass {} {} {}",
+            pass.len, pass.chunk_size, pass.threads
+        )?;
+        for claim in &pass.claims {
+            writeln!(out, "{} {} {}", claim.thread, claim.start, claim.end)?;
+        }
+    }
+    out.flush()
+}
+
+/// Reads a recording, returning the length of the recorded file and its passes
+pub fn load(path: &str) -> io::Result<(u64, Vec<Pass>)> {
+    let contents = fs::read_to_string(path)?;
+    let mut lines = contents.lines().enumerate();
+    let invalid = |line_number: usize, msg: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {msg}", line_number + 1),
+        )
+    };
+    let numbers = |line_number: usize, fields: &[&str]| -> io::Result<[usize; 3]> {
+        let mut numbers = [0; 3];
+        if fields.len() != 3 {
+            return Err(invalid(line_number, "expected 3 fields"));
+        }
+        for (number, field) in numbers.iter_mut().zip(fields) {
+            *number = field
+                .parse()
+                .map_err(|_| invalid(line_number, "expected a number"))?;
+        }
+        Ok(numbers)
+    };
+
+    let header: Vec<_> = lines
+        .next()
+        .map_or(Vec::new(), |(_, line)| line.split(' ').collect());
+    let &[HEADER, version, file_len] = &header[..] else {
+        return Err(invalid(0, "not a schedule recording"));
+    };
+    if version != VERSION.to_string() {
+        return Err(invalid(
+            0,
+            &format!("schedule version {version} is not supported, expected {VERSION}"),
+        ));
+    }
+    let file_len = file_len
+        .parse()
+        .map_err(|_| invalid(0, "expected a file length"))?;
+
+    let mut passes: Vec<Pass> = Vec::new();
+    for (line_number, line) in lines {
+        let fields: Vec<_> = line.split(' ').collect();
+        if let ["pass", fields @ ..] = &fields[..] {
+            let [len, chunk_size, threads] = numbers(line_number, fields)?;
+            passes.push(Pass {
+                len,
+                chunk_size,
+                threads,
+                claims: Vec::new(),
+            });
+            continue;
+        }
+        let [thread, start, end] = numbers(line_number, &fields)?;
+        let Some(pass) = passes.last_mut() else {
+            return Err(invalid(line_number, "claim before the first pass"));
+        };
+        if thread >= pass.threads || start >= end || end > pass.len {
+            return Err(invalid(line_number, "claim outside of its pass"));
+        }
+        pass.claims.push(Claim { thread, start, end });
+    }
+    Ok((file_len, passes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{aggregate, tests::TempFile, AggregateError, AggregateOptions, Stations};
+
+    const INPUT: &[u8] = b"Hamburg;12.0\nBulawayo;8.9\nPalembang;38.8\nSt. John's;15.2\n\
+        Cracow;12.6\nHamburg;-3.4\nBulawayo;-0.1\nPalembang;9.9\n";
+
+    fn run(
+        input: &[u8],
+        chunk_size: usize,
+        schedule: &Arc<Schedule>,
+    ) -> Result<Stations, AggregateError> {
+        let options = AggregateOptions {
+            threads: 3,
+            chunk_size: Some(chunk_size),
+            schedule: Some(schedule.clone()),
+            ..Default::default()
+        };
+        aggregate(input, &options)
+    }
+
+    /// Records two passes over `INPUT` with 8 byte chunks and reads them back from a file
+    fn recording() -> (Stations, Vec<Pass>) {
+        let schedule = Arc::new(Schedule::record());
+        let stations = run(INPUT, 8, &schedule).unwrap();
+        assert_eq!(run(INPUT, 8, &schedule).unwrap(), stations);
+        let file = TempFile::new("recording.schedule");
+        save(file.path(), INPUT.len() as u64, &schedule.recorded()).unwrap();
+        let (len, passes) = load(file.path()).unwrap();
+        assert_eq!(len, INPUT.len() as u64);
+        assert_eq!(passes, schedule.recorded());
+        (stations, passes)
+    }
+
+    #[test]
+    fn replay_gives_identical_output() {
+        let (stations, passes) = recording();
+        // Every claim is needed to cover the input, so a replay that finishes made them all
+        assert_eq!(passes[0].claims.len(), INPUT.len().div_ceil(8));
+        let schedule = Arc::new(Schedule::replay(passes));
+        assert_eq!(run(INPUT, 8, &schedule).unwrap(), stations);
+        assert_eq!(run(INPUT, 8, &schedule).unwrap(), stations);
+        schedule.finish().unwrap();
+    }
+
+    #[test]
+    fn replay_rejects_other_inputs() {
+        let (_, passes) = recording();
+        let schedule = Arc::new(Schedule::replay(passes.clone()));
+        let other = &INPUT[..INPUT.len() - 13];
+        let Err(AggregateError::Schedule(err)) = run(other, 8, &schedule) else {
+            panic!("replayed against a shorter input");
+        };
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "pass 0 of the schedule was recorded with an input length of {}, the run has {}",
+                INPUT.len(),
+                other.len()
+            )
+        );
+
+        let schedule = Arc::new(Schedule::replay(passes));
+        let Err(AggregateError::Schedule(err)) = run(INPUT, 16, &schedule) else {
+            panic!("replayed with another chunk size");
+        };
+        assert!(matches!(
+            err,
+            ScheduleMismatch::Pass {
+                pass: 0,
+                field: "a chunk size",
+                recorded: 8,
+                actual: 16
+            }
+        ));
+        // The mismatching call used up pass 0, the next one replays pass 1
+        assert!(run(INPUT, 8, &schedule).is_ok());
+        schedule.finish().unwrap();
+    }
+}
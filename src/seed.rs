@@ -0,0 +1,192 @@
+//! Known station lists for `--stations-file`, pre-seeding every worker's table.
+//!
+//! Every worker starts with all listed stations in its table, sized to hold them, so no known
+//! station is ever inserted or makes the table grow. With [`Unknown::Error`] or
+//! [`Unknown::Skip`] unlisted stations aren't inserted either, and the hot loop only ever updates
+//! existing entries. The list outlives the workers, their tables borrow the names from it instead
+//! of copying them into their arenas.
+
+use std::{
+    error::Error,
+    fmt, fs,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::Map;
+
+/// What workers do with a station that isn't in the list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Unknown {
+    /// Insert it like without a list
+    #[default]
+    Add,
+    /// Fail with [`UnknownStation`]
+    Error,
+    /// Leave out its readings and count them in [`StationList::skipped`]
+    Skip,
+}
+
+#[derive(Debug, Default)]
+pub struct StationList {
+    names: Vec<Box<[u8]>>,
+    pub unknown: Unknown,
+    /// Readings of unknown stations left out by every call that was passed this list
+    skipped: AtomicUsize,
+}
+
+impl StationList {
+    /// Reads one station name per line, skipping blank lines and repeated names
+    pub fn load(path: &str, unknown: Unknown) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+        Ok(Self::parse(&contents, unknown))
+    }
+
+    pub fn parse(contents: &[u8], unknown: Unknown) -> Self {
+        let mut seen = Map::new();
+        let names = contents
+            .split(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.is_empty() && seen.insert(*line, ()).is_none())
+            .map(Box::from)
+            .collect();
+        Self {
+            names,
+            unknown,
+            skipped: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn names(&self) -> &[Box<[u8]>] {
+        &self.names
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn add_skipped(&self, readings: usize) {
+        self.skipped.fetch_add(readings, Ordering::Relaxed);
+    }
+}
+
+/// Readings of stations that aren't in the list, seen by a worker in a chunk
+#[derive(Debug, Default)]
+pub struct Unknowns {
+    pub readings: usize,
+    /// The first unknown station
+    pub first: Option<Box<[u8]>>,
+}
+
+impl Unknowns {
+    #[cold]
+    pub fn add(&mut self, station: &[u8]) {
+        self.readings += 1;
+        self.first.get_or_insert_with(|| station.into());
+    }
+}
+
+/// A station missing from the list under [`Unknown::Error`]
+#[derive(Debug, Clone)]
+pub struct UnknownStation {
+    pub station: Box<[u8]>,
+    /// Start of the chunk the station was found in
+    pub chunk_offset: usize,
+}
+
+impl fmt::Display for UnknownStation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "station {:?} in the chunk at byte {} isn't in the station list",
+            String::from_utf8_lossy(&self.station),
+            self.chunk_offset
+        )
+    }
+}
+
+impl Error for UnknownStation {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{aggregate, AggregateError, AggregateOptions, Stations};
+
+    const LIST: &[u8] = b"Oslo\r\n\nRome\nOslo\nLima\n";
+    const INPUT: &[u8] = b"Oslo;1.0\nParis;2.0\nRome;3.0\nParis;4.0\nBern;5.0\nOslo;6.0\n";
+
+    fn run(list: &Arc<StationList>, threads: usize) -> Result<Stations, AggregateError> {
+        let options = AggregateOptions {
+            threads,
+            chunk_size: Some(16),
+            stations: Some(Arc::clone(list)),
+            ..Default::default()
+        };
+        aggregate(INPUT, &options)
+    }
+
+    /// Names and counts of `stations`, sorted by name
+    fn counts(stations: &Stations) -> Vec<(&str, usize)> {
+        let mut counts: Vec<_> = stations
+            .iter()
+            .map(|(name, rec)| (std::str::from_utf8(name).unwrap(), rec.count))
+            .collect();
+        counts.sort();
+        counts
+    }
+
+    #[test]
+    fn parse_skips_blank_and_repeated_names() {
+        let list = StationList::parse(LIST, Unknown::Add);
+        let names: Vec<&[u8]> = list.names().iter().map(|name| &name[..]).collect();
+        assert_eq!(names, [&b"Oslo"[..], b"Rome", b"Lima"]);
+    }
+
+    #[test]
+    fn unknown_stations_are_added() {
+        let list = Arc::new(StationList::parse(LIST, Unknown::Add));
+        for threads in [1, 3] {
+            let stations = run(&list, threads).unwrap();
+            // Listed stations without readings are kept with a count of 0, for --emit-empty
+            assert_eq!(
+                counts(&stations),
+                [
+                    ("Bern", 1),
+                    ("Lima", 0),
+                    ("Oslo", 2),
+                    ("Paris", 2),
+                    ("Rome", 1)
+                ]
+            );
+        }
+        assert_eq!(list.skipped(), 0);
+    }
+
+    #[test]
+    fn unknown_stations_fail() {
+        let list = Arc::new(StationList::parse(LIST, Unknown::Error));
+        for threads in [1, 3] {
+            let Err(AggregateError::Unknown(err)) = run(&list, threads) else {
+                panic!("Paris isn't in the list");
+            };
+            // The first Paris reading starts in the first chunk
+            assert_eq!((&*err.station, err.chunk_offset), (&b"Paris"[..], 0));
+            assert_eq!(
+                err.to_string(),
+                "station \"Paris\" in the chunk at byte 0 isn't in the station list"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_stations_are_skipped() {
+        let list = Arc::new(StationList::parse(LIST, Unknown::Skip));
+        let stations = run(&list, 3).unwrap();
+        assert_eq!(counts(&stations), [("Lima", 0), ("Oslo", 2), ("Rome", 1)]);
+        assert_eq!(list.skipped(), 3);
+        // Every call adds to the count
+        run(&list, 1).unwrap();
+        assert_eq!(list.skipped(), 6);
+    }
+}
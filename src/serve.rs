@@ -0,0 +1,431 @@
+//! `rs serve`, answering HTTP requests for the aggregated results, see the usage text for the
+//! endpoints.
+//!
+//! This is a minimal HTTP/1.1 responder rather than a full server: every connection carries a
+//! single request, which is answered and then the connection is closed. Request bodies are
+//! ignored. Connections are handled on a thread each, at most [`MAX_CONNECTIONS`] at a time with
+//! the others waiting in the listen backlog, and read the results through an `Arc`, which
+//! `POST /refresh` swaps for a new one once the file has been aggregated again.
+
+use std::{
+    error::Error,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use rs::{
+    names::{self, Collation},
+    MeasurementRecord,
+};
+
+use crate::{cli::parse_collation, follow, write_json_string, write_scaled, Results};
+
+/// Longest request line and headers that are read
+const MAX_REQUEST: u64 = 16 * 1024;
+/// How long a connection may take to send its request or read the response
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Most connections handled at once, so a flood of them can't spawn unbounded threads
+const MAX_CONNECTIONS: usize = 64;
+/// How often the accept loop checks for Ctrl-C
+const POLL: Duration = Duration::from_millis(50);
+
+/// Serves `results` on `listen` until Ctrl-C is pressed, `refresh` aggregates the file again.
+/// Means are written with `precision` decimals.
+pub fn serve(
+    listen: &str,
+    precision: u32,
+    results: Results,
+    refresh: impl Fn() -> Result<Results, Box<dyn Error>> + Sync,
+) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(listen).map_err(|err| format!("{listen}: {err}"))?;
+    // Accepting without blocking lets the loop notice Ctrl-C
+    listener.set_nonblocking(true)?;
+    eprintln!("listening on http://{}", listener.local_addr()?);
+    let server = Server {
+        precision,
+        results: RwLock::new(Arc::new(results)),
+        refreshing: Mutex::new(()),
+        refresh,
+    };
+    let active = AtomicUsize::new(0);
+    std::thread::scope(|s| loop {
+        // Leaves new connections in the backlog until one of the handled ones is done
+        if active.load(Ordering::Acquire) >= MAX_CONNECTIONS {
+            if !follow::sleep(POLL) {
+                return Ok(());
+            }
+            continue;
+        }
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                let (server, active) = (&server, &active);
+                active.fetch_add(1, Ordering::AcqRel);
+                s.spawn(move || {
+                    if let Err(err) = server.handle(stream) {
+                        eprintln!("warning: {peer}: {err}");
+                    }
+                    active.fetch_sub(1, Ordering::AcqRel);
+                });
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if !follow::sleep(POLL) {
+                    return Ok(());
+                }
+            }
+            Err(err) => return Err(err.into()),
+        }
+    })
+}
+
+struct Server<F> {
+    precision: u32,
+    results: RwLock<Arc<Results>>,
+    /// Held while refreshing, so concurrent refreshes don't aggregate the file at the same time
+    refreshing: Mutex<()>,
+    refresh: F,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(status: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn error(status: &'static str, message: &str) -> Self {
+        let mut body = b"{\"error\":".to_vec();
+        write_json_string(&mut body, message.as_bytes());
+        body.extend_from_slice(b"}\n");
+        Self::json(status, body)
+    }
+}
+
+impl<F: Fn() -> Result<Results, Box<dyn Error>>> Server<F> {
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        // Accepted streams inherit the listener's non-blocking mode on some platforms
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut reader = BufReader::new((&stream).take(MAX_REQUEST));
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // Skip the headers up to the blank line that ends them
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+            header.clear();
+        }
+        let mut parts = request.split_whitespace();
+        let response = match (parts.next(), parts.next()) {
+            (Some(method), Some(target)) => self.respond(method, target),
+            _ => Response::error("400 Bad Request", "malformed request line"),
+        };
+        let mut stream = &stream;
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.status,
+            response.content_type,
+            response.body.len()
+        )?;
+        stream.write_all(&response.body)?;
+        stream.flush()
+    }
+
+    fn respond(&self, method: &str, target: &str) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        match (method, path) {
+            ("GET", "/healthz") => Response {
+                status: "200 OK",
+                content_type: "text/plain",
+                body: b"ok\n".to_vec(),
+            },
+            ("GET", "/stations") => self.stations(query),
+            ("POST", "/refresh") => self.refresh(),
+            ("GET", path) if path.starts_with("/stations/") => {
+                let Some(name) = percent_decode(&path["/stations/".len()..]) else {
+                    return Response::error("400 Bad Request", "invalid percent-encoding");
+                };
+                let results = self.current();
+                match results.iter().find(|(station, _)| **station == *name) {
+                    Some((station, record)) => {
+                        let mut body = Vec::new();
+                        self.write_station(&mut body, station, record);
+                        body.push(b'\n');
+                        Response::json("200 OK", body)
+                    }
+                    None => Response::error("404 Not Found", "unknown station"),
+                }
+            }
+            _ if matches!(path, "/healthz" | "/stations" | "/refresh")
+                || path.starts_with("/stations/") =>
+            {
+                Response::error("405 Method Not Allowed", "method not allowed")
+            }
+            _ => Response::error("404 Not Found", "not found"),
+        }
+    }
+
+    fn current(&self) -> Arc<Results> {
+        Arc::clone(&self.results.read().unwrap())
+    }
+
+    /// Every station, ordered by `sort` (like `--collate`) and cut to the first `top` if given
+    fn stations(&self, query: &str) -> Response {
+        let mut sort = None;
+        let mut top = None;
+        for (key, value) in query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        {
+            match key {
+                "sort" => match parse_collation(value) {
+                    Ok(collation) => sort = Some(collation),
+                    Err(err) => return Response::error("400 Bad Request", &err.to_string()),
+                },
+                "top" => match value.parse::<usize>() {
+                    Ok(n) => top = Some(n),
+                    Err(_) => {
+                        return Response::error(
+                            "400 Bad Request",
+                            &format!("invalid top {value:?}"),
+                        )
+                    }
+                },
+                _ => {
+                    return Response::error(
+                        "400 Bad Request",
+                        &format!("unknown parameter {key:?}"),
+                    )
+                }
+            }
+        }
+        let results = self.current();
+        let sorted;
+        let stations = match sort {
+            Some(collation) => {
+                sorted = sorted_by(&results, collation);
+                &sorted
+            }
+            None => &*results,
+        };
+        let stations = &stations[..top.unwrap_or(usize::MAX).min(stations.len())];
+        let mut body = b"{\"stations\":[".to_vec();
+        for (i, (station, record)) in stations.iter().enumerate() {
+            if i > 0 {
+                body.push(b',');
+            }
+            self.write_station(&mut body, station, record);
+        }
+        body.extend_from_slice(b"]}\n");
+        Response::json("200 OK", body)
+    }
+
+    fn refresh(&self) -> Response {
+        let _refreshing = self.refreshing.lock().unwrap();
+        match (self.refresh)() {
+            Ok(results) => {
+                let stations = results.len();
+                *self.results.write().unwrap() = Arc::new(results);
+                Response::json("200 OK", format!("{{\"stations\":{stations}}}\n").into())
+            }
+            Err(err) => Response::error("500 Internal Server Error", &err.to_string()),
+        }
+    }
+
+    fn write_station(&self, out: &mut Vec<u8>, station: &[u8], record: &MeasurementRecord) {
+        // Writing into a Vec can't fail
+        let number = |out: &mut Vec<u8>, n: i64, decimals: u32| {
+            write_scaled(&mut *out, n, decimals).unwrap();
+        };
+        out.extend_from_slice(b"{\"name\":");
+        write_json_string(out, station);
+        out.extend_from_slice(b",\"min\":");
+        number(out, record.min.into(), 1);
+        out.extend_from_slice(b",\"mean\":");
+        number(out, record.mean_at(self.precision), self.precision);
+        out.extend_from_slice(b",\"max\":");
+        number(out, record.max.into(), 1);
+        write!(out, ",\"count\":{}", record.count).unwrap();
+        if let Some(histogram) = &record.histogram {
+            out.extend_from_slice(b",\"histogram\":[");
+            for (i, count) in histogram.counts().iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write!(out, "{count}").unwrap();
+            }
+            out.push(b']');
+        }
+        out.push(b'}');
+    }
+}
+
+fn sorted_by(results: &Results, collation: Collation) -> Results {
+    let mut sorted = results.clone();
+    names::sort_stations(&mut sorted, collation);
+    sorted
+}
+
+/// Decodes the `%XX` escapes of a URL path segment, `None` if one is malformed
+fn percent_decode(segment: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(segment.len());
+    let mut bytes = segment.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            decoded.push(b);
+            continue;
+        }
+        let hex = [bytes.next()?, bytes.next()?];
+        if !hex.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> Server<impl Fn() -> Result<Results, Box<dyn Error>>> {
+        let record = |min, sum, max, count| MeasurementRecord {
+            count,
+            sum,
+            min,
+            max,
+            histogram: None,
+        };
+        let results = vec![
+            ("Oslo".as_bytes().into(), record(-30, -10, 10, 2)),
+            ("Zürich".as_bytes().into(), record(55, 55, 55, 1)),
+            ("Århus".as_bytes().into(), record(20, 60, 40, 2)),
+        ];
+        Server {
+            precision: 1,
+            results: RwLock::new(Arc::new(results)),
+            refreshing: Mutex::new(()),
+            refresh: move || Ok(vec![("Rome".as_bytes().into(), record(200, 200, 200, 1))]),
+        }
+    }
+
+    /// The status and body of the response to `method target`
+    fn respond(
+        server: &Server<impl Fn() -> Result<Results, Box<dyn Error>>>,
+        method: &str,
+        target: &str,
+    ) -> (&'static str, String) {
+        let response = server.respond(method, target);
+        (response.status, String::from_utf8(response.body).unwrap())
+    }
+
+    const OSLO: &str = r#"{"name":"Oslo","min":-3.0,"mean":-0.5,"max":1.0,"count":2}"#;
+    const ZURICH: &str = r#"{"name":"Zürich","min":5.5,"mean":5.5,"max":5.5,"count":1}"#;
+    const ARHUS: &str = r#"{"name":"Århus","min":2.0,"mean":3.0,"max":4.0,"count":2}"#;
+
+    #[test]
+    fn routes() {
+        let server = server();
+        assert_eq!(
+            respond(&server, "GET", "/healthz"),
+            ("200 OK", "ok\n".into())
+        );
+        assert_eq!(
+            respond(&server, "GET", "/stations"),
+            (
+                "200 OK",
+                format!("{{\"stations\":[{OSLO},{ZURICH},{ARHUS}]}}\n")
+            )
+        );
+        assert_eq!(
+            respond(&server, "GET", "/stations/Z%C3%BCrich"),
+            ("200 OK", format!("{ZURICH}\n"))
+        );
+        assert_eq!(
+            respond(&server, "POST", "/refresh"),
+            ("200 OK", "{\"stations\":1}\n".into())
+        );
+        assert_eq!(respond(&server, "GET", "/stations/Rome").0, "200 OK");
+        assert_eq!(respond(&server, "GET", "/stations/Oslo").0, "404 Not Found");
+    }
+
+    #[test]
+    fn errors() {
+        let server = server();
+        let error = |status, message: &str| (status, format!("{{\"error\":\"{message}\"}}\n"));
+        assert_eq!(
+            respond(&server, "GET", "/stations/Bern"),
+            error("404 Not Found", "unknown station")
+        );
+        assert_eq!(
+            respond(&server, "GET", "/nowhere"),
+            error("404 Not Found", "not found")
+        );
+        assert_eq!(
+            respond(&server, "GET", "/stations/%zz"),
+            error("400 Bad Request", "invalid percent-encoding")
+        );
+        for (method, target) in [
+            ("POST", "/stations"),
+            ("DELETE", "/healthz"),
+            ("GET", "/refresh"),
+            ("PUT", "/stations/Oslo"),
+        ] {
+            assert_eq!(
+                respond(&server, method, target),
+                error("405 Method Not Allowed", "method not allowed"),
+                "{method} {target}"
+            );
+        }
+    }
+
+    #[test]
+    fn top_and_sort() {
+        let server = server();
+        let stations = |query| respond(&server, "GET", &format!("/stations?{query}"));
+        let ok = |stations: &[&str]| {
+            (
+                "200 OK",
+                format!("{{\"stations\":[{}]}}\n", stations.join(",")),
+            )
+        };
+        assert_eq!(stations("top=2"), ok(&[OSLO, ZURICH]));
+        assert_eq!(stations("top=0"), ok(&[]));
+        assert_eq!(stations("top=10"), ok(&[OSLO, ZURICH, ARHUS]));
+        assert_eq!(stations("sort=bytes"), ok(&[OSLO, ZURICH, ARHUS]));
+        #[cfg(feature = "unicode")]
+        assert_eq!(
+            stations("sort=accent-insensitive&top=2"),
+            ok(&[ARHUS, OSLO])
+        );
+        assert_eq!(stations("top=-1").0, "400 Bad Request");
+        assert_eq!(stations("sort=random").0, "400 Bad Request");
+        assert_eq!(stations("limit=1").0, "400 Bad Request");
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("Oslo").unwrap(), b"Oslo");
+        assert_eq!(percent_decode("St.%20John%27s").unwrap(), b"St. John's");
+        assert_eq!(percent_decode("Z%c3%BCrich").unwrap(), "Zürich".as_bytes());
+        // Invalid UTF-8 is passed on as it is
+        assert_eq!(percent_decode("%ff%00").unwrap(), b"\xff\0");
+        for malformed in ["%", "%2", "%zz", "%%41", "a%g0"] {
+            assert_eq!(percent_decode(malformed), None, "{malformed}");
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::{error::Error, fmt, ops::Range};
+
+use crate::{checked::ParseError, sample::ChunkSample};
+
+/// Re-parses a seeded sample of chunks with the checked parser to catch the fast path silently
+/// misreading unusual input
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowCheck {
+    /// Share of chunks that are parsed twice, in percent
+    pub percent: f64,
+    pub seed: u64,
+}
+
+impl ShadowCheck {
+    /// Whether the chunk claimed at `offset` is in the sample. The same seed and chunk size pick
+    /// the same chunks on every run.
+    pub fn samples(&self, offset: usize) -> bool {
+        ChunkSample {
+            percent: self.percent,
+            seed: self.seed,
+        }
+        .samples(offset)
+    }
+}
+
+/// A sampled chunk that the fast and checked parsers aggregated differently
+#[derive(Debug, Clone)]
+pub struct ShadowMismatch {
+    /// The claimed byte range, the records starting in it were compared
+    pub chunk: Range<usize>,
+    /// Why the checked parser rejected the chunk, `None` if it just got different results
+    pub checked: Option<ParseError>,
+}
+
+impl ShadowMismatch {
+    /// Fails if the fast parser aggregated `chunk` into something else than the checked one did
+    pub(crate) fn check<M: PartialEq>(
+        chunk: Range<usize>,
+        fast: &M,
+        checked: &M,
+    ) -> Result<(), Self> {
+        match fast == checked {
+            true => Ok(()),
+            false => Err(Self {
+                chunk,
+                checked: None,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ShadowMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fast and checked parsers disagree on the chunk at bytes {}..{}",
+            self.chunk.start, self.chunk.end
+        )?;
+        match &self.checked {
+            Some(err) => write!(f, ", the checked parser found {err}"),
+            None => f.write_str(", they aggregated it differently"),
+        }
+    }
+}
+
+impl Error for ShadowMismatch {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aggregate_unchecked, avx2_available, process_chunk, AggregateError, AggregateOptions,
+        BumpAlloc, Map, MeasurementRecord, StationKey,
+    };
+
+    /// Aggregates `data[chunk]` with the fast parser or the checked one
+    fn parse<'a>(
+        data: &[u8],
+        chunk: Range<usize>,
+        bump: &'a BumpAlloc,
+        checked: bool,
+    ) -> Map<StationKey<'a>, MeasurementRecord> {
+        let mut map = Map::new();
+        let rules = checked.then_some(crate::checked::Checked {
+            max_station_len: usize::MAX,
+            whole_degrees: true,
+            quoted_names: false,
+            skip_invalid: false,
+        });
+        // SAFETY: the input is well-formed
+        unsafe {
+            process_chunk::<b';', b'.'>(
+                data,
+                chunk.start,
+                chunk.end,
+                &mut map,
+                bump,
+                avx2_available(),
+                rules,
+                None,
+                None,
+                0,
+            )
+        }
+        .unwrap();
+        map
+    }
+
+    #[test]
+    fn injected_discrepancy() {
+        let data = b"Oslo;1.0\nRome;20.5\nOslo;-3.0\nLima;15.0\n";
+        let chunk = 0..data.len();
+        let bump = BumpAlloc::new();
+        let checked = parse(data, chunk.clone(), &bump, true);
+        let mut fast = parse(data, chunk.clone(), &bump, false);
+        assert!(ShadowMismatch::check(chunk.clone(), &fast, &checked).is_ok());
+
+        // A fast parser misreading one value
+        let rome = StationKey::inline(b"Rome").unwrap();
+        fast.get_mut(&rome).unwrap().max = 250;
+        let err = ShadowMismatch::check(chunk, &fast, &checked).unwrap_err();
+        assert_eq!(err.chunk, 0..39);
+        assert!(err.checked.is_none());
+        assert_eq!(
+            err.to_string(),
+            "fast and checked parsers disagree on the chunk at bytes 0..39, they aggregated it \
+             differently"
+        );
+    }
+
+    #[test]
+    fn rejected_chunk() {
+        let data = "Oslo;10.0\n".repeat(4) + "Oslo;1x.0\n" + &"Oslo;10.0\n".repeat(5);
+        let options = AggregateOptions {
+            chunk_size: Some(20),
+            shadow_check: Some(ShadowCheck {
+                percent: 100.0,
+                seed: 0,
+            }),
+            ..Default::default()
+        };
+        // SAFETY: the shadow check parses every chunk with the checked parser first
+        let result = unsafe { aggregate_unchecked(data.as_bytes(), &options) };
+        let Err(AggregateError::Shadow(err)) = result else {
+            panic!("the malformed record wasn't caught: {result:?}");
+        };
+        // The chunk before the line owns it
+        assert_eq!(err.chunk, 20..40);
+        let checked = err.checked.unwrap();
+        assert_eq!(
+            (checked.offset, checked.reason),
+            (40, "malformed temperature")
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::arch::x86_64::{
+    __m256i, _mm256_cmpeq_epi8, _mm256_loadu_si256, _mm256_movemask_epi8, _mm256_set1_epi8,
+};
+
+const BLOCK: usize = 64;
+
+/// Bitmasks of the delimiter and `\n` positions within one 64-byte block
+#[derive(Clone, Copy)]
+struct Masks {
+    delimiters: u64,
+    newlines: u64,
+}
+
+#[inline(always)]
+unsafe fn block_masks<const DELIMITER: u8>(block: *const u8) -> Masks {
+    let delimiter = _mm256_set1_epi8(DELIMITER as i8);
+    let newline = _mm256_set1_epi8(b'\n' as i8);
+    let lo = _mm256_loadu_si256(block as *const __m256i);
+    let hi = _mm256_loadu_si256(block.add(32) as *const __m256i);
+    let mask = |needle| {
+        let lo = _mm256_movemask_epi8(_mm256_cmpeq_epi8(lo, needle)) as u32 as u64;
+        let hi = _mm256_movemask_epi8(_mm256_cmpeq_epi8(hi, needle)) as u32 as u64;
+        lo | (hi << 32)
+    };
+    Masks {
+        delimiters: mask(delimiter),
+        newlines: mask(newline),
+    }
+}
+
+/// Walks the records in `data` using AVX2 masks for `DELIMITER` and `\n`, calling `handle_entry`
+/// with the station and the raw `[-]d{1,2}.d` value of every record that ends inside a full
+/// 64-byte block. The value is also passed as the little-endian word of the 8 bytes it starts,
+/// for [`parse_value_swar`](crate::parse_value_swar), unless `data` ends before them.
+///
+/// Returns the offset of the first record that was not handled, the caller is expected to finish
+/// the tail with the scalar parser.
+///
+/// # Safety
+/// The CPU must support AVX2.
+#[target_feature(enable = "avx2")]
+pub unsafe fn scan_records<const DELIMITER: u8>(
+    data: &[u8],
+    mut handle_entry: impl FnMut(&[u8], &[u8], Option<u64>),
+) -> usize {
+    if data.len() < BLOCK {
+        return 0;
+    }
+    let ptr = data.as_ptr();
+    // The last block that can be loaded without reading past the end of `data`
+    let last_block = data.len() - BLOCK;
+
+    let mut record_start = 0;
+    let mut base = 0;
+    let mut masks = block_masks::<DELIMITER>(ptr);
+    loop {
+        while masks.delimiters == 0 {
+            base += BLOCK;
+            if base > last_block {
+                return record_start;
+            }
+            masks = block_masks::<DELIMITER>(ptr.add(base));
+        }
+        let delimiter = base + masks.delimiters.trailing_zeros() as usize;
+
+        // The newline always comes after the delimiter, so the bits up to and including the
+        // delimiter can be discarded
+        masks.newlines &= !0 << (delimiter - base);
+        while masks.newlines == 0 {
+            base += BLOCK;
+            if base > last_block {
+                return record_start;
+            }
+            masks = block_masks::<DELIMITER>(ptr.add(base));
+        }
+        let newline = base + masks.newlines.trailing_zeros() as usize;
+
+        let mut station = data.get_unchecked(record_start..delimiter);
+        // Skip any blank lines before the record
+        while let [b'\n', rest @ ..] = station {
+            station = rest;
+        }
+        let rest = data.get_unchecked(delimiter + 1..);
+        let word = rest
+            .first_chunk::<8>()
+            .map(|word| u64::from_le_bytes(*word));
+        handle_entry(station, data.get_unchecked(delimiter + 1..newline), word);
+
+        record_start = newline + 1;
+        // Clear everything up to and including the newline, the shift is split in two so it
+        // doesn't overflow when the newline is the last byte of the block
+        let keep = (!0u64 << (newline - base)) << 1;
+        masks.delimiters &= keep;
+        masks.newlines &= keep;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Rng;
+
+    /// Records with names of 1 to 100 bytes, so they start and end at every position of a block
+    fn generate(rng: &mut Rng, records: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for _ in 0..records {
+            if rng.below(16) == 0 {
+                data.push(b'\n');
+            }
+            let len = 1 + rng.below(100) as usize;
+            data.extend((0..len).map(|i| b'a' + (i % 26) as u8));
+            data.push(b';');
+            let value = match rng.below(4) {
+                0 => "-99.9",
+                1 => "5.0",
+                2 => "-3",
+                _ => "42.1",
+            };
+            data.extend_from_slice(value.as_bytes());
+            data.push(b'\n');
+        }
+        data
+    }
+
+    /// The records of `data` split the way the scalar parser does, each with its end
+    fn reference(data: &[u8]) -> Vec<(&[u8], &[u8], usize)> {
+        let mut records = Vec::new();
+        let mut start = 0;
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            start += line.len();
+            let Some(line) = line.strip_suffix(b"\n").filter(|line| !line.is_empty()) else {
+                continue;
+            };
+            let delimiter = line.iter().position(|&b| b == b';').unwrap();
+            records.push((&line[..delimiter], &line[delimiter + 1..], start));
+        }
+        records
+    }
+
+    #[test]
+    fn agrees_with_scalar() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let mut rng = Rng(0xa5c2);
+        for _ in 0..500 {
+            let records = rng.below(12) as usize;
+            let data = generate(&mut rng, records);
+            // Cut off anywhere in the last 80 bytes, so what is left after the last full block
+            // takes every length and may end inside a record
+            let data = &data[..data.len() - rng.below(data.len() as u64 + 1).min(80) as usize];
+            let mut scanned = Vec::new();
+            // SAFETY: checked above
+            let consumed = unsafe {
+                scan_records::<b';'>(data, |station, value, word| {
+                    let start = value.as_ptr() as usize - data.as_ptr() as usize;
+                    let expected = data[start..].first_chunk::<8>().copied();
+                    assert_eq!(word, expected.map(u64::from_le_bytes));
+                    scanned.push((station.to_vec(), value.to_vec()))
+                })
+            };
+            let expected = reference(data);
+            let handled: Vec<_> = expected
+                .iter()
+                .map(|&(station, value, _)| (station.to_vec(), value.to_vec()))
+                .collect();
+            assert_eq!(scanned, handled[..scanned.len()]);
+            let end = scanned
+                .len()
+                .checked_sub(1)
+                .map_or(0, |last| expected[last].2);
+            assert_eq!(consumed, end);
+            // Everything ending in one of the full blocks it could load was handled
+            let loaded = match data.len() {
+                len if len < BLOCK => 0,
+                len => (len - BLOCK) / BLOCK * BLOCK + BLOCK,
+            };
+            let unhandled = &expected[scanned.len()..];
+            assert!(unhandled.iter().all(|&(_, _, end)| end > loaded));
+            // and the scalar parser finds the others from where it stopped
+            assert_eq!(reference(&data[consumed..]).len(), unhandled.len());
+        }
+    }
+}
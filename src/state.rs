@@ -0,0 +1,143 @@
+//! Binary snapshots of an aggregation, so runs over successive inputs can be combined without
+//! re-reading the earlier ones.
+//!
+//! The layout is `MAGIC`, a version byte and the station count as a u64, followed by every
+//! station as a u32 name length, the name, then count (u64), sum (i64), min and max (i16). All
+//! integers are little-endian.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+use crate::{MeasurementRecord, Stations};
+
+const MAGIC: &[u8; 8] = b"1brcstat";
+/// Bumped on any change to the layout or to `MeasurementRecord`
+const VERSION: u8 = 1;
+
+pub fn save(path: &str, stations: &Stations) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    out.write_all(&(stations.len() as u64).to_le_bytes())?;
+    for (station, rec) in stations {
+        out.write_all(&(station.len() as u32).to_le_bytes())?;
+        out.write_all(station)?;
+        out.write_all(&(rec.count as u64).to_le_bytes())?;
+        out.write_all(&rec.sum.to_le_bytes())?;
+        out.write_all(&rec.min.to_le_bytes())?;
+        out.write_all(&rec.max.to_le_bytes())?;
+    }
+    out.flush()
+}
+
+pub fn load(path: &str) -> io::Result<Stations> {
+    let mut input = BufReader::new(File::open(path)?);
+    read_stations(&mut input).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => invalid("truncated state file".to_owned()),
+        _ => err,
+    })
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_stations(mut input: impl Read) -> io::Result<Stations> {
+    let mut header = [0; MAGIC.len() + 1];
+    input.read_exact(&mut header)?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not a state file".to_owned()));
+    }
+    if header[MAGIC.len()] != VERSION {
+        return Err(invalid(format!(
+            "state file version {} is not supported, expected {VERSION}",
+            header[MAGIC.len()]
+        )));
+    }
+    fn read<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        input.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+    let len = u64::from_le_bytes(read(&mut input)?);
+    let mut stations = Stations::new();
+    for _ in 0..len {
+        let name_len = u32::from_le_bytes(read(&mut input)?) as usize;
+        let mut station = vec![0; name_len];
+        input.read_exact(&mut station)?;
+        let rec = MeasurementRecord {
+            count: u64::from_le_bytes(read(&mut input)?) as usize,
+            sum: i64::from_le_bytes(read(&mut input)?),
+            min: i16::from_le_bytes(read(&mut input)?),
+            max: i16::from_le_bytes(read(&mut input)?),
+            histogram: None,
+        };
+        if stations.insert(station.into(), rec).is_some() {
+            return Err(invalid("duplicate station in state file".to_owned()));
+        }
+    }
+    if input.read(&mut [0])? != 0 {
+        return Err(invalid("trailing bytes after the last station".to_owned()));
+    }
+    Ok(stations)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{aggregate, merge_maps, tests::TempFile, AggregateOptions};
+
+    #[test]
+    fn merged_state_equals_whole_input() {
+        let a: &[u8] = b"Hamburg;12.0\nBulawayo;8.9\nPalembang;38.8\nHamburg;-3.4\n";
+        let b: &[u8] = b"Bulawayo;-0.1\nSt. John's;15.2\nHamburg;34.2\n";
+        let options = AggregateOptions::default();
+        let file = TempFile::new("round-trip.state");
+        save(file.path(), &aggregate(a, &options).unwrap()).unwrap();
+        let mut merged = load(file.path()).unwrap();
+        merge_maps(&mut merged, aggregate(b, &options).unwrap());
+        assert_eq!(merged, aggregate(&[a, b].concat(), &options).unwrap());
+    }
+
+    fn load_bytes(name: &str, bytes: &[u8]) -> io::Error {
+        let file = TempFile::new(&format!("{name}.state"));
+        fs::write(&file.0, bytes).unwrap();
+        load(file.path()).unwrap_err()
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let err = load_bytes("magic", b"1brcstaT\x01\0\0\0\0\0\0\0\0");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "not a state file");
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let err = load_bytes("version", b"1brcstat\x02\0\0\0\0\0\0\0\0");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "state file version 2 is not supported, expected 1"
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let file = TempFile::new("whole.state");
+        let stations = aggregate(b"Hamburg;12.0\nBulawayo;8.9\n", &Default::default()).unwrap();
+        save(file.path(), &stations).unwrap();
+        let bytes = fs::read(&file.0).unwrap();
+        // Cut off in the magic, the station count, the first name and the last record
+        let first_name = MAGIC.len() + 1 + 8 + 4 + 2;
+        for len in [4, MAGIC.len() + 4, first_name, bytes.len() - 1] {
+            let err = load_bytes("truncated", &bytes[..len]);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{len}");
+            assert_eq!(err.to_string(), "truncated state file");
+        }
+    }
+}
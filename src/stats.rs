@@ -0,0 +1,343 @@
+//! Counters behind `--stats`, gathered once per worker rather than per row.
+
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+use crate::{cpus::CpuLimit, numa::Topology};
+
+/// Station name arena of a worker, see [`crate::BumpAlloc::usage`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArenaUsage {
+    pub chunks: usize,
+    /// Bytes allocated for the chunks
+    pub capacity: usize,
+    /// Bytes holding station names, the rest is wasted
+    pub used: usize,
+}
+
+impl ArenaUsage {
+    pub fn wasted(&self) -> usize {
+        self.capacity - self.used
+    }
+
+    pub(crate) fn add(&mut self, other: &Self) {
+        self.chunks += other.chunks;
+        self.capacity += other.capacity;
+        self.used += other.used;
+    }
+}
+
+/// What one worker did during one `aggregate` call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WorkerStats {
+    pub thread: usize,
+    pub chunks: usize,
+    /// Bytes of the parsed chunks
+    pub bytes: usize,
+    pub rows: usize,
+    pub arena: ArenaUsage,
+    /// Capacity of the worker's map when it finished
+    pub map_capacity: usize,
+    /// Time spent claiming and parsing chunks
+    pub busy: Duration,
+    /// Time spent parsing the first chunk
+    pub first_chunk: Duration,
+    /// Median time spent parsing a chunk
+    pub median_chunk: Duration,
+}
+
+impl WorkerStats {
+    /// How much longer the first chunk took than the median one, mostly page faults and cold
+    /// caches, which `--prefault-parallel` and `--populate` are meant to take out of the parse
+    pub fn first_chunk_overhead(&self) -> Duration {
+        self.first_chunk.saturating_sub(self.median_chunk)
+    }
+}
+
+/// Summary of a whole run
+#[derive(Debug, Clone)]
+pub struct RunStats {
+    pub elapsed: Duration,
+    /// Distinct stations of the merged result
+    pub stations: usize,
+    /// One entry per worker thread, summed over every `aggregate` call, with the peak capacity and
+    /// the chunk times of the first call the thread parsed a chunk in
+    pub threads: Vec<WorkerStats>,
+    /// The nodes the workers were pinned to with `--numa`
+    pub numa: Option<Topology>,
+    /// Size of the input when only a sample of it was parsed
+    pub sampled: Option<u64>,
+    /// What the default number of workers was picked from
+    pub cpus: Option<CpuLimit>,
+}
+
+impl RunStats {
+    pub fn new(workers: &[WorkerStats], elapsed: Duration, stations: usize) -> Self {
+        let mut threads: Vec<WorkerStats> = Vec::new();
+        for worker in workers {
+            if threads.len() <= worker.thread {
+                threads.resize(worker.thread + 1, WorkerStats::default());
+            }
+            let total = &mut threads[worker.thread];
+            if total.chunks == 0 {
+                total.first_chunk = worker.first_chunk;
+                total.median_chunk = worker.median_chunk;
+            }
+            total.thread = worker.thread;
+            total.chunks += worker.chunks;
+            total.bytes += worker.bytes;
+            total.rows += worker.rows;
+            total.arena.add(&worker.arena);
+            total.map_capacity = total.map_capacity.max(worker.map_capacity);
+            total.busy += worker.busy;
+        }
+        Self {
+            elapsed,
+            stations,
+            threads,
+            numa: None,
+            sampled: None,
+            cpus: None,
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.threads.iter().map(|thread| thread.bytes).sum()
+    }
+
+    pub fn rows(&self) -> usize {
+        self.threads.iter().map(|thread| thread.rows).sum()
+    }
+
+    pub fn arena(&self) -> ArenaUsage {
+        let mut arena = ArenaUsage::default();
+        for thread in &self.threads {
+            arena.add(&thread.arena);
+        }
+        arena
+    }
+
+    pub fn peak_map_capacity(&self) -> usize {
+        self.threads
+            .iter()
+            .map(|thread| thread.map_capacity)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The largest first chunk overhead of any thread
+    pub fn max_first_chunk_overhead(&self) -> Duration {
+        self.threads
+            .iter()
+            .map(WorkerStats::first_chunk_overhead)
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn gb_per_s(&self) -> f64 {
+        self.bytes() as f64 / 1e9 / self.elapsed.as_secs_f64()
+    }
+
+    fn rows_per_s(&self) -> f64 {
+        self.rows() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn write_text(&self, mut out: impl Write) -> io::Result<()> {
+        let arena = self.arena();
+        writeln!(out, "stats:")?;
+        writeln!(
+            out,
+            "  input:    {} bytes, {} rows, {} stations",
+            self.bytes(),
+            self.rows(),
+            self.stations
+        )?;
+        writeln!(
+            out,
+            "  time:     {:.3} s, {:.2} GB/s, {:.1}M rows/s",
+            self.elapsed.as_secs_f64(),
+            self.gb_per_s(),
+            self.rows_per_s() / 1e6
+        )?;
+        writeln!(
+            out,
+            "  arena:    {} chunks, {} of {} bytes used, {} wasted",
+            arena.chunks,
+            arena.used,
+            arena.capacity,
+            arena.wasted()
+        )?;
+        writeln!(
+            out,
+            "  maps:     peak capacity {}",
+            self.peak_map_capacity()
+        )?;
+        writeln!(
+            out,
+            "  warmup:   first chunk up to {:.3} ms slower than the median one",
+            self.max_first_chunk_overhead().as_secs_f64() * 1e3
+        )?;
+        if let Some(len) = self.sampled {
+            writeln!(
+                out,
+                "  sample:   {:.2}% of {len} bytes, the results are approximate",
+                self.bytes() as f64 * 100.0 / len.max(1) as f64
+            )?;
+        }
+        if let Some(topology) = &self.numa {
+            writeln!(out, "  numa:     {topology}")?;
+        }
+        if let Some(cpus) = &self.cpus {
+            writeln!(out, "  cpus:     {cpus}")?;
+        }
+        writeln!(out, "  threads:")?;
+        for thread in &self.threads {
+            writeln!(
+                out,
+                "    {:>3}: {} chunks, {} bytes, {} rows, map capacity {}, first chunk +{:.3} ms",
+                thread.thread,
+                thread.chunks,
+                thread.bytes,
+                thread.rows,
+                thread.map_capacity,
+                thread.first_chunk_overhead().as_secs_f64() * 1e3
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the summary as a single line JSON object
+    pub fn write_json(&self, mut out: impl Write) -> io::Result<()> {
+        let arena = self.arena();
+        write!(
+            out,
+            "{{\"bytes\":{},\"rows\":{},\"stations\":{},\"seconds\":{},\"gb_per_s\":{},\
+             \"rows_per_s\":{},\"arena\":{{\"chunks\":{},\"capacity\":{},\"used\":{},\
+             \"wasted\":{}}},\"peak_map_capacity\":{},\"max_first_chunk_overhead_ms\":{},\
+             \"threads\":[",
+            self.bytes(),
+            self.rows(),
+            self.stations,
+            self.elapsed.as_secs_f64(),
+            self.gb_per_s(),
+            self.rows_per_s(),
+            arena.chunks,
+            arena.capacity,
+            arena.used,
+            arena.wasted(),
+            self.peak_map_capacity(),
+            self.max_first_chunk_overhead().as_secs_f64() * 1e3
+        )?;
+        for (i, thread) in self.threads.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write!(
+                out,
+                "{{\"thread\":{},\"chunks\":{},\"bytes\":{},\"rows\":{},\"arena_used\":{},\
+                 \"map_capacity\":{},\"first_chunk_overhead_ms\":{}}}",
+                thread.thread,
+                thread.chunks,
+                thread.bytes,
+                thread.rows,
+                thread.arena.used,
+                thread.map_capacity,
+                thread.first_chunk_overhead().as_secs_f64() * 1e3
+            )?;
+        }
+        match self.sampled {
+            Some(len) => write!(
+                out,
+                "],\"sample\":{{\"input_bytes\":{len},\"fraction\":{}}}",
+                self.bytes() as f64 / len.max(1) as f64
+            )?,
+            None => write!(out, "],\"sample\":null")?,
+        }
+        write!(out, ",\"cpus\":")?;
+        match &self.cpus {
+            Some(cpus) => cpus.write_json(&mut out)?,
+            None => write!(out, "null")?,
+        }
+        write!(out, ",\"numa\":")?;
+        match &self.numa {
+            Some(topology) => {
+                write!(out, "[")?;
+                for (i, cpus) in topology.nodes.iter().enumerate() {
+                    if i > 0 {
+                        write!(out, ",")?;
+                    }
+                    let cpus: Vec<_> = cpus.iter().map(usize::to_string).collect();
+                    write!(out, "[{}]", cpus.join(","))?;
+                }
+                writeln!(out, "]}}")
+            }
+            None => writeln!(out, "null}}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{aggregate, AggregateOptions};
+
+    #[test]
+    fn first_chunk_overhead_with_prefault() {
+        let input = "Hamburg;12.0\nBulawayo;8.9\nPalembang;38.8\n".repeat(2000);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let options = AggregateOptions {
+            threads: 2,
+            chunk_size: Some(4096),
+            prefault: true,
+            stats: Some(Arc::clone(&sink)),
+            ..Default::default()
+        };
+        let stations = aggregate(input.as_bytes(), &options).unwrap();
+        let workers = sink.lock().unwrap();
+        let stats = RunStats::new(&workers, Duration::from_millis(1), stations.len());
+        assert_eq!(stats.rows(), 6000);
+        // Both threads fault in their share, but one may claim every chunk
+        assert!(stats.threads.iter().any(|thread| thread.chunks > 0));
+        for thread in stats.threads.iter().filter(|thread| thread.chunks > 0) {
+            assert!(thread.first_chunk > Duration::ZERO, "{thread:?}");
+            assert!(thread.median_chunk > Duration::ZERO, "{thread:?}");
+            assert_eq!(
+                thread.first_chunk_overhead(),
+                thread.first_chunk.saturating_sub(thread.median_chunk)
+            );
+        }
+
+        let mut text = Vec::new();
+        stats.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("warmup:   first chunk up to "), "{text}");
+        assert_eq!(text.matches("first chunk +").count(), stats.threads.len());
+        let mut json = Vec::new();
+        stats.write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains("\"max_first_chunk_overhead_ms\":"), "{json}");
+        assert_eq!(
+            json.matches("\"first_chunk_overhead_ms\":").count(),
+            stats.threads.len()
+        );
+    }
+
+    #[test]
+    fn first_chunk_comes_from_the_first_call() {
+        let worker = |chunks, first_ms, median_ms| WorkerStats {
+            thread: 0,
+            chunks,
+            first_chunk: Duration::from_millis(first_ms),
+            median_chunk: Duration::from_millis(median_ms),
+            ..Default::default()
+        };
+        let workers = [worker(0, 0, 0), worker(3, 9, 2), worker(4, 5, 1)];
+        let stats = RunStats::new(&workers, Duration::from_secs(1), 1);
+        assert_eq!(stats.threads[0].chunks, 7);
+        assert_eq!(stats.max_first_chunk_overhead(), Duration::from_millis(7));
+    }
+}
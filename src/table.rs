@@ -0,0 +1,266 @@
+//! Per-worker station tables, picked with `--table`.
+//!
+//! Workers only ever add readings to their table and drain it once at the end, so the table
+//! behind the hot loop only needs [`StationTable::upsert`] and iteration. The merged result
+//! always uses the hashbrown [`Map`].
+
+use std::hash::BuildHasher;
+
+use hashbrown::hash_map::{DefaultHashBuilder, Entry, RawEntryMut};
+
+use crate::{handle_entry, BumpAlloc, Map, MeasurementRecord, StationKey};
+
+/// Which [`StationTable`] the workers aggregate into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Table {
+    /// hashbrown's [`Map`]
+    #[default]
+    Hashbrown,
+    /// [`FlatTable`]
+    Flat,
+}
+
+/// A [`StationTable`] type for every lifetime of the worker's arena, so `work` can be instantiated
+/// per table
+pub trait TableKind {
+    type Table<'a>: StationTable<'a>;
+}
+
+pub struct HashbrownKind;
+
+impl TableKind for HashbrownKind {
+    type Table<'a> = Map<StationKey<'a>, MeasurementRecord>;
+}
+
+pub struct FlatKind;
+
+impl TableKind for FlatKind {
+    type Table<'a> = FlatTable<'a>;
+}
+
+/// What a worker aggregates its chunks into
+pub trait StationTable<'a> {
+    fn with_capacity(capacity: usize) -> Self;
+
+    /// Adds one reading, copying the station name into `bump` the first time it is seen unless
+    /// it is short enough to be stored inline. New stations get a histogram of `histogram`
+    /// buckets, or none if it is 0.
+    fn upsert(&mut self, bump: &'a BumpAlloc, station: &[u8], value: i16, histogram: usize);
+
+    /// Adds one reading to a station that is already in the table, returns false without adding
+    /// it if the station isn't
+    fn update(&mut self, station: &[u8], value: i16) -> bool;
+
+    /// Merges the aggregate of a station from another table
+    fn merge(&mut self, station: StationKey<'a>, record: MeasurementRecord);
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn capacity(&self) -> usize;
+
+    fn records(&self) -> impl Iterator<Item = &MeasurementRecord>;
+
+    fn into_iter(self) -> impl Iterator<Item = (StationKey<'a>, MeasurementRecord)>;
+}
+
+impl<'a> StationTable<'a> for Map<StationKey<'a>, MeasurementRecord> {
+    fn with_capacity(capacity: usize) -> Self {
+        Map::with_capacity(capacity)
+    }
+
+    #[inline(always)]
+    fn upsert(&mut self, bump: &'a BumpAlloc, station: &[u8], value: i16, histogram: usize) {
+        handle_entry(self, bump, station, value, histogram);
+    }
+
+    #[inline(always)]
+    fn update(&mut self, station: &[u8], value: i16) -> bool {
+        let inline = StationKey::inline(station);
+        let hash = match &inline {
+            Some(inline) => self.hasher().hash_one(inline),
+            None => self.hasher().hash_one(station),
+        };
+        let entry = self.raw_entry_mut().from_hash(hash, |key| match &inline {
+            Some(inline) => key == inline,
+            None => key.as_bytes() == station,
+        });
+        match entry {
+            RawEntryMut::Occupied(mut entry) => {
+                entry.get_mut().add(value);
+                true
+            }
+            RawEntryMut::Vacant(_) => false,
+        }
+    }
+
+    fn merge(&mut self, station: StationKey<'a>, record: MeasurementRecord) {
+        match self.entry(station) {
+            Entry::Occupied(mut existing) => existing.get_mut().merge(&record),
+            Entry::Vacant(slot) => {
+                slot.insert(record);
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        Map::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        Map::capacity(self)
+    }
+
+    fn records(&self) -> impl Iterator<Item = &MeasurementRecord> {
+        self.values()
+    }
+
+    fn into_iter(self) -> impl Iterator<Item = (StationKey<'a>, MeasurementRecord)> {
+        IntoIterator::into_iter(self)
+    }
+}
+
+struct Slot<'a> {
+    hash: u64,
+    key: StationKey<'a>,
+    record: MeasurementRecord,
+}
+
+/// An open addressing table with linear probing over a power of two number of slots, kept at
+/// most half full and doubled when it would be fuller.
+///
+/// It has none of hashbrown's control bytes or SIMD group probing: with at most a few thousand
+/// stations, almost every lookup finds its station in the first slot it looks at, and names are
+/// only compared once the full hash matches.
+pub struct FlatTable<'a> {
+    slots: Box<[Option<Slot<'a>>]>,
+    len: usize,
+    hasher: DefaultHashBuilder,
+}
+
+impl<'a> FlatTable<'a> {
+    /// The slot holding the station with `hash` for which `eq` holds, or the empty slot where it
+    /// would be inserted
+    #[inline(always)]
+    fn find(&self, hash: u64, eq: impl Fn(&StationKey<'a>) -> bool) -> usize {
+        let mask = self.slots.len() - 1;
+        let mut index = hash as usize & mask;
+        loop {
+            match &self.slots[index] {
+                Some(slot) if slot.hash == hash && eq(&slot.key) => return index,
+                Some(_) => index = (index + 1) & mask,
+                None => return index,
+            }
+        }
+    }
+
+    /// Inserts a station that isn't in the table yet
+    #[cold]
+    fn insert(&mut self, hash: u64, key: StationKey<'a>, record: MeasurementRecord) {
+        if (self.len + 1) * 2 > self.slots.len() {
+            self.grow();
+        }
+        let index = self.find(hash, |_| false);
+        self.slots[index] = Some(Slot { hash, key, record });
+        self.len += 1;
+    }
+
+    fn grow(&mut self) {
+        let slots = empty_slots(self.slots.len() * 2);
+        let old = std::mem::replace(&mut self.slots, slots);
+        for slot in old.into_vec().into_iter().flatten() {
+            let index = self.find(slot.hash, |_| false);
+            self.slots[index] = Some(slot);
+        }
+    }
+
+    #[inline(always)]
+    fn hash(&self, inline: &Option<StationKey<'_>>, station: &[u8]) -> u64 {
+        // Hashed the same way as in the hashbrown map, see `StationKey`'s Hash
+        match inline {
+            Some(inline) => self.hasher.hash_one(inline),
+            None => self.hasher.hash_one(station),
+        }
+    }
+}
+
+fn empty_slots<'a>(len: usize) -> Box<[Option<Slot<'a>>]> {
+    std::iter::repeat_with(|| None).take(len).collect()
+}
+
+impl<'a> StationTable<'a> for FlatTable<'a> {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: empty_slots((capacity * 2).next_power_of_two().max(16)),
+            len: 0,
+            hasher: DefaultHashBuilder::default(),
+        }
+    }
+
+    #[inline(always)]
+    fn upsert(&mut self, bump: &'a BumpAlloc, station: &[u8], value: i16, histogram: usize) {
+        let inline = StationKey::inline(station);
+        let hash = self.hash(&inline, station);
+        let index = self.find(hash, |key| match &inline {
+            Some(inline) => key == inline,
+            None => key.as_bytes() == station,
+        });
+        match &mut self.slots[index] {
+            Some(slot) => slot.record.add(value),
+            None => self.insert(
+                hash,
+                inline.unwrap_or_else(|| StationKey::Arena(bump.alloc_slice(station))),
+                MeasurementRecord::first(value, histogram),
+            ),
+        }
+    }
+
+    #[inline(always)]
+    fn update(&mut self, station: &[u8], value: i16) -> bool {
+        let inline = StationKey::inline(station);
+        let hash = self.hash(&inline, station);
+        let index = self.find(hash, |key| match &inline {
+            Some(inline) => key == inline,
+            None => key.as_bytes() == station,
+        });
+        match &mut self.slots[index] {
+            Some(slot) => {
+                slot.record.add(value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn merge(&mut self, station: StationKey<'a>, record: MeasurementRecord) {
+        let hash = self.hasher.hash_one(station);
+        let index = self.find(hash, |key| *key == station);
+        match &mut self.slots[index] {
+            Some(slot) => slot.record.merge(&record),
+            None => self.insert(hash, station, record),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    fn records(&self) -> impl Iterator<Item = &MeasurementRecord> {
+        self.slots.iter().flatten().map(|slot| &slot.record)
+    }
+
+    fn into_iter(self) -> impl Iterator<Item = (StationKey<'a>, MeasurementRecord)> {
+        self.slots
+            .into_vec()
+            .into_iter()
+            .flatten()
+            .map(|slot| (slot.key, slot.record))
+    }
+}
@@ -0,0 +1,108 @@
+//! Wall-clock breakdown of a run for `--timing`, taken at phase boundaries and once per worker,
+//! never per row.
+
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use crate::cpus::CpuLimit;
+
+#[derive(Debug, Clone, Default)]
+pub struct Timing {
+    /// Opening the input and reading its metadata
+    pub open: Duration,
+    /// Mapping the windows of the input
+    pub map: Duration,
+    /// The `aggregate` calls, from the first chunk claimed to the last worker done
+    pub parse: Duration,
+    /// Merging windows and saved states, normalizing names and grouping
+    pub merge: Duration,
+    /// Sorting the stations, and redacting their values
+    pub sort: Duration,
+    /// Writing the report
+    pub write: Duration,
+    /// Time every worker spent claiming and parsing chunks, summed over all calls
+    pub busy: Vec<Duration>,
+    /// What the default number of workers was picked from
+    pub cpus: Option<CpuLimit>,
+}
+
+impl Timing {
+    /// Runs `f` and adds its duration to `phase`
+    pub fn time<T>(phase: &mut Duration, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        *phase += started.elapsed();
+        result
+    }
+
+    pub fn total(&self) -> Duration {
+        self.open + self.map + self.parse + self.merge + self.sort + self.write
+    }
+
+    fn phases(&self) -> [(&'static str, Duration); 7] {
+        [
+            ("open", self.open),
+            ("map", self.map),
+            ("parse", self.parse),
+            ("merge", self.merge),
+            ("sort", self.sort),
+            ("write", self.write),
+            ("total", self.total()),
+        ]
+    }
+
+    pub fn write_text(&self, mut out: impl Write) -> io::Result<()> {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1e3;
+        writeln!(out, "timing:")?;
+        for (phase, duration) in self.phases() {
+            writeln!(
+                out,
+                "  {:<9} {:>10.3} ms",
+                format!("{phase}:"),
+                ms(duration)
+            )?;
+        }
+        if let Some(cpus) = &self.cpus {
+            writeln!(out, "  cpus:     {cpus}")?;
+        }
+        let (Some(fastest), Some(slowest)) = (self.busy.iter().min(), self.busy.iter().max())
+        else {
+            return Ok(());
+        };
+        writeln!(
+            out,
+            "  workers:  busy {:.3}..{:.3} ms, skew {:.3} ms",
+            ms(*fastest),
+            ms(*slowest),
+            ms(*slowest - *fastest)
+        )?;
+        for (thread, busy) in self.busy.iter().enumerate() {
+            writeln!(out, "    {thread:>3}: {:.3} ms", ms(*busy))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the breakdown as a single line JSON object, in milliseconds
+    pub fn write_json(&self, mut out: impl Write) -> io::Result<()> {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1e3;
+        write!(out, "{{")?;
+        for (phase, duration) in self.phases() {
+            write!(out, "\"{phase}_ms\":{:.3},", ms(duration))?;
+        }
+        if let Some(cpus) = &self.cpus {
+            write!(out, "\"cpus\":")?;
+            cpus.write_json(&mut out)?;
+            write!(out, ",")?;
+        }
+        write!(out, "\"busy_ms\":[")?;
+        for (thread, busy) in self.busy.iter().enumerate() {
+            if thread > 0 {
+                write!(out, ",")?;
+            }
+            write!(out, "{:.3}", ms(*busy))?;
+        }
+        writeln!(out, "]}}")
+    }
+}
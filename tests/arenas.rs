@@ -0,0 +1,62 @@
+//! Worker arenas are freed once their names are copied out, checked by counting the live arena
+//! chunks with a wrapper around the system allocator. In its own test binary since the counts are
+//! global.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use rs::{aggregate, AggregateOptions};
+
+/// Size of an arena chunk
+const CHUNK: usize = 1024 * 1024;
+
+struct Counting {
+    live: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == CHUNK {
+            let live = self.live.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(live, Ordering::SeqCst);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() == CHUNK {
+            self.live.fetch_sub(1, Ordering::SeqCst);
+        }
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting {
+    live: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+};
+
+#[test]
+fn arenas_are_freed_after_the_merge() {
+    // 30k names of 100 bytes fill a few arena chunks on every worker
+    let mut input = Vec::new();
+    for i in 0..30_000 {
+        input.extend_from_slice(format!("{i:0>100};12.3\n").as_bytes());
+    }
+    for threads in [1, 4] {
+        let options = AggregateOptions {
+            threads,
+            chunk_size: Some(64 * 1024),
+            ..Default::default()
+        };
+        ALLOCATOR.peak.store(0, Ordering::SeqCst);
+        let stations = aggregate(&input, &options).unwrap();
+        assert_eq!(stations.len(), 30_000);
+        assert!(ALLOCATOR.peak.load(Ordering::SeqCst) > 1, "{threads}");
+        assert_eq!(ALLOCATOR.live.load(Ordering::SeqCst), 0, "{threads}");
+    }
+}
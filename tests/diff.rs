@@ -0,0 +1,43 @@
+//! Runs `rs diff` and checks its exit status.
+
+use std::{path::PathBuf, process::Command};
+
+/// Writes `contents` to a file of the test's temporary directory
+fn input(name: &str, contents: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, contents).unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+/// Runs `rs diff` with `args`, returning its exit code and stdout
+fn diff(args: &[&str]) -> (i32, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rs"))
+        .arg("diff")
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn exit_status() {
+    let old = input("diff-old", "Oslo;1.0\nRome;20.0\n");
+    let same = input("diff-same", "Rome;20.0\nOslo;1.0\n");
+    let new = input("diff-new", "Oslo;1.0\nRome;21.0\n");
+
+    assert_eq!(diff(&[&old, &same]), (0, String::new()));
+    let (code, stdout) = diff(&[&old, &new]);
+    assert_eq!(code, 1);
+    assert_eq!(
+        stdout,
+        "changed (count;min;mean;max):\nRome;+0;+1.0;+1.0;+1.0\n"
+    );
+    // Within the threshold nothing differs
+    assert_eq!(diff(&[&old, &new, "--threshold", "1"]).0, 0);
+    // Errors exit with 1 as well, without a diff
+    let (code, stdout) = diff(&[&old, "missing-file"]);
+    assert_eq!((code, &*stdout), (1, ""));
+}
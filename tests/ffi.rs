@@ -0,0 +1,56 @@
+//! Compiles `ffi/check.c` against the shared library of this build and runs it, under valgrind
+//! when it is installed. Skipped if there is no C compiler.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The directory `librs.so` of this build is in, next to the test binary
+fn library_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+fn available(program: &str) -> bool {
+    Command::new(program).arg("--version").output().is_ok()
+}
+
+#[test]
+fn c_api() {
+    if !available("cc") {
+        eprintln!("skipped, cc isn't installed");
+        return;
+    }
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let lib = library_dir();
+    assert!(lib.join("librs.so").exists(), "{lib:?} has no librs.so");
+    let check = Path::new(env!("CARGO_TARGET_TMPDIR")).join("check");
+    let status = Command::new("cc")
+        .arg("-o")
+        .arg(&check)
+        .arg(root.join("ffi/check.c"))
+        .arg("-I")
+        .arg(root.join("ffi"))
+        .arg("-L")
+        .arg(&lib)
+        .arg(format!("-Wl,-rpath,{}", lib.display()))
+        .arg("-lrs")
+        .status()
+        .unwrap();
+    assert!(status.success(), "compiling ffi/check.c failed");
+
+    let mut command = match available("valgrind") {
+        true => {
+            let mut valgrind = Command::new("valgrind");
+            valgrind.args(["--leak-check=full", "--error-exitcode=1", "--quiet"]);
+            valgrind.arg(&check);
+            valgrind
+        }
+        false => Command::new(&check),
+    };
+    let output = command.output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert_eq!(output.stdout, b"ok\n", "{stderr}");
+}
@@ -0,0 +1,69 @@
+//! Runs the binary with `--follow` while appending to its input, stopping it with SIGINT.
+#![cfg(target_os = "linux")]
+
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn appended_records() {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("follow-input");
+    // The last line is still being written
+    std::fs::write(&path, "Oslo;1.0\nRome;2").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rs"))
+        .args([path.to_str().unwrap(), "--follow", "--interval", "20ms"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let (send, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in stdout.lines() {
+            send.send(line.unwrap()).unwrap();
+        }
+    });
+    let report = |len| -> Vec<String> {
+        (0..len)
+            .map(|_| lines.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect()
+    };
+    assert_eq!(report(1), ["Oslo;1.0;1.0;1.0"]);
+
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"0.0\nOslo;3.0\n").unwrap();
+    assert_eq!(report(3), ["", "Oslo;1.0;2.0;3.0", "Rome;20.0;20.0;20.0"]);
+
+    file.write_all(b"Lima;-5.5\n").unwrap();
+    assert_eq!(
+        report(4),
+        [
+            "",
+            "Lima;-5.5;-5.5;-5.5",
+            "Oslo;1.0;2.0;3.0",
+            "Rome;20.0;20.0;20.0"
+        ]
+    );
+
+    // Ctrl-C between passes ends with the final report
+    // SAFETY: only sends a signal to the child
+    assert_eq!(unsafe { libc::kill(child.id() as i32, libc::SIGINT) }, 0);
+    assert!(child.wait().unwrap().success());
+    let rest: Vec<String> = lines.iter().collect();
+    assert_eq!(
+        rest,
+        [
+            "",
+            "Lima;-5.5;-5.5;-5.5",
+            "Oslo;1.0;2.0;3.0",
+            "Rome;20.0;20.0;20.0"
+        ]
+    );
+}
@@ -0,0 +1,47 @@
+//! Interrupts the binary with SIGINT while it parses, it reports what it aggregated so far.
+#![cfg(target_os = "linux")]
+
+use std::{
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn partial_results() {
+    // Takes the debug binary seconds on one thread
+    let records = 4 << 20;
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("interrupt-input");
+    std::fs::write(&path, "Oslo;1.0\n".repeat(records)).unwrap();
+    let len = records * 9;
+
+    let child = Command::new(env!("CARGO_BIN_EXE_rs"))
+        .args([
+            path.to_str().unwrap(),
+            "--threads",
+            "1",
+            "--chunk-size",
+            "64K",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    // SAFETY: only sends a signal to the child
+    assert_eq!(unsafe { libc::kill(child.id() as i32, libc::SIGINT) }, 0);
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(130), "{stderr}");
+
+    // The readings parsed before the interrupt are reported
+    assert_eq!(output.stdout, b"Oslo;1.0;1.0;1.0\n");
+    let parsed: usize = stderr
+        .strip_prefix("warning: interrupted, the results only cover ")
+        .and_then(|rest| rest.strip_suffix(&format!(" of {len} bytes of {}\n", path.display())))
+        .unwrap_or_else(|| panic!("{stderr}"))
+        .parse()
+        .unwrap();
+    assert!(0 < parsed && parsed < len, "{stderr}");
+}
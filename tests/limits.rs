@@ -0,0 +1,65 @@
+//! Runs the binary on inputs that go over the limits of `--untrusted`.
+
+use std::{path::PathBuf, process::Command};
+
+/// Writes `contents` to a file of the test's temporary directory
+fn input(name: &str, contents: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Runs the binary with `args`, returning its exit code, stdout and stderr
+fn run(args: &[&str]) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rs"))
+        .args(args)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    (output.status.code().unwrap(), stdout, stderr)
+}
+
+#[test]
+fn over_limit_file() {
+    let path = input("over-limit", &"Oslo;10.0\n".repeat(10));
+    let path = path.to_str().unwrap();
+    let (code, stdout, stderr) = run(&[path, "--untrusted", "--max-input-size", "99"]);
+    assert_eq!(code, 1);
+    assert_eq!(stdout, "");
+    assert!(
+        stderr.contains("100 bytes of input exceed --max-input-size 99"),
+        "{stderr}"
+    );
+
+    let (code, stdout, _) = run(&[path, "--untrusted", "--max-input-size", "100"]);
+    assert_eq!((code, &*stdout), (0, "Oslo;10.0;10.0;10.0\n"));
+}
+
+#[test]
+fn over_limit_output() {
+    let records: String = (0..100).map(|i| format!("s{i:02};1.0\n")).collect();
+    let path = input("over-limit-output", &records);
+    let path = path.to_str().unwrap();
+    let (code, stdout, stderr) = run(&[path, "--untrusted", "--max-output-size", "1k"]);
+    assert_eq!(code, 1);
+    // What fits is printed
+    assert_eq!(stdout.len(), 1024);
+    assert!(stdout.starts_with("s00;1.0;1.0;1.0\n"));
+    assert!(stderr.contains("exceed --max-output-size 1024"), "{stderr}");
+
+    let (code, stdout, _) = run(&[path, "--untrusted", "--max-output-size", "2k"]);
+    assert_eq!((code, stdout.lines().count()), (0, 100));
+}
+
+#[test]
+fn timeout() {
+    let path = input("timeout", &"Oslo;10.0\n".repeat(1000));
+    let path = path.to_str().unwrap();
+    let (code, _, stderr) = run(&[path, "--timeout", "0ms", "--chunk-size", "1024"]);
+    assert_eq!(code, 1);
+    assert!(
+        stderr.contains("exceed --timeout 0ms in the chunk at byte 0"),
+        "{stderr}"
+    );
+}
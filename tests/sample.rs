@@ -0,0 +1,44 @@
+//! Runs the binary with `--sample-bytes` around the size of its input.
+
+use std::{path::PathBuf, process::Command};
+
+const INPUT: &str = "Oslo;1.0\nRome;20.0\nOslo;-3.0\n";
+
+/// Writes the input to the test's temporary directory
+fn input(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, INPUT).unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+/// Runs the binary with `args`, returning its stdout and stderr
+fn run(args: &[&str]) -> (String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rs"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    (stdout, stderr)
+}
+
+#[test]
+fn whole_file() {
+    let path = input("sample-whole");
+    let (full, _) = run(&[&path]);
+    assert_eq!(full, "Oslo;-3.0;-1.0;1.0\nRome;20.0;20.0;20.0\n");
+
+    // A sample of the file's size, or more, is the whole file
+    for size in [INPUT.len(), INPUT.len() + 1, 1 << 20] {
+        let (stdout, stderr) = run(&[&path, "--sample-bytes", &size.to_string()]);
+        assert_eq!(stdout, full, "{size}");
+        assert!(!stderr.contains("sampled"), "{size}: {stderr}");
+    }
+
+    // One byte less drops the last record, whose newline is cut off
+    let size = (INPUT.len() - 1).to_string();
+    let (stdout, stderr) = run(&[&path, "--sample-bytes", &size]);
+    assert_eq!(stdout, "Oslo;1.0;1.0;1.0\nRome;20.0;20.0;20.0\n");
+    assert!(stderr.contains("note: sampled 19 of 29 bytes"), "{stderr}");
+}
@@ -0,0 +1,83 @@
+//! Runs the binary with its diagnostics on and checks that they report every part of the run.
+
+use std::{path::PathBuf, process::Command};
+
+/// Writes a small input to the test's temporary directory
+fn input(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, "Oslo;1.0\nRome;20.0\nOslo;-3.0\n").unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+/// Runs the binary with `args`, checks that it printed the results and returns its stderr
+fn stderr(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_rs"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Oslo;-3.0;-1.0;1.0\nRome;20.0;20.0;20.0\n");
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn timing() {
+    let path = input("smoke-timing");
+    let report = stderr(&[&path, "--timing", "--threads", "2"]);
+    let lines: Vec<&str> = report.lines().map(str::trim_start).collect();
+    assert_eq!(lines[0], "timing:");
+    let phases = [
+        "open:", "map:", "parse:", "merge:", "sort:", "write:", "total:",
+    ];
+    for (line, phase) in lines[1..].iter().zip(phases) {
+        assert!(line.starts_with(phase) && line.ends_with(" ms"), "{report}");
+    }
+    assert!(lines[8].starts_with("cpus:"), "{report}");
+    assert!(lines[9].starts_with("workers:  busy"), "{report}");
+    // A line per worker
+    assert!(
+        lines[10].starts_with("0: ") && lines[11].starts_with("1: "),
+        "{report}"
+    );
+    assert_eq!(lines.len(), 12, "{report}");
+
+    let json = stderr(&[&path, "--timing-format", "json", "--threads", "2"]);
+    assert!(json.starts_with('{') && json.ends_with("]}\n"), "{json}");
+    for key in phases.map(|phase| format!("\"{}_ms\":", &phase[..phase.len() - 1])) {
+        assert!(json.contains(&key), "{key} missing from {json}");
+    }
+    assert!(
+        json.contains("\"cpus\":{") && json.contains("\"busy_ms\":["),
+        "{json}"
+    );
+}
+
+#[test]
+fn stats() {
+    let path = input("smoke-stats");
+    let report = stderr(&[&path, "--stats", "--threads", "2"]);
+    let lines: Vec<&str> = report.lines().map(str::trim_start).collect();
+    assert_eq!(lines[0], "stats:");
+    assert_eq!(lines[1], "input:    29 bytes, 3 rows, 2 stations");
+    for (line, start) in lines[2..].iter().zip([
+        "time:", "arena:", "maps:", "warmup:", "cpus:", "threads:", "0: ", "1: ",
+    ]) {
+        assert!(line.starts_with(start), "{report}");
+    }
+    assert_eq!(lines.len(), 10, "{report}");
+
+    let json = stderr(&[&path, "--stats-format", "json", "--threads", "2"]);
+    assert!(
+        json.starts_with("{\"bytes\":29,\"rows\":3,\"stations\":2,"),
+        "{json}"
+    );
+    for key in [
+        "\"arena\":{",
+        "\"peak_map_capacity\":",
+        "\"cpus\":{",
+        "\"sample\":null",
+    ] {
+        assert!(json.contains(key), "{key} missing from {json}");
+    }
+    assert_eq!(json.matches("{\"thread\":").count(), 2, "{json}");
+}
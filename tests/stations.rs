@@ -0,0 +1,55 @@
+//! Runs the binary with a `--stations-file`.
+
+use std::{path::PathBuf, process::Command};
+
+/// Writes `contents` to a file of the test's temporary directory
+fn input(name: &str, contents: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, contents).unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+/// Runs the binary with `args`, returning its exit code, stdout and stderr
+fn run(args: &[&str]) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rs"))
+        .args(args)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    (output.status.code().unwrap(), stdout, stderr)
+}
+
+#[test]
+fn emit_empty() {
+    let list = input("stations-list", "Oslo\nLima\nRome\n");
+    let path = input("stations-input", "Oslo;1.0\nParis;2.0\nOslo;3.0\n");
+    let (code, stdout, _) = run(&[&path, "--stations-file", &list, "--emit-empty"]);
+    assert_eq!(code, 0);
+    assert_eq!(
+        stdout,
+        "Lima;;;\nOslo;1.0;2.0;3.0\nParis;2.0;2.0;2.0\nRome;;;\n"
+    );
+
+    // Without it only stations with readings are printed
+    let (_, stdout, _) = run(&[&path, "--stations-file", &list]);
+    assert_eq!(stdout, "Oslo;1.0;2.0;3.0\nParis;2.0;2.0;2.0\n");
+
+    let (code, stdout, stderr) = run(&[
+        &path,
+        "--stations-file",
+        &list,
+        "--stations-strict=skip",
+        "--emit-empty",
+    ]);
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "Lima;;;\nOslo;1.0;2.0;3.0\nRome;;;\n");
+    assert!(
+        stderr.contains("skipped 1 readings of stations missing from"),
+        "{stderr}"
+    );
+
+    let (code, stdout, stderr) = run(&[&path, "--stations-file", &list, "--stations-strict"]);
+    assert_eq!((code, &*stdout), (1, ""));
+    assert!(stderr.contains("station \"Paris\""), "{stderr}");
+}